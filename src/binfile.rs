@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dosexe::Relocation;
+use crate::linker_error::LinkerError;
+
+//
+// Raw binary images, for ROMs and boot sectors. There is no header and no loader to
+// relocate the image, so it is linked for a fixed load segment and written as is.
+//
+
+/// Resolve the segment fixups the DOS loader would have applied, for an image loaded at
+/// `base_segment`.
+///
+pub fn relocate(image: &mut [u8], relocs: &[Relocation], base_segment: u16) -> Result<(), LinkerError> {
+    for reloc in relocs {
+        let at = ((reloc.seg as usize) << 4) + reloc.offset as usize;
+        let seg = u16::from_le_bytes([image[at], image[at+1]]);
+
+        let seg = seg.checked_add(base_segment).ok_or_else(|| LinkerError::new(&format!(
+            "segment fixup at {:04X}:{:04X} is outside 1M with base segment {:04X}H.", reloc.seg, reloc.offset, base_segment
+        )))?;
+
+        image[at..at+2].copy_from_slice(&seg.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Split an image into ROMs of `rom_size` bytes, padding the last with FFH as an erased
+/// ROM reads.
+///
+fn rom_chunks(image: &[u8], rom_size: usize) -> Vec<Vec<u8>> {
+    image.chunks(rom_size)
+        .map(|chunk| {
+            let mut rom = chunk.to_vec();
+            rom.resize(rom_size, 0xff);
+            rom
+        })
+        .collect()
+}
+
+/// The name of the `index`th ROM: the output name with the index before the extension.
+///
+fn rom_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut name = format!("{}-{}", stem, index);
+
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+
+    path.with_file_name(name)
+}
+
+/// Write a raw binary image. With `rom_size`, the image is padded to a whole number of
+/// ROMs, and written one file per ROM if there is more than one.
+///
+pub fn write_binary(path: &Path, image: &[u8], rom_size: Option<usize>) -> Result<(), LinkerError> {
+    let Some(rom_size) = rom_size else {
+        fs::write(path, image)?;
+        return Ok(());
+    };
+
+    if rom_size == 0 {
+        return Err(LinkerError::new("ROM size must not be zero."));
+    }
+
+    let roms = rom_chunks(image, rom_size);
+
+    if roms.len() == 1 {
+        fs::write(path, &roms[0])?;
+    } else {
+        for (i, rom) in roms.iter().enumerate() {
+            fs::write(rom_path(path, i), rom)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn relocations() -> Result<(), LinkerError> {
+        let mut image = vec![0x9a, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xf0, 0xff];
+        let relocs = [
+            Relocation { seg: 0, offset: 3 },
+            Relocation { seg: 0, offset: 7 },
+        ];
+
+        relocate(&mut image[..7], &relocs[..1], 0xf000)?;
+        assert_eq!(&image[3..5], &[0x01, 0xf0]);
+
+        assert!(relocate(&mut image, &relocs[1..], 0x1000).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn roms() {
+        let image = vec![0x55u8; 0x500];
+        let roms = rom_chunks(&image, 0x200);
+
+        assert_eq!(roms.len(), 3);
+        assert!(roms.iter().all(|rom| rom.len() == 0x200));
+        assert_eq!(roms[2][0xff], 0x55);
+        assert_eq!(roms[2][0x100], 0xff);
+
+        assert_eq!(rom_path(Path::new("out/bios.bin"), 1), PathBuf::from("out/bios-1.bin"));
+        assert_eq!(rom_path(Path::new("bios"), 0), PathBuf::from("bios-0"));
+    }
+}
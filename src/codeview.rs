@@ -0,0 +1,949 @@
+use std::collections::HashMap;
+
+use crate::linker_error::LinkerError;
+use crate::linkstate::LinkState;
+use crate::object::Object;
+use crate::symbols::Symbol;
+
+//
+// CodeView 4 debug information, in the packed NB09 format.
+//
+// Compilers put CodeView data in each object module's $$TYPES and $$SYMBOLS segments.
+// By the time this code runs, pass 2 has collected each module's debug data and applied
+// its fixups, so addresses in the symbols are logical segment numbers and offsets. What
+// is left is to merge the modules' types into one global table, renumber the type indices
+// the symbols refer to, and lay out the subsections which make up the debug information.
+//
+
+/// The signature at the start of CV4 $$TYPES and $$SYMBOLS data.
+///
+const CV4_SIGNATURE: u32 = 1;
+
+/// Type indices below this are primitive types, which are not in any type table.
+///
+const FIRST_TYPE_INDEX: u16 = 0x1000;
+
+//
+// Subsection types.
+//
+const SST_MODULE: u16 = 0x120;
+const SST_ALIGN_SYM: u16 = 0x125;
+const SST_SRC_MODULE: u16 = 0x127;
+const SST_LIBRARIES: u16 = 0x128;
+const SST_GLOBAL_SYM: u16 = 0x129;
+const SST_GLOBAL_PUB: u16 = 0x12a;
+const SST_GLOBAL_TYPES: u16 = 0x12b;
+const SST_SEG_MAP: u16 = 0x12d;
+
+//
+// Symbol record types, 16-bit forms.
+//
+const S_REGISTER: u16 = 0x0002;
+const S_CONSTANT: u16 = 0x0003;
+const S_UDT: u16 = 0x0004;
+const S_END: u16 = 0x0006;
+const S_MANYREG: u16 = 0x000c;
+const S_BPREL16: u16 = 0x0100;
+const S_LDATA16: u16 = 0x0101;
+const S_GDATA16: u16 = 0x0102;
+const S_PUB16: u16 = 0x0103;
+const S_LPROC16: u16 = 0x0104;
+const S_GPROC16: u16 = 0x0105;
+const S_THUNK16: u16 = 0x0106;
+const S_BLOCK16: u16 = 0x0107;
+const S_WITH16: u16 = 0x0108;
+const S_REGREL16: u16 = 0x010c;
+
+/// Map a linear address in the image to a CodeView logical segment (a 1-based index into
+/// the segment map, which follows the memory map order) and offset in that segment.
+///
+pub fn logical_segment(state: &LinkState, linear: usize) -> Option<(u16, usize)> {
+    state.segment_order
+        .iter()
+        .enumerate()
+        .map(|(i, segidx)| (i + 1, &state.segments[*segidx]))
+        .find(|(_, seg)| linear >= seg.base && (linear < seg.base + seg.length || linear == seg.base))
+        .map(|(i, seg)| (i as u16, linear - seg.base))
+}
+
+fn get_u16(data: &[u8], at: usize) -> Result<u16, LinkerError> {
+    if at + 2 > data.len() {
+        Err(LinkerError::new("CodeView record is truncated."))
+    } else {
+        Ok(u16::from_le_bytes([data[at], data[at+1]]))
+    }
+}
+
+fn get_u8(data: &[u8], at: usize) -> Result<u8, LinkerError> {
+    data.get(at).copied().ok_or_else(|| LinkerError::new("CodeView record is truncated."))
+}
+
+/// Return the length of the numeric leaf at `at`. Values below 8000H are stored in the
+/// leaf itself; others are followed by a value of a size given by the leaf.
+///
+fn numeric_leaf_length(data: &[u8], at: usize) -> Result<usize, LinkerError> {
+    let leaf = get_u16(data, at)?;
+
+    Ok(2 + match leaf {
+        0x0000..=0x7fff => 0,
+        0x8000 => 1,
+        0x8001 | 0x8002 => 2,
+        0x8003..=0x8005 => 4,
+        0x800b => 6,
+        0x8006 | 0x8009 | 0x800a | 0x800c => 8,
+        0x8007 => 10,
+        0x8008 | 0x800d => 16,
+        0x800e => 20,
+        0x800f => 32,
+        0x8010 => 2 + get_u16(data, at + 2)? as usize,
+        _ => return Err(LinkerError::new(&format!("unknown CodeView numeric leaf {:04X}H.", leaf))),
+    })
+}
+
+/// Return the length of the length-prefixed name at `at`.
+///
+fn name_length(data: &[u8], at: usize) -> Result<usize, LinkerError> {
+    Ok(1 + get_u8(data, at)? as usize)
+}
+
+/// Does a method attribute say the method introduces a virtual function, in which case the
+/// method's entry contains a vtable offset.
+///
+fn is_intro_virtual(attr: u16) -> bool {
+    matches!((attr >> 2) & 7, 4 | 6)
+}
+
+/// Return the offsets of the type indices in the members of a field list, which starts at
+/// `at` and runs to the end of the record.
+///
+fn field_list_type_indices(rec: &[u8], mut at: usize) -> Result<Vec<usize>, LinkerError> {
+    let mut indices = Vec::new();
+
+    while at < rec.len() {
+        //
+        // Members are padded to alignment with LF_PADn bytes, where n is the number
+        // of bytes to skip.
+        //
+        if rec[at] >= 0xf0 {
+            at += std::cmp::max(1, (rec[at] & 0x0f) as usize);
+            continue;
+        }
+
+        let leaf = get_u16(rec, at)?;
+
+        at = match leaf {
+            // LF_BCLASS: type, attr, offset
+            0x0400 => {
+                indices.push(at + 2);
+                at + 6 + numeric_leaf_length(rec, at + 6)?
+            },
+            // LF_VBCLASS, LF_IVBCLASS: btype, vbtype, attr, vbpoff, vboff
+            0x0401 | 0x0402 => {
+                indices.extend([at + 2, at + 4]);
+                let vboff = at + 8 + numeric_leaf_length(rec, at + 8)?;
+                vboff + numeric_leaf_length(rec, vboff)?
+            },
+            // LF_ENUMERATE: attr, value, name
+            0x0403 => {
+                let name = at + 4 + numeric_leaf_length(rec, at + 4)?;
+                name + name_length(rec, name)?
+            },
+            // LF_FRIENDFCN, LF_NESTTYPE: type, name
+            0x0404 | 0x0409 => {
+                indices.push(at + 2);
+                at + 4 + name_length(rec, at + 4)?
+            },
+            // LF_INDEX, LF_VFUNCTAB, LF_FRIENDCLS: type
+            0x0405 | 0x040a | 0x040b => {
+                indices.push(at + 2);
+                at + 4
+            },
+            // LF_MEMBER: type, attr, offset, name
+            0x0406 => {
+                indices.push(at + 2);
+                let name = at + 6 + numeric_leaf_length(rec, at + 6)?;
+                name + name_length(rec, name)?
+            },
+            // LF_STMEMBER: type, attr, name
+            0x0407 => {
+                indices.push(at + 2);
+                at + 6 + name_length(rec, at + 6)?
+            },
+            // LF_METHOD: count, method list, name
+            0x0408 => {
+                indices.push(at + 4);
+                at + 6 + name_length(rec, at + 6)?
+            },
+            // LF_ONEMETHOD: attr, type, [vtable offset], name
+            0x040c => {
+                indices.push(at + 4);
+                let name = if is_intro_virtual(get_u16(rec, at + 2)?) { at + 10 } else { at + 6 };
+                name + name_length(rec, name)?
+            },
+            // LF_VFUNCOFF: type, offset
+            0x040d => {
+                indices.push(at + 2);
+                at + 8
+            },
+            _ => return Err(LinkerError::new(&format!("unknown CodeView field list leaf {:04X}H.", leaf))),
+        };
+    }
+
+    Ok(indices)
+}
+
+/// Return the offsets of the type indices in a type record, which starts with its leaf.
+///
+fn type_indices(rec: &[u8]) -> Result<Vec<usize>, LinkerError> {
+    let leaf = get_u16(rec, 0)?;
+
+    let counted = |count_at: usize, first: usize| -> Result<Vec<usize>, LinkerError> {
+        let count = get_u16(rec, count_at)? as usize;
+        Ok((0..count).map(|i| first + 2 * i).collect())
+    };
+
+    Ok(match leaf {
+        // LF_MODIFIER, LF_POINTER: attr, type
+        0x0001 | 0x0002 => vec![4],
+        // LF_ARRAY: element type, index type
+        0x0003 => vec![2, 4],
+        // LF_CLASS, LF_STRUCTURE: count, field list, property, derivation list, vtable shape
+        0x0004 | 0x0005 => vec![4, 8, 10],
+        // LF_UNION: count, field list
+        0x0006 => vec![4],
+        // LF_ENUM: count, underlying type, field list
+        0x0007 => vec![4, 6],
+        // LF_PROCEDURE: return type, calling convention, parameter count, argument list
+        0x0008 => vec![2, 8],
+        // LF_MFUNCTION: return type, class, this, calling convention, parameter count, argument list
+        0x0009 => vec![2, 4, 6, 12],
+        // LF_VTSHAPE, LF_COBOL0/1, LF_LABEL, LF_NULL, LF_NOTTRAN, LF_SKIP, LF_LIST, LF_REFSYM
+        0x000a | 0x000b | 0x000c | 0x000e | 0x000f | 0x0010 | 0x0200 | 0x0203 | 0x020c => vec![],
+        // LF_BARRAY, LF_DEFARG: type
+        0x000d | 0x0202 => vec![2],
+        // LF_DIMARRAY: underlying type, dimension information
+        0x0011 => vec![2, 4],
+        // LF_VFTPATH, LF_ARGLIST, LF_DERIVED: count, types
+        0x0012 | 0x0201 | 0x0205 => counted(2, 4)?,
+        // LF_FIELDLIST
+        0x0204 => field_list_type_indices(rec, 2)?,
+        // LF_BITFIELD: length, position, type
+        0x0206 => vec![4],
+        // LF_MLIST: attr, type, [vtable offset] for each method
+        0x0207 => {
+            let mut indices = Vec::new();
+            let mut at = 2;
+
+            while at < rec.len() {
+                indices.push(at + 2);
+                at += if is_intro_virtual(get_u16(rec, at)?) { 8 } else { 4 };
+            }
+
+            indices
+        },
+        // LF_DIMCONU, LF_DIMCONLU: rank, index type, bounds
+        0x0208 | 0x0209 => vec![4],
+        // LF_DIMVARU: rank, index type, upper bound types
+        0x020a => {
+            let rank = get_u16(rec, 2)? as usize;
+            std::iter::once(4).chain((0..rank).map(|i| 6 + 2 * i)).collect()
+        },
+        // LF_DIMVARLU: rank, index type, lower and upper bound types
+        0x020b => {
+            let rank = get_u16(rec, 2)? as usize;
+            std::iter::once(4).chain((0..2 * rank).map(|i| 6 + 2 * i)).collect()
+        },
+        _ => return Err(LinkerError::new(&format!("unknown CodeView type leaf {:04X}H.", leaf))),
+    })
+}
+
+/// Split a CV4 $$TYPES segment into its type records (leaf data without the length).
+///
+fn parse_types(data: &[u8]) -> Result<Vec<&[u8]>, LinkerError> {
+    let mut records = Vec::new();
+
+    if data.is_empty() {
+        return Ok(records);
+    }
+
+    if data.len() < 4 || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) != CV4_SIGNATURE {
+        return Err(LinkerError::new("$$TYPES is not in CodeView 4 format."));
+    }
+
+    let mut at = 4;
+    while at < data.len() {
+        let len = get_u16(data, at)? as usize;
+
+        if at + 2 + len > data.len() {
+            return Err(LinkerError::new("CodeView type record is truncated."));
+        }
+
+        records.push(&data[at+2..at+2+len]);
+        at += 2 + len;
+    }
+
+    Ok(records)
+}
+
+/// The result of packing types: the global type table, and for each module, the map
+/// from the module's type indices (less `FIRST_TYPE_INDEX`) to global type indices.
+///
+struct PackedTypes {
+    types: Vec<Vec<u8>>,
+    maps: Vec<Vec<u16>>,
+}
+
+/// Merge the type records of all modules into one table, removing duplicates.
+///
+/// Types refer to each other by index, and may do so circularly (a structure containing a
+/// pointer to itself), so identical types from different modules do not have identical
+/// bytes. Instead the types are partitioned into classes of equivalent records: first by
+/// their contents apart from references, then repeatedly splitting classes whose members
+/// refer to types in different classes, until no class splits. Each class is one type in
+/// the packed table.
+///
+fn pack_types(modules: &[Vec<&[u8]>]) -> Result<PackedTypes, LinkerError> {
+    struct Node {
+        bytes: Vec<u8>,
+        fields: Vec<usize>,
+        refs: Vec<Option<usize>>,
+    }
+
+    let mut nodes = Vec::new();
+    let mut firsts = Vec::new();
+
+    for records in modules.iter() {
+        let first = nodes.len();
+        firsts.push(first);
+
+        for rec in records.iter() {
+            let fields = type_indices(rec)?;
+            let mut bytes = rec.to_vec();
+            let mut refs = Vec::new();
+
+            for field in fields.iter() {
+                let index = get_u16(rec, *field)?;
+                let local = index.wrapping_sub(FIRST_TYPE_INDEX) as usize;
+
+                //
+                // References to other records are masked out of the contents; primitive
+                // types are part of them.
+                //
+                if index >= FIRST_TYPE_INDEX && local < records.len() {
+                    refs.push(Some(first + local));
+                    bytes[*field..*field+2].copy_from_slice(&[0, 0]);
+                } else {
+                    refs.push(None);
+                }
+            }
+
+            nodes.push(Node { bytes, fields, refs });
+        }
+    }
+
+    //
+    // Initial partition by contents and which fields are references.
+    //
+    let mut class = Vec::new();
+    let mut classes = {
+        let mut keys = HashMap::new();
+        for node in nodes.iter() {
+            let key = (node.bytes.clone(), node.refs.iter().map(|r| r.is_some()).collect::<Vec<bool>>());
+            let next = keys.len();
+            class.push(*keys.entry(key).or_insert(next));
+        }
+        keys.len()
+    };
+
+    //
+    // Refine until stable.
+    //
+    loop {
+        let mut keys = HashMap::new();
+        let mut next_class = Vec::new();
+
+        for node in nodes.iter() {
+            let key = (class[next_class.len()], node.refs.iter().map(|r| r.map(|r| class[r])).collect::<Vec<Option<usize>>>());
+            let next = keys.len();
+            next_class.push(*keys.entry(key).or_insert(next));
+        }
+
+        class = next_class;
+
+        if keys.len() == classes {
+            break;
+        }
+
+        classes = keys.len();
+    }
+
+    //
+    // Number the classes in order of first appearance, and emit one record for each.
+    //
+    let mut global = vec![None; classes];
+    let mut reps = Vec::new();
+
+    for (i, c) in class.iter().enumerate() {
+        if global[*c].is_none() {
+            global[*c] = Some(FIRST_TYPE_INDEX + reps.len() as u16);
+            reps.push(i);
+        }
+    }
+
+    let index_of = |node: usize| global[class[node]].unwrap();
+
+    let types = reps.iter().map(|rep| {
+        let node = &nodes[*rep];
+        let mut bytes = node.bytes.clone();
+
+        for (field, r) in node.fields.iter().zip(node.refs.iter()) {
+            if let Some(r) = r {
+                bytes[*field..*field+2].copy_from_slice(&index_of(*r).to_le_bytes());
+            }
+        }
+
+        bytes
+    }).collect();
+
+    let maps = modules.iter().zip(firsts.iter())
+        .map(|(records, first)| (0..records.len()).map(|i| index_of(first + i)).collect())
+        .collect();
+
+    Ok(PackedTypes { types, maps })
+}
+
+/// Return the offset of the type index in a symbol record, if it has one.
+///
+fn symbol_type_index(rectyp: u16) -> Option<usize> {
+    match rectyp {
+        S_REGISTER | S_CONSTANT | S_UDT | S_MANYREG => Some(4),
+        S_BPREL16 => Some(6),
+        S_LDATA16 | S_GDATA16 | S_PUB16 | S_REGREL16 => Some(8),
+        S_LPROC16 | S_GPROC16 => Some(26),
+        _ => None,
+    }
+}
+
+/// Prepare a module's $$SYMBOLS data for the executable: renumber type indices to the
+/// packed type table, and link each scope to its parent and its S_END record. Scope
+/// links are offsets from the start of the data, which becomes the module's sstAlignSym.
+///
+fn link_symbols(data: &mut [u8], typemap: &[u16]) -> Result<(), LinkerError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    if data.len() < 4 || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) != CV4_SIGNATURE {
+        return Err(LinkerError::new("$$SYMBOLS is not in CodeView 4 format."));
+    }
+
+    let mut scopes: Vec<usize> = Vec::new();
+    let mut at = 4;
+
+    while at < data.len() {
+        let len = get_u16(data, at)? as usize;
+        let rectyp = get_u16(data, at + 2)?;
+
+        if at + 2 + len > data.len() {
+            return Err(LinkerError::new("CodeView symbol record is truncated."));
+        }
+
+        if let Some(field) = symbol_type_index(rectyp) {
+            let index = get_u16(data, at + field)?;
+
+            if index >= FIRST_TYPE_INDEX {
+                let global = typemap.get((index - FIRST_TYPE_INDEX) as usize).copied().unwrap_or(0);
+                data[at+field..at+field+2].copy_from_slice(&global.to_le_bytes());
+            }
+        }
+
+        match rectyp {
+            S_LPROC16 | S_GPROC16 | S_THUNK16 | S_BLOCK16 | S_WITH16 => {
+                let parent = scopes.last().copied().unwrap_or(0) as u32;
+                data[at+4..at+8].copy_from_slice(&parent.to_le_bytes());
+                scopes.push(at);
+            },
+            S_END => {
+                if let Some(scope) = scopes.pop() {
+                    data[scope+8..scope+12].copy_from_slice(&(at as u32).to_le_bytes());
+                }
+            },
+            _ => {},
+        }
+
+        at += 2 + len;
+    }
+
+    Ok(())
+}
+
+/// Pad a buffer with zeros to a multiple of 4 bytes.
+///
+fn pad4(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..std::cmp::min(name.len(), 255)];
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(name);
+}
+
+/// Build the sstModule subsection, which names a module and lists its code and data.
+///
+fn sst_module(state: &LinkState, obj: &Object, ilib: u16) -> Vec<u8> {
+    let mut segs = Vec::new();
+
+    for segdef in obj.segdefs.iter().filter(|segdef| state.segments[segdef.segidx].debug.is_none() && segdef.length != 0 && !segdef.discarded) {
+        let seg = &state.segments[segdef.segidx];
+
+        if let Some((logical, offset)) = logical_segment(state, seg.base + segdef.base) {
+            segs.push((logical, offset, segdef.length));
+        }
+    }
+
+    let mut sst = Vec::new();
+    push_u16(&mut sst, 0);              // overlay number
+    push_u16(&mut sst, ilib);
+    push_u16(&mut sst, segs.len() as u16);
+    push_u16(&mut sst, 0x5643);         // "CV"
+
+    for (logical, offset, length) in segs {
+        push_u16(&mut sst, logical);
+        push_u16(&mut sst, 0);
+        push_u32(&mut sst, offset as u32);
+        push_u32(&mut sst, length as u32);
+    }
+
+    push_name(&mut sst, &obj.name);
+    sst
+}
+
+/// Build the sstSrcModule subsection from a module's line numbers. All of a module's lines
+/// are attributed to the source file named in its THEADR.
+///
+fn sst_src_module(state: &LinkState, obj: &Object) -> Vec<u8> {
+    struct SegLines {
+        seg: u16,
+        start: u32,
+        end: u32,
+        offsets: Vec<u32>,
+        lines: Vec<u16>,
+    }
+
+    let mut segs = Vec::new();
+
+    for linnums in obj.linnums.iter().filter(|linnums| !linnums.lines.is_empty()) {
+        let seg = &state.segments[linnums.segidx];
+
+        let logical = match logical_segment(state, seg.base) {
+            Some((logical, _)) => logical,
+            None => continue,
+        };
+
+        let offsets = linnums.lines.iter().map(|line| line.offset as u32).collect::<Vec<u32>>();
+
+        segs.push(SegLines {
+            seg: logical,
+            start: *offsets.iter().min().unwrap(),
+            end: *offsets.iter().max().unwrap(),
+            lines: linnums.lines.iter().map(|line| line.line).collect(),
+            offsets,
+        });
+    }
+
+    let cseg = segs.len();
+
+    //
+    // Module header: one file, and the ranges of each segment.
+    //
+    let header_len = 4 + 4 + 8 * cseg + 2 * cseg;
+    let header_len = (header_len + 3) & !3;
+
+    //
+    // File table: the line table offsets and ranges of each segment, and the name.
+    //
+    let file_len = 4 + 4 * cseg + 8 * cseg + 1 + std::cmp::min(obj.name.len(), 255);
+    let file_len = (file_len + 3) & !3;
+
+    let mut sst = Vec::new();
+    push_u16(&mut sst, 1);
+    push_u16(&mut sst, cseg as u16);
+    push_u32(&mut sst, header_len as u32);
+    for seg in segs.iter() {
+        push_u32(&mut sst, seg.start);
+        push_u32(&mut sst, seg.end);
+    }
+    for seg in segs.iter() {
+        push_u16(&mut sst, seg.seg);
+    }
+    pad4(&mut sst);
+
+    push_u16(&mut sst, cseg as u16);
+    push_u16(&mut sst, 0);
+
+    let mut line_table = header_len + file_len;
+    for seg in segs.iter() {
+        push_u32(&mut sst, line_table as u32);
+        line_table += (4 + 6 * seg.lines.len() + 3) & !3;
+    }
+    for seg in segs.iter() {
+        push_u32(&mut sst, seg.start);
+        push_u32(&mut sst, seg.end);
+    }
+    push_name(&mut sst, &obj.name);
+    pad4(&mut sst);
+
+    for seg in segs.iter() {
+        push_u16(&mut sst, seg.seg);
+        push_u16(&mut sst, seg.lines.len() as u16);
+        for offset in seg.offsets.iter() {
+            push_u32(&mut sst, *offset);
+        }
+        for line in seg.lines.iter() {
+            push_u16(&mut sst, *line);
+        }
+        pad4(&mut sst);
+    }
+
+    sst
+}
+
+/// Build a symbol table subsection (sstGlobalPub or sstGlobalSym) around symbol records,
+/// without hash tables.
+///
+fn sst_symbols(records: &[u8]) -> Vec<u8> {
+    let mut sst = Vec::new();
+    push_u16(&mut sst, 0);              // symbol hash function
+    push_u16(&mut sst, 0);              // address hash function
+    push_u32(&mut sst, records.len() as u32);
+    push_u32(&mut sst, 0);              // symbol hash size
+    push_u32(&mut sst, 0);              // address hash size
+    sst.extend_from_slice(records);
+    sst
+}
+
+/// Build the S_PUB16 records for the public symbols, sorted by name.
+///
+fn public_records(state: &LinkState) -> Vec<u8> {
+    let mut names = state.symbols.symbols.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    let mut records = Vec::new();
+
+    for name in names {
+        let (seg, offset) = match state.symbols.symbols.get(name) {
+            Some(Symbol::Public(public)) if public.segment != 0 => {
+                let linear = state.segments[public.segment].base + public.offset as usize;
+                match logical_segment(state, linear) {
+                    Some((seg, offset)) => (seg, offset as u16),
+                    None => continue,
+                }
+            },
+            Some(Symbol::Public(public)) => (0, public.offset),
+            _ => continue,
+        };
+
+        let mut rec = Vec::new();
+        push_u16(&mut rec, S_PUB16);
+        push_u16(&mut rec, offset);
+        push_u16(&mut rec, seg);
+        push_u16(&mut rec, 0);
+        push_name(&mut rec, name);
+
+        push_u16(&mut records, rec.len() as u16);
+        records.extend_from_slice(&rec);
+    }
+
+    records
+}
+
+/// Build the sstGlobalTypes subsection from the packed types.
+///
+fn sst_global_types(types: &[Vec<u8>]) -> Vec<u8> {
+    let mut sst = Vec::new();
+    push_u32(&mut sst, CV4_SIGNATURE);
+    push_u32(&mut sst, types.len() as u32);
+
+    let mut offset = 0;
+    for rec in types.iter() {
+        push_u32(&mut sst, offset as u32);
+        offset += 2 + rec.len();
+    }
+
+    for rec in types.iter() {
+        push_u16(&mut sst, rec.len() as u16);
+        sst.extend_from_slice(rec);
+    }
+
+    sst
+}
+
+/// Build the sstSegMap subsection, which gives the frame of each logical segment.
+///
+fn sst_seg_map(state: &LinkState) -> Vec<u8> {
+    const SEG_READ: u16 = 0x0001;
+    const SEG_WRITE: u16 = 0x0002;
+    const SEG_EXECUTE: u16 = 0x0004;
+
+    let count = state.segment_order.len() as u16;
+
+    let mut sst = Vec::new();
+    push_u16(&mut sst, count);
+    push_u16(&mut sst, count);
+
+    for segidx in state.segment_order.iter() {
+        let seg = &state.segments[*segidx];
+        let class = state.lnames.get(seg.name.classidx);
+
+        let flags = if class.ends_with("CODE") { SEG_READ | SEG_EXECUTE } else { SEG_READ | SEG_WRITE };
+        let frame = state.segment_address_base(*segidx) & !0x000f;
+
+        push_u16(&mut sst, flags);
+        push_u16(&mut sst, 0);              // overlay
+        push_u16(&mut sst, 0);              // group
+        push_u16(&mut sst, (frame >> 4) as u16);
+        push_u16(&mut sst, 0xffff);         // segment name
+        push_u16(&mut sst, 0xffff);         // class name
+        push_u32(&mut sst, (seg.base - frame) as u32);
+        push_u32(&mut sst, seg.length as u32);
+    }
+
+    sst
+}
+
+/// Build the NB09 CodeView information for the linked program, to be appended to the
+/// executable.
+///
+pub fn build(state: &LinkState, objects: &mut [Object]) -> Result<Vec<u8>, LinkerError> {
+    //
+    // Pack the types of all modules, then renumber each module's symbols to match.
+    //
+    let mut module_types = Vec::new();
+
+    for obj in objects.iter() {
+        match parse_types(&obj.debug_types) {
+            Ok(types) => module_types.push(types),
+            Err(err) => return Err(LinkerError::new(&format!("module {}: {}", obj.name, err))),
+        }
+    }
+
+    let packed = pack_types(&module_types)?;
+
+    for (obj, typemap) in objects.iter_mut().zip(packed.maps.iter()) {
+        if let Err(err) = link_symbols(&mut obj.debug_symbols, typemap) {
+            return Err(LinkerError::new(&format!("module {}: {}", obj.name, err)));
+        }
+    }
+
+    //
+    // Libraries, so modules can refer to them by index. Index 0 is no library.
+    //
+    let mut libraries: Vec<&str> = vec![""];
+
+    for obj in objects.iter() {
+        if let Some(library) = &obj.library {
+            if !libraries.contains(&library.as_str()) {
+                libraries.push(library);
+            }
+        }
+    }
+
+    //
+    // Subsections, as (type, module index, data).
+    //
+    let mut subsections: Vec<(u16, u16, Vec<u8>)> = Vec::new();
+
+    for (i, obj) in objects.iter().enumerate() {
+        let ilib = obj.library.as_ref().map(|library| libraries.iter().position(|l| l == library).unwrap()).unwrap_or(0);
+        subsections.push((SST_MODULE, (i + 1) as u16, sst_module(state, obj, ilib as u16)));
+    }
+
+    for (i, obj) in objects.iter().enumerate() {
+        if !obj.debug_symbols.is_empty() {
+            subsections.push((SST_ALIGN_SYM, (i + 1) as u16, obj.debug_symbols.clone()));
+        }
+
+        if obj.linnums.iter().any(|linnums| !linnums.lines.is_empty()) {
+            subsections.push((SST_SRC_MODULE, (i + 1) as u16, sst_src_module(state, obj)));
+        }
+    }
+
+    let mut libs = Vec::new();
+    for library in libraries.iter() {
+        push_name(&mut libs, library);
+    }
+
+    subsections.push((SST_GLOBAL_PUB, 0xffff, sst_symbols(&public_records(state))));
+    subsections.push((SST_GLOBAL_SYM, 0xffff, sst_symbols(&[])));
+    subsections.push((SST_LIBRARIES, 0xffff, libs));
+    subsections.push((SST_GLOBAL_TYPES, 0xffff, sst_global_types(&packed.types)));
+    subsections.push((SST_SEG_MAP, 0xffff, sst_seg_map(state)));
+
+    //
+    // Lay out the signature, the subsections, then the directory. All offsets are from the
+    // start of the debug information.
+    //
+    let mut debug = Vec::new();
+    debug.extend_from_slice(b"NB09");
+    push_u32(&mut debug, 0);
+
+    let mut entries = Vec::new();
+
+    for (sst, imod, data) in subsections.iter() {
+        pad4(&mut debug);
+        entries.push((*sst, *imod, debug.len() as u32, data.len() as u32));
+        debug.extend_from_slice(data);
+    }
+
+    pad4(&mut debug);
+    let directory = debug.len() as u32;
+    debug[4..8].copy_from_slice(&directory.to_le_bytes());
+
+    push_u16(&mut debug, 16);               // directory header size
+    push_u16(&mut debug, 12);               // directory entry size
+    push_u32(&mut debug, entries.len() as u32);
+    push_u32(&mut debug, 0);                // next directory
+    push_u32(&mut debug, 0);                // flags
+
+    for (sst, imod, lfo, cb) in entries {
+        push_u16(&mut debug, sst);
+        push_u16(&mut debug, imod);
+        push_u32(&mut debug, lfo);
+        push_u32(&mut debug, cb);
+    }
+
+    //
+    // The trailing signature lets the debugger find the start of the information from the
+    // end of the file.
+    //
+    debug.extend_from_slice(b"NB09");
+    let base = debug.len() as u32 + 4;
+    push_u32(&mut debug, base);
+
+    Ok(debug)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::segment::{Align, Combine, SegName, Segment};
+
+    #[test]
+    fn field_list_indices() -> Result<(), LinkerError> {
+        let rec = [
+            0x04, 0x02,                                     // LF_FIELDLIST
+            0x06, 0x04, 0x74, 0x00, 0x03, 0x00, 0x00, 0x00, // LF_MEMBER int, public, offset 0
+            0x01, 0x61,                                     // "a"
+            0xf2, 0xf1,                                     // padding
+            0x06, 0x04, 0x00, 0x10, 0x03, 0x00, 0x02, 0x00, // LF_MEMBER 1000H, public, offset 2
+            0x01, 0x62,                                     // "b"
+        ];
+
+        assert_eq!(type_indices(&rec)?, vec![4, 16]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn packs_recursive_types() -> Result<(), LinkerError> {
+        //
+        // struct node { struct node *next; } in two modules: 1000H is the structure,
+        // 1001H its field list, and 1002H the pointer.
+        //
+        let structure = [0x05, 0x00, 0x01, 0x00, 0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x6e];
+        let fields = [0x04, 0x02, 0x06, 0x04, 0x02, 0x10, 0x03, 0x00, 0x00, 0x00, 0x01, 0x6e];
+        let pointer = [0x02, 0x00, 0x0a, 0x00, 0x00, 0x10];
+        let int = [0x01, 0x00, 0x01, 0x00, 0x74, 0x00];
+
+        let module1: Vec<&[u8]> = vec![&structure, &fields, &pointer];
+        let module2: Vec<&[u8]> = vec![&int, &structure, &fields, &pointer];
+
+        //
+        // Module 2's records are numbered one higher.
+        //
+        let mut structure2 = structure;
+        structure2[4] = 0x02;
+        let mut fields2 = fields;
+        fields2[4] = 0x03;
+        let mut pointer2 = pointer;
+        pointer2[4] = 0x01;
+        let module2: Vec<&[u8]> = vec![module2[0], &structure2, &fields2, &pointer2];
+
+        let packed = pack_types(&[module1, module2])?;
+
+        assert_eq!(packed.types.len(), 4);
+        assert_eq!(packed.maps[0], vec![0x1000, 0x1001, 0x1002]);
+        assert_eq!(packed.maps[1], vec![0x1003, 0x1000, 0x1001, 0x1002]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn links_scopes() -> Result<(), LinkerError> {
+        let mut data = vec![0x01, 0x00, 0x00, 0x00];
+
+        //
+        // S_GPROC16 with a return type of 1000H, then S_BLOCK16, S_END, S_END.
+        //
+        let mut proc16 = vec![0u8; 31];
+        proc16[0..2].copy_from_slice(&29u16.to_le_bytes());
+        proc16[2..4].copy_from_slice(&S_GPROC16.to_le_bytes());
+        proc16[26..28].copy_from_slice(&0x1000u16.to_le_bytes());
+        data.extend_from_slice(&proc16);
+
+        let mut block16 = vec![0u8; 20];
+        block16[0..2].copy_from_slice(&18u16.to_le_bytes());
+        block16[2..4].copy_from_slice(&S_BLOCK16.to_le_bytes());
+        data.extend_from_slice(&block16);
+
+        data.extend_from_slice(&[0x02, 0x00, 0x06, 0x00]);
+        data.extend_from_slice(&[0x02, 0x00, 0x06, 0x00]);
+
+        link_symbols(&mut data, &[0x1005])?;
+
+        let block = 4 + 31;
+        let end_block = block + 20;
+        let end_proc = end_block + 4;
+
+        assert_eq!(get_u16(&data, 4 + 26)?, 0x1005);
+        assert_eq!(u32::from_le_bytes(data[12..16].try_into().unwrap()), end_proc as u32);
+        assert_eq!(u32::from_le_bytes(data[block+4..block+8].try_into().unwrap()), 4);
+        assert_eq!(u32::from_le_bytes(data[block+8..block+12].try_into().unwrap()), end_block as u32);
+
+        Ok(())
+    }
+
+    #[test]
+    fn packed_segment_map() {
+        let mut state = LinkState::new();
+        let class = state.lnames.add("CODE");
+
+        for (name, base) in [("_TEXT", 0x100), ("FAR_TEXT", 0x132)] {
+            let mut seg = Segment::new(SegName::new(state.lnames.add(name), class, 0), 0x20, Align::Byte, Combine::Public);
+            seg.base = base;
+            let segidx = state.segments.add(seg);
+            state.segment_order.push(segidx);
+        }
+
+        state.segments[2].pack = 1;
+
+        //
+        // The second segment is at offset 32H in the frame of the first.
+        //
+        let sst = sst_seg_map(&state);
+        assert_eq!(&sst[4..24], &[5, 0, 0, 0, 0, 0, 0x10, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0x20, 0, 0, 0]);
+        assert_eq!(&sst[30..36], &[0x10, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(&sst[36..40], &[0x32, 0, 0, 0]);
+    }
+}
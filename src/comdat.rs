@@ -0,0 +1,292 @@
+use crate::linker_error::LinkerError;
+use crate::linkstate::LinkState;
+use crate::object::Object;
+use crate::record::{Record, RecordType};
+use crate::segment::{Align, Combine, SegName, Segment};
+
+//
+// COMDAT records hold initialized communal data: a function or variable which may be
+// defined by several modules, such as an inline function, of which the linker keeps one
+// copy. Each is named by a public symbol, and placed either in a SEGDEF of the module or
+// in a segment the linker provides. Their layout is
+//
+//     db flags            01 continues the previous COMDAT, 02 iterated data, 04 local
+//     db attributes       selection criteria in the high nibble, allocation in the low
+//     db align            as in a SEGDEF, or 0 for the alignment of the segment
+//     dw/dd offset        of this record's data in the COMDAT
+//     index type
+//     [index group, index segment, [dw frame]]   for explicit allocation
+//     index name          in the module's LNAMES and LLNAMES
+//     data                as in LEDATA, or LIDATA if iterated
+//
+// FIXUPP records after a COMDAT apply to its data.
+//
+
+const FLAG_ITERATED: u8 = 0x02;
+const FLAG_LOCAL: u8 = 0x04;
+
+/// How to choose between definitions of a COMDAT from different modules.
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Selection {
+    NoMatch,
+    PickAny,
+    SameSize,
+    ExactMatch,
+}
+
+/// Where a COMDAT is placed: in a SEGDEF of the module, or in a far code or data
+/// segment provided by the linker.
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Allocation {
+    Explicit,
+    FarCode,
+    FarData,
+}
+
+/// The fields of a COMDAT record before its data.
+///
+pub struct ComdatHeader {
+    pub iterated: bool,
+    pub local: bool,
+    pub selection: Selection,
+    pub allocation: Allocation,
+    pub align: Option<Align>,
+    pub offset: usize,
+    pub group: usize,
+    pub segment: usize,
+    pub name: String,
+}
+
+impl ComdatHeader {
+    /// Read the header of a COMDAT record, leaving `rec` at the data.
+    ///
+    pub fn read(rec: &mut Record, obj: &Object, state: &LinkState) -> Result<ComdatHeader, LinkerError> {
+        let flags = rec.byte()?;
+        let attributes = rec.byte()?;
+        let align = rec.byte()?;
+
+        let selection = match attributes >> 4 {
+            0 => Selection::NoMatch,
+            1 => Selection::PickAny,
+            2 => Selection::SameSize,
+            3 => Selection::ExactMatch,
+            _ => return Err(LinkerError::new(&format!("invalid COMDAT selection criteria {:02X}H.", attributes))),
+        };
+
+        //
+        // 32-bit code and data are allocated as 16-bit; they are limited to 64K anyway.
+        //
+        let allocation = match attributes & 0x0f {
+            0 => Allocation::Explicit,
+            1 | 3 => Allocation::FarCode,
+            2 | 4 => Allocation::FarData,
+            _ => return Err(LinkerError::new(&format!("invalid COMDAT allocation type {:02X}H.", attributes))),
+        };
+
+        let align = if align == 0 { None } else { Some(Align::from_acbp(align << 5)?) };
+
+        let offset = if rec.rectype == RecordType::COMDAT32 { rec.dword()? as usize } else { rec.word()? as usize };
+        let _typeidx = rec.index()?;
+
+        let (group, segment) = if allocation == Allocation::Explicit {
+            let group = rec.index()?;
+            let segment = rec.index()?;
+
+            if segment == 0 {
+                return Err(LinkerError::new("COMDAT at an absolute frame is not supported."));
+            }
+
+            if !obj.grpdefs.is_valid_index(group) || !obj.segdefs.is_valid_index(segment) {
+                return Err(LinkerError::new(&format!("invalid public base {}:{} in COMDAT", group, segment)));
+            }
+
+            (obj.grpdefs.get(group), segment)
+        } else {
+            (0, 0)
+        };
+
+        let nameidx = rec.index()?;
+
+        if nameidx == 0 || !obj.lnames.is_valid_index(nameidx) {
+            return Err(LinkerError::new(&format!("invalid name index {} in COMDAT", nameidx)));
+        }
+
+        Ok(ComdatHeader {
+            iterated: (flags & FLAG_ITERATED) != 0,
+            local: (flags & FLAG_LOCAL) != 0,
+            selection,
+            allocation,
+            align,
+            offset,
+            group,
+            segment,
+            name: state.lnames.get(obj.lnames.get(nameidx)).to_owned(),
+        })
+    }
+}
+
+/// Read just enough of a COMDAT record, without the module to check it against, to
+/// return the index of its name in the module's LNAMES and LLNAMES if it is local.
+///
+pub fn local_comdat_nameidx(rec: &mut Record) -> Result<Option<usize>, LinkerError> {
+    let flags = rec.byte()?;
+    let attributes = rec.byte()?;
+    let _align = rec.byte()?;
+
+    if rec.rectype == RecordType::COMDAT32 { rec.dword()?; } else { rec.word()?; }
+    let _typeidx = rec.index()?;
+
+    if attributes & 0x0f == 0 {
+        let _group = rec.index()?;
+
+        if rec.index()? == 0 {
+            let _frame = rec.word()?;
+        }
+    }
+
+    let nameidx = rec.index()?;
+
+    Ok(((flags & FLAG_LOCAL) != 0).then_some(nameidx))
+}
+
+/// A COMDAT defined by a module. Once the module has been read, `segdef` is the index of
+/// the SEGDEF the linker made to hold it, or None if another module's definition was
+/// chosen.
+///
+#[derive(Clone)]
+pub struct Comdat {
+    pub name: String,
+    pub local: bool,
+    pub selection: Selection,
+    pub allocation: Allocation,
+    pub align: Option<Align>,
+    pub group: usize,
+    pub segment: usize,
+    pub data: Vec<u8>,
+    pub segdef: Option<usize>,
+}
+
+impl Comdat {
+    pub fn new(header: &ComdatHeader) -> Comdat {
+        Comdat {
+            name: header.name.clone(),
+            local: header.local,
+            selection: header.selection,
+            allocation: header.allocation,
+            align: header.align,
+            group: header.group,
+            segment: header.segment,
+            data: Vec::new(),
+            segdef: None,
+        }
+    }
+
+    /// Install a record's data at `offset` in the COMDAT.
+    ///
+    pub fn add_data(&mut self, offset: usize, data: &[u8]) {
+        if self.data.len() < offset + data.len() {
+            self.data.resize(offset + data.len(), 0);
+        }
+
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    /// Check a later module's definition of the COMDAT against the chosen one, which
+    /// is kept.
+    ///
+    pub fn check_duplicate(&self, other: &Comdat) -> Result<(), LinkerError> {
+        match self.selection {
+            Selection::NoMatch => Err(LinkerError::new(&format!("COMDAT {} is multiply defined.", self.name))),
+            Selection::PickAny => Ok(()),
+            Selection::SameSize if self.data.len() != other.data.len() =>
+                Err(LinkerError::new(&format!("COMDAT {} is defined with different sizes.", self.name))),
+            Selection::ExactMatch if self.data != other.data =>
+                Err(LinkerError::new(&format!("COMDAT {} is defined with different contents.", self.name))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The segment the linker provides for far code or far data COMDATs.
+///
+pub fn allocation_segment(state: &mut LinkState, allocation: Allocation) -> usize {
+    let (name, class) = match allocation {
+        Allocation::FarCode => ("COMDAT_CODE", "CODE"),
+        _ => ("COMDAT_DATA", "FAR_DATA"),
+    };
+
+    let segname = SegName::new(state.lnames.find_or_add(name), state.lnames.find_or_add(class), state.lnames.find_or_add(""));
+
+    match state.get_segment_named(&segname) {
+        Some(index) => index,
+        None => state.segments.add(Segment::new(segname, 0, Align::Byte, Combine::Public)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn comdat(selection: Selection, data: &[u8]) -> Comdat {
+        let mut comdat = Comdat {
+            name: "_inline".to_owned(),
+            local: false,
+            selection,
+            allocation: Allocation::FarCode,
+            align: None,
+            group: 0,
+            segment: 0,
+            data: Vec::new(),
+            segdef: None,
+        };
+        comdat.add_data(0, data);
+        comdat
+    }
+
+    #[test]
+    fn header() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let mut obj = Object::new();
+        obj.lnames.add(state.lnames.add("_TEXT"));
+        obj.lnames.add(state.lnames.add("_inline"));
+
+        let rec = [0xc2, 0x09, 0x00, 0x03, 0x21, 0x02, 0x10, 0x00, 0x00, 0x02, 0xcb, 0x00];
+        let mut rec = Record::new(&rec)?;
+        let header = ComdatHeader::read(&mut rec, &obj, &state)?;
+
+        assert!(header.iterated && !header.local);
+        assert_eq!(header.selection, Selection::SameSize);
+        assert_eq!(header.allocation, Allocation::FarCode);
+        assert_eq!(header.align, Some(Align::Word));
+        assert_eq!(header.offset, 0x10);
+        assert_eq!(header.name, "_inline");
+        assert_eq!(rec.rest(), &[0xcb]);
+
+        let rec = [0xc2, 0x09, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut rec = Record::new(&rec)?;
+        assert!(ComdatHeader::read(&mut rec, &obj, &state)
+            .is_err_and(|err| err.to_string() == "COMDAT at an absolute frame is not supported."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn selection() {
+        let chosen = comdat(Selection::PickAny, &[0xc3]);
+        assert!(chosen.check_duplicate(&comdat(Selection::PickAny, &[0x90, 0xc3])).is_ok());
+
+        let chosen = comdat(Selection::NoMatch, &[0xc3]);
+        assert!(chosen.check_duplicate(&comdat(Selection::NoMatch, &[0xc3]))
+            .is_err_and(|err| err.to_string() == "COMDAT _inline is multiply defined."));
+
+        let chosen = comdat(Selection::SameSize, &[0xc3]);
+        assert!(chosen.check_duplicate(&comdat(Selection::SameSize, &[0xcb])).is_ok());
+        assert!(chosen.check_duplicate(&comdat(Selection::SameSize, &[0x90, 0xc3])).is_err());
+
+        let chosen = comdat(Selection::ExactMatch, &[0xc3]);
+        assert!(chosen.check_duplicate(&comdat(Selection::ExactMatch, &[0xc3])).is_ok());
+        assert!(chosen.check_duplicate(&comdat(Selection::ExactMatch, &[0xcb])).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::linker_error::LinkerError;
+
+//
+// DOS device drivers. A driver starts with a device header, at offset 0 of the segment
+// DOS loads it at:
+//
+//     dd next             next driver in the file, or FFFF:FFFF for the last
+//     dw attributes       bit 15 set for a character device
+//     dw strategy         offset of the strategy routine
+//     dw interrupt        offset of the interrupt routine
+//     db 8 dup (?)        a character device's name, or a block device's unit count
+//
+
+const DEVICE_HEADER_SIZE: usize = 18;
+
+const OFF_ATTRIBUTES: usize = 4;
+const OFF_STRATEGY: usize = 6;
+const OFF_INTERRUPT: usize = 8;
+const OFF_NAME: usize = 10;
+
+const ATTR_CHARACTER: u16 = 0x8000;
+
+/// Check that `image` holds a device header at `start`, with its strategy and interrupt
+/// routines past the header and in the image.
+///
+pub fn check_device_header(image: &[u8], start: usize) -> Result<(), LinkerError> {
+    if start + DEVICE_HEADER_SIZE > image.len() {
+        return Err(LinkerError::new("device driver is too short to hold a device header."));
+    }
+
+    let header = &image[start..start + DEVICE_HEADER_SIZE];
+    let word = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+    let end = std::cmp::min(image.len() - start, 0x10000);
+
+    for (routine, at) in [("strategy", OFF_STRATEGY), ("interrupt", OFF_INTERRUPT)] {
+        let offset = word(at) as usize;
+
+        if offset < DEVICE_HEADER_SIZE || offset >= end {
+            return Err(LinkerError::new(&format!("device header's {} routine at offset {:04X}H is outside the driver.", routine, offset)));
+        }
+    }
+
+    if word(OFF_ATTRIBUTES) & ATTR_CHARACTER != 0 && !header[OFF_NAME..].iter().all(|c| (0x20..0x7f).contains(c)) {
+        return Err(LinkerError::new("character device's name is not 8 printable characters."));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn driver(attributes: u16, strategy: u16, interrupt: u16, name: &[u8; 8]) -> Vec<u8> {
+        let mut image = vec![0xff, 0xff, 0xff, 0xff];
+        image.extend_from_slice(&attributes.to_le_bytes());
+        image.extend_from_slice(&strategy.to_le_bytes());
+        image.extend_from_slice(&interrupt.to_le_bytes());
+        image.extend_from_slice(name);
+        image.resize(0x40, 0xcb);
+        image
+    }
+
+    #[test]
+    fn device_headers() {
+        assert!(check_device_header(&driver(0x8000, 0x12, 0x20, b"CLOCK$  "), 0).is_ok());
+        assert!(check_device_header(&driver(0x0000, 0x12, 0x20, b"\x01\0\0\0\0\0\0\0"), 0).is_ok());
+
+        let mut exe = vec![0; 0x20];
+        exe.extend(driver(0x8000, 0x12, 0x20, b"NUL2    "));
+        assert!(check_device_header(&exe, 0x20).is_ok());
+
+        assert!(check_device_header(&driver(0x8000, 0x40, 0x20, b"CLOCK$  "), 0)
+            .is_err_and(|err| err.to_string() == "device header's strategy routine at offset 0040H is outside the driver."));
+        assert!(check_device_header(&driver(0x8000, 0x12, 0x04, b"CLOCK$  "), 0).is_err());
+        assert!(check_device_header(&driver(0x8000, 0x12, 0x20, b"CLOCK$\0\0"), 0).is_err());
+        assert!(check_device_header(&[0xff; 0x10], 0).is_err());
+    }
+}
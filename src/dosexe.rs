@@ -1,9 +1,41 @@
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
+use crate::exepack;
 use crate::linker_error::LinkerError;
 use crate::linkstate::FarPtr;
 
+/// The size of a page in an MZ executable file.
+///
+const PAGE_SIZE: usize = 512;
+const PARA_SIZE: usize = 16;
+
+//
+// Offsets of fields in the MZ header.
+//
+const OFF_MZ_SIG: usize = 0x00;
+const OFF_EXTRA_BYTES: usize = 0x02;
+const OFF_PAGES: usize = 0x04;
+const OFF_RELOCS: usize = 0x06;
+const OFF_HEADER_SIZE: usize = 0x08;
+const OFF_MIN_ALLOC: usize = 0x0a;
+const OFF_MAX_ALLOC: usize = 0x0c;
+const OFF_SS: usize = 0x0e;
+const OFF_SP: usize = 0x10;
+const OFF_CHECKSUM: usize = 0x12;
+const OFF_IP: usize = 0x14;
+const OFF_CS: usize = 0x16;
+const OFF_RELOC_OFFSET: usize = 0x18;
+const OFF_OVERLAY: usize = 0x1a;
+const OFF_OVERLAY_DATA: usize = 0x1c;
+const FIXED_HEADER_SIZE: usize = 0x1c;
+
+//
+// NB this is where tlink starts relocations. We start them here as well,
+// just to make the binary diff with tlink output easier.
+//
+const RELOC_START: usize = 0x3e;
+
 /// A relocation table entry
 ///
 pub struct Relocation {
@@ -20,6 +52,8 @@ pub struct DosExe<'a> {
     entry_point: FarPtr,
     init_stack: FarPtr,
     data: &'a [u8],
+    debug_info: Vec<u8>,
+    exepack: bool,
 }
 
 impl<'a> DosExe<'a> {
@@ -31,6 +65,8 @@ impl<'a> DosExe<'a> {
             entry_point: FarPtr::null(),
             init_stack: FarPtr::null(),
             data,
+            debug_info: Vec::new(),
+            exepack: false,
         }
     }
 
@@ -78,48 +114,67 @@ impl<'a> DosExe<'a> {
         self.max_alloc = max_alloc;
     }
 
+    /// Set debug information to be appended to the file after the load image, where
+    /// DOS will not load it.
+    ///
+    pub fn set_debug_info(&mut self, debug_info: Vec<u8>) {
+        self.debug_info = debug_info;
+    }
+
+    /// Compress the executable with EXEPACK when it is written.
+    ///
+    pub fn set_exepack(&mut self, exepack: bool) {
+        self.exepack = exepack;
+    }
+
     /// Add an entry to the relocation table.
     ///
     pub fn add_relocation(&mut self, reloc: Relocation) {
         self.relocs.push(reloc);
     }
 
-    pub fn write(&self, fname: &PathBuf) -> Result<(), LinkerError> {
-        const OFF_MZ_SIG: usize = 0x00;
-        const OFF_EXTRA_BYTES: usize = 0x02;
-        const OFF_PAGES: usize = 0x04;
-        const OFF_RELOCS: usize = 0x06;
-        const OFF_HEADER_SIZE: usize = 0x08;
-        const OFF_MIN_ALLOC: usize = 0x0a;
-        const OFF_MAX_ALLOC: usize = 0x0c;
-        const OFF_SS: usize = 0x0e;
-        const OFF_SP: usize = 0x10;
-        const _OFF_CHECKSUM: usize = 0x12;
-        const OFF_IP: usize = 0x14;
-        const OFF_CS: usize = 0x16;
-        const OFF_RELOC_OFFSET: usize = 0x18;
-        const OFF_OVERLAY: usize = 0x1a;
-        const OFF_OVERLAY_DATA: usize = 0x1c;
-        const _FIXED_HEADER_SIZE: usize = 0x1e;
-        const PAGE_SIZE: usize = 512;
-        const PARA_SIZE: usize = 16;
+    /// The number of pages the header, including a relocation table of `relocs`
+    /// entries, takes.
+    ///
+    fn header_pages(relocs: usize) -> usize {
+        let header_size = RELOC_START + (relocs * 4);
+        (header_size + PAGE_SIZE - 1) / PAGE_SIZE
+    }
 
+    /// The size of the executable file without any debug information.
+    ///
+    pub fn file_size(&self) -> usize {
+        Self::header_pages(self.relocs.len()) * PAGE_SIZE + self.data.len()
+    }
+
+    /// Build the executable file's contents.
+    ///
+    fn build(&self) -> Result<Vec<u8>, LinkerError> {
         //
-        // NB this is where tlink starts relocations. We start them here as well,
-        // just to make the binary diff with tlink output easier.
+        // With EXEPACK, the file holds the packed program, which has no relocations
+        // of its own and starts in the unpacker.
         //
-        const RELOC_START: usize = 0x3e;
+        let packed = if self.exepack {
+            exepack::pack(self.data, &self.relocs, self.entry_point, self.init_stack, self.min_alloc, self.max_alloc)?
+        } else {
+            None
+        };
+
+        if self.exepack && packed.is_none() {
+            eprintln!("warning: EXEPACK would not make the program smaller; it is not packed.");
+        }
 
-        if self.relocs.len() > 0xffff {
+        let (data, relocs, entry_point, init_stack, min_alloc, max_alloc) = match &packed {
+            Some(packed) => (&packed.image[..], &[][..], packed.entry, packed.stack, packed.min_alloc, packed.max_alloc),
+            None => (self.data, &self.relocs[..], self.entry_point, self.init_stack, self.min_alloc, self.max_alloc),
+        };
+
+        if relocs.len() > 0xffff {
             return Err(LinkerError::new("Too many relocations (max 65535)"));
         }
 
-        //
-        // Figure out how many pages for the header
-        //
-        let header_size = RELOC_START + (self.relocs.len() * 4);
-        let header_pages = (header_size + PAGE_SIZE - 1) / PAGE_SIZE;
-        let image_pages = (self.data.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+        let header_pages = Self::header_pages(relocs.len());
+        let image_pages = (data.len() + PAGE_SIZE - 1) / PAGE_SIZE;
         let total_pages = header_pages + image_pages;
 
         if image_pages > 0xffff {
@@ -135,26 +190,22 @@ impl<'a> DosExe<'a> {
         header[OFF_MZ_SIG] = 'M' as u8;
         header[OFF_MZ_SIG+1] = 'Z' as u8;
 
-        let extra_bytes = (self.data.len() % PAGE_SIZE) as u16;
+        let extra_bytes = (data.len() % PAGE_SIZE) as u16;
         header[OFF_EXTRA_BYTES..OFF_EXTRA_BYTES+2].copy_from_slice(&extra_bytes.to_le_bytes());
         header[OFF_PAGES..OFF_PAGES+2].copy_from_slice(&(total_pages as u16).to_le_bytes());
-        header[OFF_RELOCS..OFF_RELOCS+2].copy_from_slice(&(self.relocs.len() as u16).to_le_bytes());
+        header[OFF_RELOCS..OFF_RELOCS+2].copy_from_slice(&(relocs.len() as u16).to_le_bytes());
 
         let header_para = (header_pages * PAGE_SIZE / PARA_SIZE) as u16;
         header[OFF_HEADER_SIZE..OFF_HEADER_SIZE+2].copy_from_slice(&header_para.to_le_bytes());
 
-        header[OFF_MIN_ALLOC..OFF_MIN_ALLOC+2].copy_from_slice(&self.min_alloc.to_le_bytes());
-        header[OFF_MAX_ALLOC..OFF_MAX_ALLOC+2].copy_from_slice(&self.max_alloc.to_le_bytes());
+        header[OFF_MIN_ALLOC..OFF_MIN_ALLOC+2].copy_from_slice(&min_alloc.to_le_bytes());
+        header[OFF_MAX_ALLOC..OFF_MAX_ALLOC+2].copy_from_slice(&max_alloc.to_le_bytes());
 
-        header[OFF_SS..OFF_SS+2].copy_from_slice(&self.init_stack.seg.to_le_bytes());
-        header[OFF_SP..OFF_SP+2].copy_from_slice(&self.init_stack.offset.to_le_bytes());
-
-        //
-        // TODO compute checksum
-        //
+        header[OFF_SS..OFF_SS+2].copy_from_slice(&init_stack.seg.to_le_bytes());
+        header[OFF_SP..OFF_SP+2].copy_from_slice(&init_stack.offset.to_le_bytes());
 
-        header[OFF_IP..OFF_IP+2].copy_from_slice(&self.entry_point.offset.to_le_bytes());
-        header[OFF_CS..OFF_CS+2].copy_from_slice(&self.entry_point.seg.to_le_bytes());
+        header[OFF_IP..OFF_IP+2].copy_from_slice(&entry_point.offset.to_le_bytes());
+        header[OFF_CS..OFF_CS+2].copy_from_slice(&entry_point.seg.to_le_bytes());
 
         header[OFF_RELOC_OFFSET..OFF_RELOC_OFFSET+2].copy_from_slice(&(RELOC_START as u16).to_le_bytes());
 
@@ -164,7 +215,7 @@ impl<'a> DosExe<'a> {
         //
         // Relocations
         //
-        for (i, reloc) in self.relocs.iter().enumerate() {
+        for (i, reloc) in relocs.iter().enumerate() {
             let offset = i * 4 + RELOC_START;
 
             header[offset..offset+2].copy_from_slice(&reloc.offset.to_le_bytes());
@@ -172,12 +223,238 @@ impl<'a> DosExe<'a> {
         }
 
         //
-        // Write the file
+        // The checksum makes the words of the header and load image sum to FFFFH.
         //
+        let checksum = !word_sum(&header).wrapping_add(word_sum(data));
+        header[OFF_CHECKSUM..OFF_CHECKSUM+2].copy_from_slice(&checksum.to_le_bytes());
+
+        let mut exe = header;
+        exe.extend_from_slice(data);
+        exe.extend_from_slice(&self.debug_info);
+
+        Ok(exe)
+    }
+
+    pub fn write(&self, fname: &PathBuf) -> Result<(), LinkerError> {
         let mut exe = fs::File::create(fname)?;
+        exe.write_all(&self.build()?)?;
+        Ok(())
+    }
+}
+
+/// The 16-bit sum, ignoring overflow, of the little-endian words of `data`. An odd last
+/// byte is summed as if followed by a zero. The header and load image are both a whole
+/// number of paragraphs in the files we write, so they can be summed separately.
+///
+fn word_sum(data: &[u8]) -> u16 {
+    data.chunks(2).fold(0u16, |sum, word| sum.wrapping_add(u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+}
+
+fn get_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at+1]])
+}
+
+/// The fields of an MZ header, as read back from a file.
+///
+pub struct MzHeader {
+    pub extra_bytes: u16,
+    pub pages: u16,
+    pub relocs: u16,
+    pub header_para: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub stack: FarPtr,
+    pub checksum: u16,
+    pub entry_point: FarPtr,
+    pub reloc_offset: u16,
+    pub overlay: u16,
+}
+
+impl MzHeader {
+    pub fn parse(file: &[u8]) -> Result<MzHeader, LinkerError> {
+        if file.len() < FIXED_HEADER_SIZE || (&file[OFF_MZ_SIG..OFF_MZ_SIG+2] != b"MZ" && &file[OFF_MZ_SIG..OFF_MZ_SIG+2] != b"ZM") {
+            return Err(LinkerError::new("not an MZ executable."));
+        }
+
+        Ok(MzHeader {
+            extra_bytes: get_u16(file, OFF_EXTRA_BYTES),
+            pages: get_u16(file, OFF_PAGES),
+            relocs: get_u16(file, OFF_RELOCS),
+            header_para: get_u16(file, OFF_HEADER_SIZE),
+            min_alloc: get_u16(file, OFF_MIN_ALLOC),
+            max_alloc: get_u16(file, OFF_MAX_ALLOC),
+            stack: FarPtr::new(get_u16(file, OFF_SS), get_u16(file, OFF_SP)),
+            checksum: get_u16(file, OFF_CHECKSUM),
+            entry_point: FarPtr::new(get_u16(file, OFF_CS), get_u16(file, OFF_IP)),
+            reloc_offset: get_u16(file, OFF_RELOC_OFFSET),
+            overlay: get_u16(file, OFF_OVERLAY),
+        })
+    }
+
+    /// The size of the part of the file DOS loads, the header and the load image.
+    ///
+    pub fn file_size(&self) -> usize {
+        match self.extra_bytes {
+            0 => self.pages as usize * PAGE_SIZE,
+            extra => (self.pages as usize).saturating_sub(1) * PAGE_SIZE + extra as usize,
+        }
+    }
+
+    pub fn header_size(&self) -> usize {
+        self.header_para as usize * PARA_SIZE
+    }
+
+    pub fn image_size(&self) -> usize {
+        self.file_size().saturating_sub(self.header_size())
+    }
+}
+
+/// Check that an executable's header is consistent with the file and with itself. Returns
+/// the header and a description of each problem found.
+///
+pub fn verify(file: &[u8]) -> Result<(MzHeader, Vec<String>), LinkerError> {
+    let header = MzHeader::parse(file)?;
+    let mut problems = Vec::new();
+
+    let file_size = header.file_size();
+    let header_size = header.header_size();
+    let image_size = header.image_size();
+
+    if header.extra_bytes as usize >= PAGE_SIZE {
+        problems.push(format!("last page byte count {:04X}H is not less than a page.", header.extra_bytes));
+    }
+
+    if file_size > file.len() {
+        problems.push(format!("header gives a file size of {:05X}H bytes, but the file is {:05X}H bytes.", file_size, file.len()));
+    }
+
+    if header_size < FIXED_HEADER_SIZE || header_size > file_size {
+        problems.push(format!("header size of {:04X}H paragraphs does not fit the file.", header.header_para));
+    }
+
+    //
+    // Relocations must be in the header, and patch words in the load image.
+    //
+    let reloc_table = header.reloc_offset as usize..header.reloc_offset as usize + header.relocs as usize * 4;
+
+    if header.relocs != 0 && (reloc_table.start < FIXED_HEADER_SIZE || reloc_table.end > std::cmp::min(header_size, file.len())) {
+        problems.push(format!("relocation table of {} entries at {:04X}H is outside the header.", header.relocs, header.reloc_offset));
+    } else {
+        for (i, at) in reloc_table.step_by(4).enumerate() {
+            let reloc = FarPtr::new(get_u16(file, at + 2), get_u16(file, at));
+
+            if reloc.to_linear() + 2 > image_size {
+                problems.push(format!("relocation {} at {:04X}:{:04X} is outside the load image.", i + 1, reloc.seg, reloc.offset));
+            }
+        }
+    }
+
+    //
+    // The program starts in the load image, with its stack in the memory DOS allocates.
+    //
+    if header.entry_point.to_linear() >= image_size {
+        problems.push(format!("entry point {:04X}:{:04X} is outside the load image.", header.entry_point.seg, header.entry_point.offset));
+    }
+
+    let memory = (image_size.div_ceil(PARA_SIZE) + header.min_alloc as usize) * PARA_SIZE;
+    let stack_top = match header.stack.offset {
+        0 => ((header.stack.seg as usize) << 4) + 0x10000,
+        _ => header.stack.to_linear(),
+    };
+
+    if stack_top > memory {
+        problems.push(format!("stack {:04X}:{:04X} is outside the allocated memory.", header.stack.seg, header.stack.offset));
+    }
+
+    if header.max_alloc < header.min_alloc {
+        problems.push(format!("maximum allocation {:04X}H is less than minimum allocation {:04X}H.", header.max_alloc, header.min_alloc));
+    }
+
+    //
+    // A zero checksum means none was computed.
+    //
+    if header.checksum != 0 && file_size <= file.len() && word_sum(&file[..file_size]) != 0xffff {
+        problems.push(format!("checksum {:04X}H is wrong.", header.checksum));
+    }
+
+    Ok((header, problems))
+}
+
+/// Read back an executable and print a summary of it, failing if it has problems.
+///
+pub fn verify_file(path: &PathBuf) -> Result<(), LinkerError> {
+    let file = fs::read(path)?;
+    let (header, problems) = verify(&file)?;
+
+    println!("{}: MZ executable", path.display());
+    println!(" File size      {:05X}H bytes loaded, {:05X}H bytes in file", header.file_size(), file.len());
+    println!(" Header         {:04X}H paragraphs, {} relocations at {:04X}H", header.header_para, header.relocs, header.reloc_offset);
+    println!(" Load image     {:05X}H bytes", header.image_size());
+    println!(" Allocation     {:04X}H paragraphs minimum, {:04X}H maximum", header.min_alloc, header.max_alloc);
+    println!(" Entry point    {:04X}:{:04X}", header.entry_point.seg, header.entry_point.offset);
+    println!(" Stack          {:04X}:{:04X}", header.stack.seg, header.stack.offset);
+    println!(" Checksum       {:04X}H", header.checksum);
+    println!(" Overlay        {}", header.overlay);
+
+    for problem in problems.iter() {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+
+    if !problems.is_empty() {
+        return Err(LinkerError::new(&format!("{}: {} problems found.", path.display(), problems.len())));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_exe(data: &[u8]) -> Result<DosExe<'_>, LinkerError> {
+        let mut exe = DosExe::new(data);
+        exe.set_entry_point(&FarPtr::new(0x0001, 0x0010))?;
+        exe.set_stack(0x0003, 0x0100);
+        exe.set_min_alloc(0x0010);
+        exe.add_relocation(Relocation { seg: 0x0002, offset: 0x0004 });
+        Ok(exe)
+    }
+
+    #[test]
+    fn checksum() -> Result<(), LinkerError> {
+        let data = (0..0x45).collect::<Vec<u8>>();
+        let file = sample_exe(&data)?.build()?;
+
+        assert_eq!(word_sum(&file), 0xffff);
+
+        let (header, problems) = verify(&file)?;
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(header.image_size(), 0x45);
+        assert_eq!(header.relocs, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_problems() -> Result<(), LinkerError> {
+        let data = vec![0x90u8; 0x40];
+        let mut file = sample_exe(&data)?.build()?;
+
+        file[OFF_IP] = 0x40;
+        file[OFF_SS] = 0x10;
+        file[RELOC_START + 2] = 0x10;
+
+        let (_, problems) = verify(&file)?;
+        assert_eq!(problems, vec![
+            "relocation 1 at 0010:0004 is outside the load image.",
+            "entry point 0001:0040 is outside the load image.",
+            "stack 0010:0100 is outside the allocated memory.",
+            "checksum 91E7H is wrong.",
+        ]);
 
-        exe.write_all(&header)?;
-        exe.write_all(&self.data)?;
+        let (_, problems) = verify(&file[..0x230])?;
+        assert_eq!(problems[0], "header gives a file size of 00240H bytes, but the file is 00230H bytes.");
+        assert!(verify(b"not an executable").is_err());
 
         Ok(())
     }
@@ -0,0 +1,434 @@
+use crate::dosexe::Relocation;
+use crate::linker_error::LinkerError;
+use crate::linkstate::FarPtr;
+
+//
+// EXEPACK compression of MZ executables.
+//
+// A packed executable's load image is the run-length compressed program image, padded
+// to a paragraph with FFH, followed by the EXEPACK block: an 18-byte header, the unpacker,
+// and the packed relocation table. The MZ header starts the program in the unpacker with
+// no relocations.
+//
+// The compressed data is a sequence of commands which are decoded from the end of the
+// data backwards, each building the image backwards from its end. A command is a byte
+// (B0H fill, B2H copy, with bit 0 set on the last one) preceded by a 16-bit length, which
+// is preceded by the fill byte or the bytes to copy.
+//
+// The relocation table has 16 sections, one for each 64K of the image. Each is a count
+// followed by that many offsets, relative to the start of the section.
+//
+
+/// The unpacker, preceded by the EXEPACK header. On entry CS is the EXEPACK block, and
+/// ES the PSP. It copies the EXEPACK block out of the way to `move_para` paragraphs above
+/// the load segment, then decodes the image in place, except that the image is built
+/// `shift` paragraphs higher than it belongs so that decoding never overwrites data it
+/// has still to read. It then moves the image down, applies relocations, and starts the
+/// program.
+///
+///         dw real_ip, real_cs, mem_start, exepack_size, real_sp, real_ss, dest_len, skip_len
+///         db 'RB'
+/// start:  mov cs:[saved_ax],ax                ; load segment in BP throughout
+///         mov ax,es / add ax,10h / mov bp,ax
+///         push cs / pop ds
+///         mov bx,[move_para] / add bx,bp / mov [entry2+2],bx
+///         mov es,bx / xor si,si / xor di,di / mov cx,[6] / cld / rep movsb
+///         jmp dword ptr [entry2]
+/// relocated:                                  ; compressed data ends at DS:0
+///         mov ax,ds / xor si,si / call norm / mov ds,ax / dec si
+///         mov ax,cs:[0ch] / add ax,cs:[shift] / add ax,bp
+///         xor di,di / xchg si,di / call norm / xchg si,di / mov es,ax / dec di
+///         std
+/// skip:   lodsb / cmp al,0ffh / je skip / inc si
+/// command:
+///         mov ax,ds / call norm / mov ds,ax
+///         mov ax,es / xchg si,di / call norm / xchg si,di / mov es,ax
+///         lodsb / mov dl,al / dec si / lodsw / mov cx,ax / inc si
+///         mov al,dl / and al,0feh / cmp al,0b0h / jne @f
+///         lodsb / rep stosb / jmp next
+/// @@:     cmp al,0b2h / je copy
+/// corrupt:
+///         cld / push cs / pop ds / mov dx,offset message / mov ah,9 / int 21h
+///         mov ax,4cffh / int 21h
+/// copy:   rep movsb
+/// next:   test dl,1 / jz command
+///         cld / mov bx,cs:[shift] / or bx,bx / jz moved
+///         mov dx,cs:[0ch] / mov ax,bp
+/// move:   mov cx,800h / cmp dx,cx / jae @f / mov cx,dx
+/// @@:     mov es,ax / add ax,bx / mov ds,ax / sub ax,bx / add ax,cx / sub dx,cx
+///         shl cx,1 / shl cx,1 / shl cx,1 / xor si,si / xor di,di / rep movsw
+///         or dx,dx / jnz move
+/// moved:  push cs / pop ds / mov si,offset relocs / xor dx,dx
+/// section:
+///         lodsw / mov cx,ax / jcxz @f
+/// reloc:  lodsw / mov di,ax / mov ax,dx / add ax,bp / mov es,ax / add es:[di],bp / loop reloc
+/// @@:     add dx,1000h / jnz section
+///         mov ax,[0ah] / add ax,bp / mov bx,[8] / add [2],bp / mov dx,bp / sub dx,10h
+///         cli / mov ss,ax / mov sp,bx / sti / mov es,dx / mov ds,dx
+///         mov ax,cs:[saved_ax] / jmp dword ptr cs:[0]
+/// norm:   mov bx,si / and si,0fh / mov cl,4 / shr bx,cl / add ax,bx   ; make AX:SI have
+///         cmp ax,0fffh / jb @f / sub ax,0fffh / add si,0fff0h / ret   ; the largest offset
+/// @@:     shl ax,cl / add si,ax / xor ax,ax / ret
+/// message:   db 'Packed file is corrupt$'
+/// saved_ax:  dw 0
+/// move_para: dw 0
+/// shift:     dw 0
+/// entry2:    dw relocated, 0
+/// relocs:
+///
+const UNPACKER: [u8; 0x15d] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x52, 0x42, 0x2e, 0xa3, 0x53, 0x01, 0x8c, 0xc0, 0x83, 0xc0, 0x10, 0x89, 0xc5, 0x0e, 0x1f, 0x8b,
+    0x1e, 0x55, 0x01, 0x01, 0xeb, 0x89, 0x1e, 0x5b, 0x01, 0x8e, 0xc3, 0x31, 0xf6, 0x31, 0xff, 0x8b,
+    0x0e, 0x06, 0x00, 0xfc, 0xf3, 0xa4, 0xff, 0x2e, 0x59, 0x01, 0x8c, 0xd8, 0x31, 0xf6, 0xe8, 0xdd,
+    0x00, 0x8e, 0xd8, 0x4e, 0x2e, 0xa1, 0x0c, 0x00, 0x2e, 0x03, 0x06, 0x57, 0x01, 0x01, 0xe8, 0x31,
+    0xff, 0x87, 0xfe, 0xe8, 0xc8, 0x00, 0x87, 0xfe, 0x8e, 0xc0, 0x4f, 0xfd, 0xac, 0x3c, 0xff, 0x74,
+    0xfb, 0x46, 0x8c, 0xd8, 0xe8, 0xb7, 0x00, 0x8e, 0xd8, 0x8c, 0xc0, 0x87, 0xfe, 0xe8, 0xae, 0x00,
+    0x87, 0xfe, 0x8e, 0xc0, 0xac, 0x88, 0xc2, 0x4e, 0xad, 0x89, 0xc1, 0x46, 0x88, 0xd0, 0x24, 0xfe,
+    0x3c, 0xb0, 0x75, 0x05, 0xac, 0xf3, 0xaa, 0xeb, 0x15, 0x3c, 0xb2, 0x74, 0x0f, 0xfc, 0x0e, 0x1f,
+    0xba, 0x3c, 0x01, 0xb4, 0x09, 0xcd, 0x21, 0xb8, 0xff, 0x4c, 0xcd, 0x21, 0xf3, 0xa4, 0xf6, 0xc2,
+    0x01, 0x74, 0xbf, 0xfc, 0x2e, 0x8b, 0x1e, 0x57, 0x01, 0x09, 0xdb, 0x74, 0x2c, 0x2e, 0x8b, 0x16,
+    0x0c, 0x00, 0x89, 0xe8, 0xb9, 0x00, 0x08, 0x39, 0xca, 0x73, 0x02, 0x89, 0xd1, 0x8e, 0xc0, 0x01,
+    0xd8, 0x8e, 0xd8, 0x29, 0xd8, 0x01, 0xc8, 0x29, 0xca, 0xd1, 0xe1, 0xd1, 0xe1, 0xd1, 0xe1, 0x31,
+    0xf6, 0x31, 0xff, 0xf3, 0xa5, 0x09, 0xd2, 0x75, 0xdb, 0x0e, 0x1f, 0xbe, 0x5d, 0x01, 0x31, 0xd2,
+    0xad, 0x89, 0xc1, 0xe3, 0x0e, 0xad, 0x89, 0xc7, 0x89, 0xd0, 0x01, 0xe8, 0x8e, 0xc0, 0x26, 0x01,
+    0x2d, 0xe2, 0xf2, 0x81, 0xc2, 0x00, 0x10, 0x75, 0xe7, 0xa1, 0x0a, 0x00, 0x01, 0xe8, 0x8b, 0x1e,
+    0x08, 0x00, 0x01, 0x2e, 0x02, 0x00, 0x89, 0xea, 0x83, 0xea, 0x10, 0xfa, 0x8e, 0xd0, 0x89, 0xdc,
+    0xfb, 0x8e, 0xc2, 0x8e, 0xda, 0x2e, 0xa1, 0x53, 0x01, 0x2e, 0xff, 0x2e, 0x00, 0x00, 0x89, 0xf3,
+    0x83, 0xe6, 0x0f, 0xb1, 0x04, 0xd3, 0xeb, 0x01, 0xd8, 0x3d, 0xff, 0x0f, 0x72, 0x07, 0x2d, 0xff,
+    0x0f, 0x83, 0xc6, 0xf0, 0xc3, 0xd3, 0xe0, 0x01, 0xc6, 0x31, 0xc0, 0xc3, 0x50, 0x61, 0x63, 0x6b,
+    0x65, 0x64, 0x20, 0x66, 0x69, 0x6c, 0x65, 0x20, 0x69, 0x73, 0x20, 0x63, 0x6f, 0x72, 0x72, 0x75,
+    0x70, 0x74, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x00, 0x00, 0x00,
+];
+
+//
+// Offsets of fields in the EXEPACK block.
+//
+const OFF_REAL_IP: usize = 0x00;
+const OFF_REAL_CS: usize = 0x02;
+const OFF_EXEPACK_SIZE: usize = 0x06;
+const OFF_REAL_SP: usize = 0x08;
+const OFF_REAL_SS: usize = 0x0a;
+const OFF_DEST_LEN: usize = 0x0c;
+const OFF_MOVE_PARA: usize = 0x155;
+const OFF_SHIFT: usize = 0x157;
+
+/// Where the unpacker starts, relative to the EXEPACK block.
+///
+const UNPACKER_ENTRY: u16 = 0x12;
+
+/// The size of the unpacker's stack, which is just above the moved EXEPACK block.
+///
+const UNPACKER_STACK: usize = 0x100;
+
+const FILL: u8 = 0xb0;
+const COPY: u8 = 0xb2;
+
+/// Runs shorter than this are left in copy commands, as a fill command would not save
+/// enough to pay for breaking the copy in two.
+///
+const MIN_FILL: usize = 8;
+
+/// The most bytes one command may produce. The unpacker normalizes its pointers before
+/// each command to have an offset of at least FFF0H, so that a command can never wrap
+/// an offset.
+///
+const MAX_COMMAND: usize = 0xff00;
+
+const PARA_SIZE: usize = 16;
+
+/// A program after packing, with what the MZ header needs to start it.
+///
+pub struct Packed {
+    pub image: Vec<u8>,
+    pub entry: FarPtr,
+    pub stack: FarPtr,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+}
+
+fn paragraphs(bytes: usize) -> usize {
+    bytes.div_ceil(PARA_SIZE)
+}
+
+fn put_u16(data: &mut [u8], at: usize, value: u16) {
+    data[at..at+2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Run-length compress an image. The command for the start of the image, which is
+/// decoded last, is marked as the last command.
+///
+fn compress(image: &[u8]) -> Vec<u8> {
+    let mut commands = Vec::new();
+    let mut copy_start = 0;
+    let mut at = 0;
+
+    while at < image.len() {
+        let run = image[at..].iter().take(MAX_COMMAND).take_while(|b| **b == image[at]).count();
+
+        if run >= MIN_FILL {
+            commands.extend(image[copy_start..at].chunks(MAX_COMMAND).map(|chunk| (COPY, chunk)));
+            commands.push((FILL, &image[at..at+run]));
+            copy_start = at + run;
+        }
+
+        at += run;
+    }
+
+    commands.extend(image[copy_start..].chunks(MAX_COMMAND).map(|chunk| (COPY, chunk)));
+
+    let mut packed = Vec::new();
+
+    for (i, (command, data)) in commands.iter().enumerate() {
+        if *command == FILL {
+            packed.push(data[0]);
+        } else {
+            packed.extend_from_slice(data);
+        }
+
+        packed.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packed.push(if i == 0 { command | 1 } else { *command });
+    }
+
+    packed
+}
+
+/// Return how many bytes higher than its place the image must be decoded, so that
+/// decoding never writes over compressed data it has still to read. The compressed data
+/// is decoded from its end, and the part still to be read must stay below the part of
+/// the image written so far.
+///
+fn decode_slack(packed: &[u8], image_len: usize) -> usize {
+    let mut at = packed.len();
+    let mut read = 0isize;
+    let mut written = 0isize;
+    let mut most_ahead = 0isize;
+
+    while at > 0 && packed[at - 1] == 0xff {
+        at -= 1;
+        read += 1;
+    }
+
+    while at >= 3 {
+        let command = packed[at - 1];
+        let length = u16::from_le_bytes([packed[at - 3], packed[at - 2]]) as usize;
+        let input = 3 + if command & 0xfe == FILL { 1 } else { length };
+
+        at -= input;
+        read += input as isize;
+        written += length as isize;
+        most_ahead = std::cmp::max(most_ahead, written - read);
+    }
+
+    std::cmp::max(0, packed.len() as isize + most_ahead - image_len as isize) as usize
+}
+
+/// Pack the relocation table into EXEPACK's per-64K sections.
+///
+fn pack_relocations(relocs: &[Relocation]) -> Vec<u8> {
+    let mut sections = vec![Vec::new(); 16];
+
+    for reloc in relocs.iter() {
+        let linear = ((reloc.seg as usize) << 4) + reloc.offset as usize;
+        sections[linear >> 16].push((linear & 0xffff) as u16);
+    }
+
+    let mut table = Vec::new();
+
+    for section in sections.iter_mut() {
+        section.sort();
+        table.extend_from_slice(&(section.len() as u16).to_le_bytes());
+        for offset in section.iter() {
+            table.extend_from_slice(&offset.to_le_bytes());
+        }
+    }
+
+    table
+}
+
+/// Pack a program image, returning `None` if packing does not make it smaller.
+/// `min_alloc` and `max_alloc` are those of the unpacked program, in paragraphs
+/// beyond the image.
+///
+pub fn pack(image: &[u8], relocs: &[Relocation], entry: FarPtr, stack: FarPtr, min_alloc: u16, max_alloc: u16) -> Result<Option<Packed>, LinkerError> {
+    if image.is_empty() {
+        return Ok(None);
+    }
+
+    if relocs.iter().any(|reloc| ((reloc.seg as usize) << 4) + reloc.offset as usize >= 0x100000) {
+        return Err(LinkerError::new("relocation is outside of 1M."));
+    }
+
+    let dest_len = paragraphs(image.len());
+    let mut padded = image.to_vec();
+    padded.resize(dest_len * PARA_SIZE, 0);
+
+    let mut packed = compress(&padded);
+    let compressed_len = paragraphs(packed.len());
+    packed.resize(compressed_len * PARA_SIZE, 0xff);
+
+    let mut block = UNPACKER.to_vec();
+    block.extend_from_slice(&pack_relocations(relocs));
+    let block_len = paragraphs(block.len());
+
+    //
+    // The block moves to above the image as it is decoded, and must not overlap itself
+    // in the move.
+    //
+    let shift = paragraphs(decode_slack(&packed, dest_len * PARA_SIZE));
+    let move_para = std::cmp::max(shift + dest_len, compressed_len + block_len);
+
+    put_u16(&mut block, OFF_REAL_IP, entry.offset);
+    put_u16(&mut block, OFF_REAL_CS, entry.seg);
+    let block_size = block.len() as u16;
+    put_u16(&mut block, OFF_EXEPACK_SIZE, block_size);
+    put_u16(&mut block, OFF_REAL_SP, stack.offset);
+    put_u16(&mut block, OFF_REAL_SS, stack.seg);
+    put_u16(&mut block, OFF_DEST_LEN, dest_len as u16);
+    put_u16(&mut block, OFF_MOVE_PARA, move_para as u16);
+    put_u16(&mut block, OFF_SHIFT, shift as u16);
+
+    packed.extend_from_slice(&block);
+
+    if packed.len() >= image.len() + relocs.len() * 4 {
+        return Ok(None);
+    }
+
+    //
+    // Memory needed, in paragraphs from the load segment: as much as the program needs,
+    // and enough for the unpacker while it runs.
+    //
+    let stack_para = move_para + block_len;
+    let unpacker_needs = stack_para + paragraphs(UNPACKER_STACK);
+    let program_needs = paragraphs(image.len()) + min_alloc as usize;
+    let program_wants = paragraphs(image.len()) + max_alloc as usize;
+
+    let load_len = paragraphs(packed.len());
+    let needs = std::cmp::max(unpacker_needs, program_needs);
+
+    let new_min_alloc = needs - load_len;
+    if new_min_alloc > 0xffff || dest_len > 0xffff || move_para > 0xffff {
+        return Err(LinkerError::new("program is too large to pack."));
+    }
+
+    let new_max_alloc = std::cmp::min(0xffff, std::cmp::max(new_min_alloc, program_wants.saturating_sub(load_len)));
+
+    Ok(Some(Packed {
+        image: packed,
+        entry: FarPtr::new(compressed_len as u16, UNPACKER_ENTRY),
+        stack: FarPtr::new(stack_para as u16, UNPACKER_STACK as u16),
+        min_alloc: new_min_alloc as u16,
+        max_alloc: new_max_alloc as u16,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Decode compressed data the way the unpacker does, backwards from the end.
+    ///
+    fn decompress(packed: &[u8], len: usize) -> Vec<u8> {
+        let mut image = vec![0u8; len];
+        let mut src = packed.len();
+        let mut dst = len;
+
+        while packed[src - 1] == 0xff {
+            src -= 1;
+        }
+
+        loop {
+            let command = packed[src - 1];
+            let length = u16::from_le_bytes([packed[src - 3], packed[src - 2]]) as usize;
+            src -= 3;
+
+            match command & 0xfe {
+                FILL => {
+                    image[dst-length..dst].fill(packed[src - 1]);
+                    src -= 1;
+                },
+                COPY => {
+                    image[dst-length..dst].copy_from_slice(&packed[src-length..src]);
+                    src -= length;
+                },
+                _ => panic!("bad command {:02X}", command),
+            }
+
+            dst -= length;
+
+            if command & 1 != 0 {
+                break;
+            }
+        }
+
+        assert_eq!(src, 0);
+        assert_eq!(dst, 0);
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut image = Vec::new();
+        image.extend_from_slice(b"\xb8\x00\x00\x9a\x00\x00\x00\x00");
+        image.extend(std::iter::repeat_n(0, 100));
+        image.extend_from_slice(b"hello, world");
+        image.extend(std::iter::repeat_n(0x90, 0x1_2000));
+        image.extend_from_slice(b"\xff\xff\xff\xff\xff\xff");
+
+        let packed = compress(&image);
+
+        assert!(packed.len() < 100);
+        assert_eq!(decompress(&packed, image.len()), image);
+    }
+
+    #[test]
+    fn incompressible() {
+        let image = (0..0x1_1000).map(|i| (i * 7 + i / 256) as u8).collect::<Vec<u8>>();
+        let packed = compress(&image);
+        assert_eq!(decompress(&packed, image.len()), image);
+    }
+
+    #[test]
+    fn relocation_sections() {
+        let relocs = [
+            Relocation { seg: 0x1000, offset: 0x0002 },
+            Relocation { seg: 0x0000, offset: 0x0010 },
+            Relocation { seg: 0x0001, offset: 0x0000 },
+        ];
+
+        let table = pack_relocations(&relocs);
+
+        assert_eq!(table.len(), 2 * 16 + 2 * 3);
+        assert_eq!(&table[0..6], &[2, 0, 0x10, 0, 0x10, 0]);
+        assert_eq!(&table[6..10], &[1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn packs_header() -> Result<(), LinkerError> {
+        let image = vec![0u8; 0x4000];
+        let packed = pack(&image, &[], FarPtr::new(0x10, 0x20), FarPtr::new(0x300, 0x100), 0x10, 0xffff)?.unwrap();
+
+        //
+        // 4000H zeros compress to one fill command in one paragraph.
+        //
+        assert_eq!(&packed.image[0..5], &[0x00, 0x00, 0x40, 0xb1, 0xff]);
+        assert_eq!(packed.entry.seg, 1);
+        assert_eq!(packed.entry.offset, 0x12);
+
+        let block = &packed.image[0x10..];
+        assert_eq!(&block[0x00..0x04], &[0x20, 0x00, 0x10, 0x00]);
+        assert_eq!(&block[0x08..0x0e], &[0x00, 0x01, 0x00, 0x03, 0x00, 0x04]);
+        assert_eq!(&block[0x10..0x12], b"RB");
+        assert_eq!(&block[OFF_MOVE_PARA..OFF_MOVE_PARA+2], &[0x00, 0x04]);
+        assert_eq!(&block[OFF_SHIFT..OFF_SHIFT+2], &[0x00, 0x00]);
+
+        //
+        // The program needs 410H paragraphs, but the unpacker needs more: its block of
+        // 18H paragraphs at 400H, then its stack.
+        //
+        assert_eq!(packed.stack.seg, 0x418);
+        assert_eq!(packed.min_alloc as usize, 0x428 - paragraphs(packed.image.len()));
+
+        Ok(())
+    }
+}
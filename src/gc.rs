@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::comdat::ComdatHeader;
+use crate::linker_error::LinkerError;
+use crate::linkstate::LinkState;
+use crate::object::Object;
+use crate::pass2::{FrameType, TargetType, ThreadState};
+use crate::record::{Record, RecordType};
+use crate::segment::Combine;
+use crate::symbols::Symbol;
+
+//
+// Garbage collection of unreferenced segment contributions. Each SEGDEF of each module,
+// including those made for COMDATs, is a node, and each fixup in a SEGDEF's data is an
+// edge to the SEGDEF (or, through an external, the public) it targets. Everything not
+// reachable from the program's start address, its stack, debug information or symbols
+// named on the command line is discarded before the memory map is built.
+//
+// A fixup targeting a group may refer to anything in it, so it keeps the whole group.
+//
+
+/// A segment contribution: the index of a module in the object list, and of a SEGDEF
+/// in the module.
+///
+type Contribution = (usize, usize);
+
+/// Something a fixup or start address refers to.
+///
+enum Reference {
+    SegDef(usize),
+    Group(usize),
+    External(String),
+}
+
+/// A public symbol defined relative to a SEGDEF, with its offset in the SEGDEF. A local
+/// symbol is known only to the module which defines it.
+///
+struct SegDefPublic {
+    name: String,
+    segdef: usize,
+    offset: u16,
+    local: bool,
+}
+
+/// What garbage collection needs from one module's records.
+///
+#[derive(Default)]
+struct ModuleRefs {
+    publics: Vec<SegDefPublic>,
+    fixups: Vec<(usize, Reference)>,
+    start: Vec<Reference>,
+}
+
+/// The references a fixup makes: its target, and its frame unless that is a group or
+/// a fixed frame, which do not depend on the contents of any segment.
+///
+fn fixup_references(rec: &mut Record, threads: &ThreadState, obj: &Object) -> Result<Vec<Reference>, LinkerError> {
+    let fixup = threads.read_fixup_data(rec)?;
+    let mut refs = Vec::new();
+
+    let external = |index: usize| -> Result<Reference, LinkerError> {
+        if index == 0 || !obj.extdefs.is_valid_index(index) {
+            Err(LinkerError::new(&format!("invalid external index {} in fixup", index)))
+        } else {
+            Ok(Reference::External(obj.extdefs[index].clone()))
+        }
+    };
+
+    refs.push(match fixup.target_type {
+        TargetType::SEGDEF => Reference::SegDef(fixup.target_index),
+        TargetType::GRPDEF => {
+            if !obj.grpdefs.is_valid_index(fixup.target_index) {
+                return Err(LinkerError::new(&format!("invalid group index {} in fixup", fixup.target_index)));
+            }
+            Reference::Group(obj.grpdefs.get(fixup.target_index))
+        },
+        TargetType::EXTDEF => external(fixup.target_index)?,
+        TargetType::Frame => unreachable!(),
+    });
+
+    match fixup.frame_type {
+        FrameType::SEGDEF => refs.push(Reference::SegDef(fixup.frame_index)),
+        FrameType::EXTDEF => refs.push(external(fixup.frame_index)?),
+        _ => {},
+    }
+
+    Ok(refs)
+}
+
+/// Collect the publics, fixup references and start address of one module.
+///
+fn module_refs(data: &[u8], obj: &Object, state: &LinkState) -> Result<ModuleRefs, LinkerError> {
+    const IS_MAIN: u8 = 0x80;
+    const HAS_START: u8 = 0x40;
+
+    let mut refs = ModuleRefs::default();
+    let mut threads = ThreadState::new();
+    let mut segdef = 0;
+    let mut start = 0;
+
+    while start < data.len() {
+        let mut rec = Record::new(&data[start..])?;
+        let reclen = rec.total_length();
+
+        match rec.rectype {
+            RecordType::PUBDEF => {
+                let _group = rec.index()?;
+                let segment = rec.index()?;
+
+                if segment != 0 {
+                    while !rec.end() {
+                        let name = rec.counted_string()?;
+                        let offset = rec.word()?;
+                        rec.index()?;
+
+                        refs.publics.push(SegDefPublic { name, segdef: segment, offset, local: false });
+                    }
+                }
+            },
+            RecordType::LEDATA |
+            RecordType::LIDATA => segdef = rec.index()?,
+            RecordType::COMDAT |
+            RecordType::COMDAT32 => {
+                let header = ComdatHeader::read(&mut rec, obj, state)?;
+                segdef = obj.comdat_segdef(&header.name).unwrap_or(0);
+            },
+            RecordType::FIXUPP => {
+                while !rec.end() {
+                    let b0 = rec.byte()?;
+
+                    if (b0 & 0x80) == 0x00 {
+                        threads.read_thread(&mut rec, b0)?;
+                    } else {
+                        rec.byte()?;
+
+                        for reference in fixup_references(&mut rec, &threads, obj)? {
+                            refs.fixups.push((segdef, reference));
+                        }
+                    }
+                }
+            },
+            RecordType::MODEND => {
+                if !rec.end() {
+                    let modtype = rec.byte()?;
+
+                    if (modtype & IS_MAIN) != 0 && (modtype & HAS_START) != 0 {
+                        refs.start = fixup_references(&mut rec, &threads, obj)?;
+                    }
+                }
+                break;
+            },
+            _ => {},
+        }
+
+        start += reclen;
+    }
+
+    //
+    // Each COMDAT kept from the module is a public at the start of its own SEGDEF.
+    //
+    for comdat in obj.comdats.iter() {
+        if let Some(segdef) = comdat.segdef {
+            refs.publics.push(SegDefPublic { name: comdat.name.clone(), segdef, offset: 0, local: comdat.local });
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Discard the segment contributions the program cannot reach, and lay out what is left
+/// of each segment again. Returns the number of bytes removed.
+///
+pub fn collect_garbage(state: &mut LinkState, objects: &mut [Object], roots: &[String]) -> Result<usize, LinkerError> {
+    let mut modules = Vec::new();
+
+    for obj in objects.iter() {
+        let refs = module_refs(obj.data.as_ref().unwrap(), obj, state)
+            .map_err(|err| LinkerError::new(&format!("gc: module {}: {}", obj.name, err)))?;
+        modules.push(refs);
+    }
+
+    let mut publics = HashMap::new();
+    let mut locals = HashMap::new();
+
+    for (objidx, refs) in modules.iter().enumerate() {
+        for public in refs.publics.iter() {
+            if public.local {
+                locals.insert((objidx, public.name.clone()), public.segdef);
+            } else {
+                publics.insert(public.name.clone(), (objidx, public.segdef));
+            }
+        }
+    }
+
+    //
+    // Each module's fixups, by the SEGDEF whose data they are in.
+    //
+    let mut edges: HashMap<Contribution, Vec<&Reference>> = HashMap::new();
+    let mut live = HashSet::new();
+    let mut work: Vec<Contribution> = Vec::new();
+
+    for (objidx, refs) in modules.iter().enumerate() {
+        for (segdef, reference) in refs.fixups.iter() {
+            edges.entry((objidx, *segdef)).or_default().push(reference);
+        }
+    }
+
+    //
+    // The roots: the start address, the stack, debug information, and symbols named on
+    // the command line.
+    //
+    let resolve = |objidx: usize, reference: &Reference, work: &mut Vec<Contribution>| {
+        match reference {
+            Reference::SegDef(segdef) => work.push((objidx, *segdef)),
+            Reference::Group(group) => {
+                for (objidx, obj) in objects.iter().enumerate() {
+                    for segdef in 1..=obj.segdefs.len() {
+                        if state.groups[*group].has(obj.segdefs[segdef].segidx) {
+                            work.push((objidx, segdef));
+                        }
+                    }
+                }
+            },
+            Reference::External(name) => {
+                if let Some(segdef) = locals.get(&(objidx, name.clone())) {
+                    work.push((objidx, *segdef));
+                } else if let Some(contribution) = publics.get(name) {
+                    work.push(*contribution);
+                }
+            },
+        }
+    };
+
+    for (objidx, refs) in modules.iter().enumerate() {
+        for reference in refs.start.iter() {
+            resolve(objidx, reference, &mut work);
+        }
+    }
+
+    for (objidx, obj) in objects.iter().enumerate() {
+        for segdef in 1..=obj.segdefs.len() {
+            let segment = &state.segments[obj.segdefs[segdef].segidx];
+
+            if segment.combine == Combine::Stack || segment.debug.is_some() {
+                work.push((objidx, segdef));
+            }
+        }
+    }
+
+    for root in roots {
+        match (publics.get(root), state.symbols.symbols.get(root)) {
+            (Some(contribution), _) => work.push(*contribution),
+            (None, Some(Symbol::Public(_))) => {},
+            _ => return Err(LinkerError::new(&format!("gc root {} is not a public symbol.", root))),
+        }
+    }
+
+    while let Some((objidx, segdef)) = work.pop() {
+        if !live.insert((objidx, segdef)) {
+            continue;
+        }
+
+        for reference in edges.get(&(objidx, segdef)).into_iter().flatten() {
+            resolve(objidx, reference, &mut work);
+        }
+    }
+
+    //
+    // Lay out the live contributions of each segment again, in their original order.
+    //
+    let lengths = state.segments.iter().map(|segment| segment.length).collect::<Vec<_>>();
+    let mut old_bases = Vec::new();
+
+    for segidx in 1..=state.segments.len() {
+        if state.segments[segidx].debug.is_none() {
+            state.segments[segidx].length = 0;
+        }
+    }
+
+    for (objidx, obj) in objects.iter_mut().enumerate() {
+        let mut bases = vec![0];
+
+        for segdef in 1..=obj.segdefs.len() {
+            let contribution = &mut obj.segdefs[segdef];
+            let segment = &mut state.segments[contribution.segidx];
+            bases.push(contribution.base);
+
+            if segment.debug.is_some() {
+                continue;
+            }
+
+            if live.contains(&(objidx, segdef)) {
+                contribution.base = segment.add_segdef(contribution)?;
+            } else {
+                contribution.discarded = true;
+            }
+        }
+
+        old_bases.push(bases);
+    }
+
+    //
+    // Move the publics and line numbers of live contributions with them, and forget
+    // those of discarded ones.
+    //
+    for (objidx, refs) in modules.iter().enumerate() {
+        for public in refs.publics.iter() {
+            let obj = &mut objects[objidx];
+            let segdef = &obj.segdefs[public.segdef];
+            let symbols = if public.local { &mut obj.local_symbols } else { &mut state.symbols.symbols };
+
+            if segdef.discarded {
+                symbols.remove(&public.name);
+            } else if let Some(Symbol::Public(symbol)) = symbols.get_mut(&public.name) {
+                symbol.offset = (segdef.base + public.offset as usize) as u16;
+            }
+        }
+    }
+
+    for (objidx, obj) in objects.iter_mut().enumerate() {
+        let segdefs = &obj.segdefs;
+        let bases = &old_bases[objidx];
+
+        for linnums in obj.linnums.iter_mut() {
+            linnums.lines.retain_mut(|line| {
+                let segdef = (1..=segdefs.len()).find(|segdef| {
+                    let old = bases[*segdef];
+                    segdefs[*segdef].segidx == linnums.segidx && line.offset >= old && line.offset <= old + segdefs[*segdef].length
+                });
+
+                match segdef {
+                    Some(segdef) if !segdefs[segdef].discarded => {
+                        line.offset = line.offset - bases[segdef] + segdefs[segdef].base;
+                        true
+                    },
+                    _ => false,
+                }
+            });
+        }
+    }
+
+    let removed = state.segments.iter()
+        .zip(lengths)
+        .map(|(segment, length)| length - segment.length)
+        .sum();
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pass1::pass1_object;
+    use crate::prescan::ModuleScan;
+
+    //
+    // Two modules: MAIN, whose code calls USED in LIB's code. LIB's data, which holds
+    // UNUSED, is never referred to.
+    //
+    fn main_module() -> Vec<u8> {
+        vec![
+            // LNAMES "", "_TEXT", "CODE"
+            0x96, 0x0d, 0x00, 0x00, 0x05, b'_', b'T', b'E', b'X', b'T', 0x04, b'C', b'O', b'D', b'E', 0x00,
+            // SEGDEF _TEXT CODE, byte aligned public, 5 bytes
+            0x98, 0x07, 0x00, 0x28, 0x05, 0x00, 0x02, 0x03, 0x01, 0x00,
+            // EXTDEF USED
+            0x8c, 0x07, 0x00, 0x04, b'U', b'S', b'E', b'D', 0x00, 0x00,
+            // LEDATA _TEXT: call near USED; ret; ret
+            0xa0, 0x09, 0x00, 0x01, 0x00, 0x00, 0xe8, 0x00, 0x00, 0xc3, 0xc3, 0x00,
+            // FIXUPP self-relative offset at 1, frame target, target EXTDEF 1
+            0x9c, 0x05, 0x00, 0x84, 0x01, 0x56, 0x01, 0x00,
+            // MODEND main, start at _TEXT:0
+            0x8a, 0x06, 0x00, 0xc1, 0x50, 0x01, 0x00, 0x00, 0x00,
+        ]
+    }
+
+    fn lib_module() -> Vec<u8> {
+        vec![
+            // LNAMES "", "_TEXT", "CODE", "_DATA", "DATA"
+            0x96, 0x18, 0x00, 0x00, 0x05, b'_', b'T', b'E', b'X', b'T', 0x04, b'C', b'O', b'D', b'E',
+            0x05, b'_', b'D', b'A', b'T', b'A', 0x04, b'D', b'A', b'T', b'A', 0x00,
+            // SEGDEF _TEXT CODE, 2 bytes; SEGDEF _DATA DATA, 16 bytes
+            0x98, 0x07, 0x00, 0x28, 0x02, 0x00, 0x02, 0x03, 0x01, 0x00,
+            0x98, 0x07, 0x00, 0x28, 0x10, 0x00, 0x04, 0x05, 0x01, 0x00,
+            // PUBDEF USED at _TEXT:0; PUBDEF UNUSED at _DATA:4
+            0x90, 0x0b, 0x00, 0x00, 0x01, 0x04, b'U', b'S', b'E', b'D', 0x00, 0x00, 0x00, 0x00,
+            0x90, 0x0d, 0x00, 0x00, 0x02, 0x06, b'U', b'N', b'U', b'S', b'E', b'D', 0x04, 0x00, 0x00, 0x00,
+            // LEDATA _TEXT: ret; ret
+            0xa0, 0x06, 0x00, 0x01, 0x00, 0x00, 0xc3, 0xc3, 0x00,
+            // MODEND
+            0x8a, 0x02, 0x00, 0x00, 0x00,
+        ]
+    }
+
+    #[test]
+    fn unreferenced_contributions() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let mut objects = Vec::new();
+
+        for data in [main_module(), lib_module()] {
+            let mut obj = Object::from_bytes(data.clone());
+            pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, "test")?;
+            objects.push(obj);
+        }
+
+        let removed = collect_garbage(&mut state, &mut objects, &[])?;
+
+        assert_eq!(removed, 0x10);
+        assert!(!objects[1].segdefs[1].discarded);
+        assert!(objects[1].segdefs[2].discarded);
+        assert_eq!(state.segments[objects[1].segdefs[2].segidx].length, 0);
+        assert!(!state.symbols.symbols.contains_key("UNUSED"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roots() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let mut objects = Vec::new();
+
+        for data in [main_module(), lib_module()] {
+            let mut obj = Object::from_bytes(data.clone());
+            pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, "test")?;
+            objects.push(obj);
+        }
+
+        assert_eq!(collect_garbage(&mut state, &mut objects, &["UNUSED".to_owned()])?, 0);
+        assert!(!objects[1].segdefs[2].discarded);
+
+        assert!(collect_garbage(&mut state, &mut objects, &["MISSING".to_owned()])
+            .is_err_and(|err| err.to_string() == "gc root MISSING is not a public symbol."));
+
+        Ok(())
+    }
+}
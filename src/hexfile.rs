@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::Path;
+
+use crate::linker_error::LinkerError;
+use crate::linkstate::FarPtr;
+
+//
+// Intel HEX and Motorola S-record files, as read by EPROM programmers. Both are text,
+// one record per line, each a record type, a length, an address, data and a checksum
+// in hex. Lines end in CR LF, as the tools which read them expect.
+//
+// Intel HEX addresses are 16-bit offsets from the segment given by the last extended
+// segment address record, as in the 8086's own addressing. S-records hold 24-bit linear
+// addresses.
+//
+
+/// The number of data bytes in each record.
+///
+const RECORD_DATA: usize = 16;
+
+const IHEX_DATA: u8 = 0x00;
+const IHEX_EOF: u8 = 0x01;
+const IHEX_SEGMENT: u8 = 0x02;
+const IHEX_START: u8 = 0x03;
+
+fn ihex_record(out: &mut String, rectype: u8, offset: u16, data: &[u8]) {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&offset.to_be_bytes());
+    record.push(rectype);
+    record.extend_from_slice(data);
+
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(sum.wrapping_neg());
+
+    out.push(':');
+    record.iter().for_each(|byte| out.push_str(&format!("{:02X}", byte)));
+    out.push_str("\r\n");
+}
+
+/// Build an Intel HEX file for an image loaded at `base_segment`, starting at `entry`
+/// (relative to the image) if given.
+///
+pub fn intel_hex(image: &[u8], base_segment: u16, entry: Option<FarPtr>) -> Result<String, LinkerError> {
+    let mut out = String::new();
+    let mut segment = None;
+
+    //
+    // Each 64K of the image is addressed from its own segment.
+    //
+    for (i, data) in image.chunks(RECORD_DATA).enumerate() {
+        let at = i * RECORD_DATA;
+        let seg = base_segment as usize + ((at >> 16) << 12);
+
+        if seg > 0xffff {
+            return Err(LinkerError::new(&format!("image is outside 1M with base segment {:04X}H.", base_segment)));
+        }
+
+        if segment != Some(seg) {
+            ihex_record(&mut out, IHEX_SEGMENT, 0, &(seg as u16).to_be_bytes());
+            segment = Some(seg);
+        }
+
+        ihex_record(&mut out, IHEX_DATA, (at & 0xffff) as u16, data);
+    }
+
+    if let Some(entry) = entry {
+        let mut start = entry.seg.wrapping_add(base_segment).to_be_bytes().to_vec();
+        start.extend_from_slice(&entry.offset.to_be_bytes());
+        ihex_record(&mut out, IHEX_START, 0, &start);
+    }
+
+    ihex_record(&mut out, IHEX_EOF, 0, &[]);
+
+    Ok(out)
+}
+
+fn srecord(out: &mut String, rectype: char, address: &[u8], data: &[u8]) {
+    let mut record = vec![(address.len() + data.len() + 1) as u8];
+    record.extend_from_slice(address);
+    record.extend_from_slice(data);
+
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(!sum);
+
+    out.push('S');
+    out.push(rectype);
+    record.iter().for_each(|byte| out.push_str(&format!("{:02X}", byte)));
+    out.push_str("\r\n");
+}
+
+/// The low 24 bits of a linear address, as S2 and S8 records hold it.
+///
+fn address24(address: usize) -> [u8; 3] {
+    let bytes = (address as u32).to_be_bytes();
+    [bytes[1], bytes[2], bytes[3]]
+}
+
+/// Build an S-record file named `name` for an image loaded at `base_segment`, starting
+/// at `entry` (relative to the image) if given.
+///
+pub fn srecords(image: &[u8], base_segment: u16, entry: Option<FarPtr>, name: &str) -> Result<String, LinkerError> {
+    let mut out = String::new();
+    let base = (base_segment as usize) << 4;
+
+    if base + image.len() > 0x100000 {
+        return Err(LinkerError::new(&format!("image is outside 1M with base segment {:04X}H.", base_segment)));
+    }
+
+    srecord(&mut out, '0', &[0, 0], name.as_bytes());
+
+    let mut count = 0;
+
+    for (i, data) in image.chunks(RECORD_DATA).enumerate() {
+        srecord(&mut out, '2', &address24(base + i * RECORD_DATA), data);
+        count += 1;
+    }
+
+    if count <= 0xffff {
+        srecord(&mut out, '5', &(count as u16).to_be_bytes(), &[]);
+    }
+
+    let start = entry.map(|entry| ((entry.seg.wrapping_add(base_segment) as usize) << 4) + entry.offset as usize).unwrap_or(0);
+    srecord(&mut out, '8', &address24(start), &[]);
+
+    Ok(out)
+}
+
+/// Write an Intel HEX file.
+///
+pub fn write_intel_hex(path: &Path, image: &[u8], base_segment: u16, entry: Option<FarPtr>) -> Result<(), LinkerError> {
+    fs::write(path, intel_hex(image, base_segment, entry)?)?;
+    Ok(())
+}
+
+/// Write an S-record file, with the output's name in its header.
+///
+pub fn write_srecords(path: &Path, image: &[u8], base_segment: u16, entry: Option<FarPtr>) -> Result<(), LinkerError> {
+    let name = path.file_stem().map(|stem| stem.to_string_lossy().to_uppercase()).unwrap_or_default();
+
+    fs::write(path, srecords(image, base_segment, entry, &name)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intel_hex_segments() -> Result<(), LinkerError> {
+        let mut image = vec![0u8; 0x10010];
+        image[0] = 0xea;
+        image[0x10000] = 0x55;
+
+        let hex = intel_hex(&image, 0x1000, Some(FarPtr::new(0, 0)))?;
+        let lines = hex.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], ":020000021000EC");
+        assert_eq!(lines[1], ":10000000EA00000000000000000000000000000006");
+        assert_eq!(lines[0x1001], ":020000022000DC");
+        assert_eq!(lines[0x1002], ":10000000550000000000000000000000000000009B");
+        assert_eq!(lines[0x1003], ":0400000310000000E9");
+        assert_eq!(lines[0x1004], ":00000001FF");
+
+        assert!(intel_hex(&image, 0xffff, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn srecord_lines() -> Result<(), LinkerError> {
+        let image = [0x01, 0x02, 0x03];
+        let srec = srecords(&image, 0x1000, Some(FarPtr::new(0, 2)), "ROM")?;
+
+        assert_eq!(srec, "S0060000524F4D0B\r\nS207010000010203F1\r\nS5030001FB\r\nS804010002F8\r\n");
+
+        Ok(())
+    }
+}
@@ -149,7 +149,7 @@ impl Library {
             return Err(LinkerError::new(&format!("page {} in library {} is not a module (missing THEADR)", modpage, self.name)));
         }
 
-        let _modname = match header.counted_string() {
+        let modname = match header.counted_string() {
             Ok(name) => name,
             Err(err) => return Err(LinkerError::new(&format!("page {} in library {} is not a module: {}", modpage, self.name, err))),
         };
@@ -172,7 +172,11 @@ impl Library {
 
         let contents = self.data[modstart..modend].to_vec();
 
-        Ok(Object::from_bytes(contents))
+        let mut obj = Object::from_bytes(contents);
+        obj.name = modname;
+        obj.library = Some(self.name.clone());
+
+        Ok(obj)
     }
 
     /// Compute the hash for a symbol. Libraries use a two-level hashing scheme -
@@ -0,0 +1,638 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::linker_error::LinkerError;
+use crate::linkstate::{FarPtr, LinkState};
+use crate::object::Object;
+use crate::segment::{Align, Combine};
+use crate::symbols::Symbol;
+
+//
+// The link map. All map formats are rendered from a single `LinkMap`, which is
+// built from the link state, so that they can never disagree with each other.
+//
+
+/// The part of a segment contributed by one object module's SEGDEF.
+///
+pub struct MapContribution {
+    pub module: String,
+    pub frame: usize,
+    pub offset: usize,
+    pub linear: usize,
+    pub length: usize,
+    pub acbp: u8,
+}
+
+/// A segment in the memory map.
+///
+pub struct MapSegment {
+    pub name: String,
+    pub class: String,
+    pub group: Option<String>,
+    pub base: usize,
+    pub length: usize,
+    pub align: Align,
+    pub combine: Combine,
+    pub contributions: Vec<MapContribution>,
+}
+
+/// A group, with its segments in memory order.
+///
+pub struct MapGroup {
+    pub name: String,
+    pub base: usize,
+    pub segments: Vec<String>,
+}
+
+/// A public symbol and its final address. Absolute symbols are not relative to
+/// any segment.
+///
+pub struct MapPublic {
+    pub name: String,
+    pub frame: usize,
+    pub offset: usize,
+    pub linear: usize,
+    pub used: bool,
+    pub absolute: bool,
+}
+
+/// A module pulled in from a library, and why.
+///
+pub struct MapLibraryModule {
+    pub module: String,
+    pub symbol: String,
+    pub referrer: String,
+}
+
+/// A source line and the address of its code.
+///
+pub struct MapLine {
+    pub line: u16,
+    pub frame: usize,
+    pub offset: usize,
+}
+
+/// The line numbers from one module for one segment.
+///
+pub struct MapLineNumbers {
+    pub file: String,
+    pub source: String,
+    pub segment: String,
+    pub lines: Vec<MapLine>,
+}
+
+/// The layout of the text link map.
+///
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MapStyle {
+    /// Borland TLINK
+    Tlink,
+    /// Microsoft LINK, as read by MAPSYM and SYMDEB
+    Ms,
+}
+
+pub struct LinkMap {
+    pub segments: Vec<MapSegment>,
+    pub groups: Vec<MapGroup>,
+    pub publics: Vec<MapPublic>,
+    pub library_modules: Vec<MapLibraryModule>,
+    pub line_numbers: Vec<MapLineNumbers>,
+    pub entry: Option<FarPtr>,
+    pub stack: Option<FarPtr>,
+    pub relocations: usize,
+    pub far_calls: Option<usize>,
+}
+
+/// Split a linear address into frame:offset relative to `base`, the linear base of
+/// the containing segment or group.
+///
+fn frame_offset(base: usize, linear: usize) -> (usize, usize) {
+    (base >> 4, (base & 0x000f) + (linear - base))
+}
+
+impl LinkMap {
+    /// Gather everything the map formats need from the link state.
+    ///
+    pub fn new(state: &LinkState, objects: &[Object]) -> LinkMap {
+        let mut segments = Vec::new();
+
+        for segidx in state.segment_order.iter() {
+            let seg = &state.segments[*segidx];
+            let group = if seg.group != 0 { Some(state.lnames.get(state.groups[seg.group].name).to_owned()) } else { None };
+            let base = if seg.group == 0 { state.segment_frame_base(*segidx) } else { state.groups[seg.group].base };
+
+            let mut contributions = Vec::new();
+
+            for obj in objects.iter() {
+                for segdef in obj.segdefs.iter().filter(|segdef| segdef.segidx == *segidx && !segdef.discarded) {
+                    let linear = seg.base + segdef.base;
+                    let (frame, offset) = frame_offset(base, linear);
+
+                    contributions.push(MapContribution {
+                        module: obj.name.to_uppercase(),
+                        frame,
+                        offset,
+                        linear,
+                        length: segdef.length,
+                        acbp: segdef.acbp,
+                    });
+                }
+            }
+
+            segments.push(MapSegment {
+                name: state.lnames.get(seg.name.nameidx).to_owned(),
+                class: state.lnames.get(seg.name.classidx).to_owned(),
+                group,
+                base: seg.base,
+                length: seg.length,
+                align: seg.align,
+                combine: seg.combine,
+                contributions,
+            });
+        }
+
+        let mut groups = Vec::new();
+
+        for group in state.groups.iter() {
+            let mut members = group.iter().collect::<Vec<usize>>();
+            members.sort_by_key(|segidx| state.segments[*segidx].base);
+
+            groups.push(MapGroup {
+                name: state.lnames.get(group.name).to_owned(),
+                base: group.base,
+                segments: members.iter().map(|segidx| state.lnames.get(state.segments[*segidx].name.nameidx).to_owned()).collect(),
+            });
+        }
+
+        let mut names = state.symbols.symbols.keys().collect::<Vec<&String>>();
+        names.sort();
+
+        let mut publics = Vec::new();
+
+        for name in names {
+            let (frame, offset, used, absolute) = match state.symbols.symbols.get(name).unwrap() {
+                Symbol::Public(p) => {
+                    let (frame, offset) = if p.segment != 0 {
+                        let linear = state.segments[p.segment].base + p.offset as usize;
+
+                        let base = if p.group != 0 {
+                            state.groups[p.group].base
+                        } else {
+                            state.segment_frame_base(p.segment)
+                        };
+
+                        frame_offset(base, linear)
+                    } else {
+                        (p.frame as usize, p.offset as usize)
+                    };
+
+                    (frame, offset, p.used, p.segment == 0)
+                },
+                Symbol::_Common(_c) => (0, 0, true, false),
+                _ => continue,
+            };
+
+            publics.push(MapPublic {
+                name: name.to_owned(),
+                frame,
+                offset,
+                linear: (frame << 4) + offset,
+                used,
+                absolute,
+            });
+        }
+
+        let library_modules = objects
+            .iter()
+            .filter_map(|obj| obj.linked_by.as_ref().map(|why| MapLibraryModule {
+                module: obj.display_name(),
+                symbol: why.symbol.clone(),
+                referrer: objects[why.referrer].display_name(),
+            }))
+            .collect();
+
+        let mut line_numbers = Vec::new();
+
+        for obj in objects.iter() {
+            for linnums in obj.linnums.iter() {
+                let seg = &state.segments[linnums.segidx];
+                let base = if linnums.group != 0 { state.groups[linnums.group].base } else { state.segment_frame_base(linnums.segidx) };
+
+                let lines = linnums.lines.iter().map(|line| {
+                    let (frame, offset) = frame_offset(base, seg.base + line.offset);
+                    MapLine { line: line.line, frame, offset }
+                }).collect();
+
+                line_numbers.push(MapLineNumbers {
+                    file: obj.source_file().to_owned(),
+                    source: obj.line_source(linnums).to_owned(),
+                    segment: state.lnames.get(seg.name.nameidx).to_owned(),
+                    lines,
+                });
+            }
+        }
+
+        LinkMap {
+            segments,
+            groups,
+            publics,
+            library_modules,
+            line_numbers,
+            entry: state.entry,
+            stack: state.stack,
+            relocations: state.relocations,
+            far_calls: state.far_calls,
+        }
+    }
+
+    /// Public symbols ordered by address rather than by name.
+    ///
+    pub fn publics_by_value(&self) -> Vec<&MapPublic> {
+        let mut byvalue = self.publics.iter().collect::<Vec<&MapPublic>>();
+        byvalue.sort_by_key(|sym| sym.linear);
+        byvalue
+    }
+}
+
+/// For each module pulled from a library, write the chain of symbol references which
+/// caused it to be linked, back to an object from the command line.
+///
+pub fn write_why_linked(fp: &mut impl Write, objects: &[Object]) -> Result<(), LinkerError> {
+    for obj in objects.iter().filter(|obj| obj.linked_by.is_some()) {
+        writeln!(fp, " {}", obj.display_name())?;
+
+        let mut reason = &obj.linked_by;
+
+        while let Some(why) = reason {
+            let referrer = &objects[why.referrer];
+            writeln!(fp, "     {:24} <- {}", why.symbol, referrer.display_name())?;
+            reason = &referrer.linked_by;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the line number sections, four lines to a row as MS LINK /LI does.
+///
+fn write_line_numbers(fp: &mut impl Write, map: &LinkMap) -> Result<(), LinkerError> {
+    for linnums in map.line_numbers.iter() {
+        writeln!(fp, "\nLine numbers for {}({}) segment {}\n", linnums.file, linnums.source, linnums.segment)?;
+
+        for row in linnums.lines.chunks(4) {
+            let row = row.iter().map(|line| format!("{:6} {:04X}:{:04X}", line.line, line.frame, line.offset)).collect::<Vec<String>>();
+            writeln!(fp, "{}", row.join(""))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the text link map in the requested style.
+///
+pub fn write_linkmap(path: &PathBuf, map: &LinkMap, objects: &[Object], style: MapStyle) -> Result<(), LinkerError> {
+    let mut fp = File::create(path)?;
+
+    match style {
+        MapStyle::Tlink => write_linkmap_tlink(&mut fp, map, objects),
+        MapStyle::Ms => write_linkmap_ms(&mut fp, map),
+    }
+}
+
+/// Write the text link map in the style of TLINK.
+///
+fn write_linkmap_tlink(fp: &mut impl Write, map: &LinkMap, objects: &[Object]) -> Result<(), LinkerError> {
+
+    writeln!(fp, "\n Start  Stop   Length Name               Class\n")?;
+    for seg in map.segments.iter() {
+        writeln!(fp, " {:05X}H {:05X}H {:05X}H {:18} {}",
+            seg.base,
+            if seg.length == 0 { seg.base } else { seg.base + seg.length - 1},
+            seg.length,
+            seg.name,
+            seg.class)?;
+    }
+
+    writeln!(fp, "\n\nDetailed map of segments\n")?;
+
+    for seg in map.segments.iter() {
+        let grp = seg.group.as_deref().unwrap_or("(none)");
+
+        for contrib in seg.contributions.iter() {
+            writeln!(fp, " {:04X}:{:04X} {:04X} C={:6} S={:14} G={:7} M={:10} ACBP={:02X}",
+                contrib.frame, contrib.offset,
+                contrib.length,
+                seg.class,
+                seg.name,
+                grp,
+                contrib.module,
+                contrib.acbp
+            )?;
+        }
+    }
+
+    writeln!(fp, "\n  Address         Publics by Name\n")?;
+
+    for sym in map.publics.iter() {
+        let used = if sym.used { "    " } else { "idle" };
+        writeln!(fp, " {:04X}:{:04X} {used}  {}", sym.frame, sym.offset, sym.name.to_uppercase())?;
+    }
+
+    writeln!(fp, "\n  Address         Publics by Value\n")?;
+
+    for sym in map.publics_by_value() {
+        let used = if sym.used { "    " } else { "idle" };
+        writeln!(fp, " {:04X}:{:04X} {used}  {}", sym.frame, sym.offset, sym.name.to_uppercase())?;
+    }
+
+    write_line_numbers(fp, map)?;
+
+    if let Some(entry) = &map.entry {
+        writeln!(fp, "\nProgram entry point at {:04X}:{:04X}", entry.seg, entry.offset)?;
+    }
+
+    if let Some(far_calls) = map.far_calls {
+        writeln!(fp, "\n{} far calls translated to near calls.", far_calls)?;
+    }
+
+    if !map.library_modules.is_empty() {
+        writeln!(fp, "\n  Library modules linked\n")?;
+        write_why_linked(fp, objects)?;
+    }
+
+    Ok(())
+}
+
+/// Write the text link map in the layout of Microsoft LINK, which is what MAPSYM and
+/// other Microsoft symbol tools expect.
+///
+fn write_linkmap_ms(fp: &mut impl Write, map: &LinkMap) -> Result<(), LinkerError> {
+    writeln!(fp, "\n Start  Stop   Length Name                   Class")?;
+    for seg in map.segments.iter() {
+        writeln!(fp, " {:05X}H {:05X}H {:05X}H {:22} {}",
+            seg.base,
+            if seg.length == 0 { seg.base } else { seg.base + seg.length - 1},
+            seg.length,
+            seg.name,
+            seg.class)?;
+    }
+
+    if !map.groups.is_empty() {
+        writeln!(fp, "\n Origin   Group")?;
+        for grp in map.groups.iter() {
+            writeln!(fp, " {:04X}:{:X}   {}", grp.base >> 4, grp.base & 0x000f, grp.name)?;
+        }
+    }
+
+    writeln!(fp, "\n  Address         Publics by Name\n")?;
+
+    for sym in map.publics.iter() {
+        let abs = if sym.absolute { "Abs" } else { "   " };
+        writeln!(fp, " {:04X}:{:04X}  {abs}  {}", sym.frame, sym.offset, sym.name)?;
+    }
+
+    writeln!(fp, "\n  Address         Publics by Value\n")?;
+
+    for sym in map.publics_by_value() {
+        let abs = if sym.absolute { "Abs" } else { "   " };
+        writeln!(fp, " {:04X}:{:04X}  {abs}  {}", sym.frame, sym.offset, sym.name)?;
+    }
+
+    write_line_numbers(fp, map)?;
+
+    if let Some(entry) = &map.entry {
+        writeln!(fp, "\nProgram entry point at {:04X}:{:04X}", entry.seg, entry.offset)?;
+    }
+
+    if let Some(far_calls) = map.far_calls {
+        writeln!(fp, "\n{} far calls translated to near calls.", far_calls)?;
+    }
+
+    Ok(())
+}
+
+/// Quote a string for JSON.
+///
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Format an optional far pointer for JSON.
+///
+fn json_far_ptr(ptr: &Option<FarPtr>) -> String {
+    match ptr {
+        Some(ptr) => format!("{{ \"segment\": {}, \"offset\": {} }}", ptr.seg, ptr.offset),
+        None => "null".to_owned(),
+    }
+}
+
+/// Render the link map as JSON.
+///
+pub fn linkmap_json(map: &LinkMap) -> String {
+    let mut json = String::from("{\n");
+
+    let segments = map.segments.iter().map(|seg| {
+        let contributions = seg.contributions.iter().map(|contrib| format!(
+            "        {{ \"module\": {}, \"frame\": {}, \"offset\": {}, \"linear\": {}, \"length\": {}, \"acbp\": {} }}",
+            json_string(&contrib.module),
+            contrib.frame,
+            contrib.offset,
+            contrib.linear,
+            contrib.length,
+            contrib.acbp,
+        )).collect::<Vec<String>>();
+
+        format!(
+            "    {{\n      \"name\": {},\n      \"class\": {},\n      \"group\": {},\n      \"base\": {},\n      \"length\": {},\n      \"align\": {},\n      \"combine\": {},\n      \"contributions\": [\n{}\n      ]\n    }}",
+            json_string(&seg.name),
+            json_string(&seg.class),
+            seg.group.as_deref().map(json_string).unwrap_or_else(|| "null".to_owned()),
+            seg.base,
+            seg.length,
+            json_string(&format!("{:?}", seg.align).to_lowercase()),
+            json_string(&format!("{:?}", seg.combine).to_lowercase()),
+            contributions.join(",\n"),
+        )
+    }).collect::<Vec<String>>();
+
+    json.push_str(&format!("  \"segments\": [\n{}\n  ],\n", segments.join(",\n")));
+
+    let groups = map.groups.iter().map(|grp| format!(
+        "    {{ \"name\": {}, \"base\": {}, \"segments\": [{}] }}",
+        json_string(&grp.name),
+        grp.base,
+        grp.segments.iter().map(|seg| json_string(seg)).collect::<Vec<String>>().join(", "),
+    )).collect::<Vec<String>>();
+
+    json.push_str(&format!("  \"groups\": [\n{}\n  ],\n", groups.join(",\n")));
+
+    let publics = map.publics.iter().map(|sym| format!(
+        "    {{ \"name\": {}, \"frame\": {}, \"offset\": {}, \"linear\": {}, \"used\": {}, \"absolute\": {} }}",
+        json_string(&sym.name),
+        sym.frame,
+        sym.offset,
+        sym.linear,
+        sym.used,
+        sym.absolute,
+    )).collect::<Vec<String>>();
+
+    json.push_str(&format!("  \"publics\": [\n{}\n  ],\n", publics.join(",\n")));
+
+    let library_modules = map.library_modules.iter().map(|module| format!(
+        "    {{ \"module\": {}, \"symbol\": {}, \"referrer\": {} }}",
+        json_string(&module.module),
+        json_string(&module.symbol),
+        json_string(&module.referrer),
+    )).collect::<Vec<String>>();
+
+    json.push_str(&format!("  \"library_modules\": [\n{}\n  ],\n", library_modules.join(",\n")));
+
+    let line_numbers = map.line_numbers.iter().map(|linnums| format!(
+        "    {{ \"file\": {}, \"source\": {}, \"segment\": {}, \"lines\": [{}] }}",
+        json_string(&linnums.file),
+        json_string(&linnums.source),
+        json_string(&linnums.segment),
+        linnums.lines.iter().map(|line| format!(
+            "{{ \"line\": {}, \"frame\": {}, \"offset\": {} }}", line.line, line.frame, line.offset
+        )).collect::<Vec<String>>().join(", "),
+    )).collect::<Vec<String>>();
+
+    json.push_str(&format!("  \"line_numbers\": [\n{}\n  ],\n", line_numbers.join(",\n")));
+
+    json.push_str(&format!("  \"entry\": {},\n", json_far_ptr(&map.entry)));
+    json.push_str(&format!("  \"stack\": {},\n", json_far_ptr(&map.stack)));
+    json.push_str(&format!("  \"relocations\": {},\n", map.relocations));
+    json.push_str(&format!("  \"far_calls_translated\": {}\n", map.far_calls.map_or("null".to_owned(), |n| n.to_string())));
+    json.push_str("}\n");
+
+    json
+}
+
+/// Write the link map as JSON.
+///
+pub fn write_linkmap_json(path: &PathBuf, map: &LinkMap) -> Result<(), LinkerError> {
+    let mut fp = File::create(path)?;
+    fp.write_all(linkmap_json(map).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("ABC"), "\"ABC\"");
+        assert_eq!(json_string("A\"B\\C"), "\"A\\\"B\\\\C\"");
+        assert_eq!(json_string("\x01"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn frame_offset_is_relative_to_base() {
+        assert_eq!(frame_offset(0x120, 0x150), (0x12, 0x30));
+        assert_eq!(frame_offset(0x122, 0x150), (0x12, 0x30));
+    }
+
+    fn sample_map() -> LinkMap {
+        LinkMap {
+            segments: vec![MapSegment {
+                name: "_TEXT".to_owned(),
+                class: "CODE".to_owned(),
+                group: None,
+                base: 0,
+                length: 0x20,
+                align: Align::Para,
+                combine: Combine::Public,
+                contributions: Vec::new(),
+            }],
+            groups: vec![MapGroup { name: "DGROUP".to_owned(), base: 0x22, segments: Vec::new() }],
+            publics: vec![
+                MapPublic { name: "_main".to_owned(), frame: 0, offset: 0x10, linear: 0x10, used: true, absolute: false },
+                MapPublic { name: "__acrtused".to_owned(), frame: 0, offset: 0x9876, linear: 0x9876, used: true, absolute: true },
+            ],
+            library_modules: Vec::new(),
+            line_numbers: vec![MapLineNumbers {
+                file: "HELLO.OBJ".to_owned(),
+                source: "hello.c".to_owned(),
+                segment: "_TEXT".to_owned(),
+                lines: vec![MapLine { line: 3, frame: 0, offset: 0x10 }, MapLine { line: 4, frame: 0, offset: 0x14 }],
+            }],
+            entry: Some(FarPtr::new(0, 0x10)),
+            stack: None,
+            relocations: 0,
+            far_calls: None,
+        }
+    }
+
+    #[test]
+    fn ms_layout() -> Result<(), LinkerError> {
+        let map = sample_map();
+
+        let mut text = Vec::new();
+        write_linkmap_ms(&mut text, &map)?;
+        let text = String::from_utf8(text).unwrap();
+        let lines = text.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines[1], " Start  Stop   Length Name                   Class");
+        assert_eq!(lines[2], " 00000H 0001FH 00020H _TEXT                  CODE");
+        assert_eq!(lines[4], " Origin   Group");
+        assert_eq!(lines[5], " 0002:2   DGROUP");
+        assert!(lines.contains(&" 0000:0010       _main"));
+        assert!(lines.contains(&" 0000:9876  Abs  __acrtused"));
+        assert!(lines.contains(&"Line numbers for HELLO.OBJ(hello.c) segment _TEXT"));
+        assert!(lines.contains(&"     3 0000:0010     4 0000:0014"));
+        assert_eq!(lines.last(), Some(&"Program entry point at 0000:0010"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn text_and_json_agree() -> Result<(), LinkerError> {
+        let map = sample_map();
+
+        let mut text = Vec::new();
+        write_linkmap_ms(&mut text, &map)?;
+        let text = String::from_utf8(text).unwrap();
+        let json = linkmap_json(&map);
+
+        for seg in map.segments.iter() {
+            assert!(text.contains(&format!(" {:05X}H {:05X}H {:05X}H {:22} {}", seg.base, seg.base + seg.length - 1, seg.length, seg.name, seg.class)));
+            assert!(json.contains(&format!("\"name\": \"{}\",\n      \"class\": \"{}\",\n      \"group\": null,\n      \"base\": {},\n      \"length\": {},",
+                seg.name, seg.class, seg.base, seg.length)));
+        }
+
+        for sym in map.publics.iter() {
+            let abs = if sym.absolute { "Abs" } else { "   " };
+            assert!(text.contains(&format!(" {:04X}:{:04X}  {abs}  {}", sym.frame, sym.offset, sym.name)));
+            assert!(json.contains(&format!("{{ \"name\": \"{}\", \"frame\": {}, \"offset\": {}, \"linear\": {}, \"used\": {}, \"absolute\": {} }}",
+                sym.name, sym.frame, sym.offset, sym.linear, sym.used, sym.absolute)));
+        }
+
+        for line in map.line_numbers.iter().flat_map(|linnums| linnums.lines.iter()) {
+            assert!(text.contains(&format!("{:6} {:04X}:{:04X}", line.line, line.frame, line.offset)));
+            assert!(json.contains(&format!("{{ \"line\": {}, \"frame\": {}, \"offset\": {} }}", line.line, line.frame, line.offset)));
+        }
+
+        let entry = map.entry.as_ref().unwrap();
+        assert!(text.contains(&format!("Program entry point at {:04X}:{:04X}", entry.seg, entry.offset)));
+        assert!(json.contains(&format!("\"entry\": {{ \"segment\": {}, \"offset\": {} }}", entry.seg, entry.offset)));
+
+        Ok(())
+    }
+}
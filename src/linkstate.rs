@@ -1,11 +1,14 @@
+use std::collections::HashMap;
+
+use crate::comdat::Comdat;
 use crate::group::Group;
 use crate::lnames::LNames;
 use crate::omf_vec::OmfVec;
-use crate::segment::{Segment, SegName};
+use crate::segment::{Combine, Segment, SegName};
 use crate::symbols::SymbolTable;
 
 #[cfg(test)]
-use crate::segment::{Align, Combine};
+use crate::segment::Align;
 
 //
 // Linker-global data
@@ -34,11 +37,19 @@ impl FarPtr {
     }
 
     pub fn to_linear(&self) -> usize {
-        (self.seg as usize) << 4 + self.offset as usize
+        ((self.seg as usize) << 4) + self.offset as usize
     }
 }
 
 
+/// A segment whose initial data is stored at `base` rather than where it runs, for
+/// startup code to copy.
+///
+pub struct DataLoad {
+    pub segment: usize,
+    pub base: usize,
+}
+
 pub struct LinkState {
     pub lnames: LNames,
     pub segments: OmfVec<Segment>,
@@ -46,6 +57,13 @@ pub struct LinkState {
     pub symbols: SymbolTable,
     pub segment_order: Vec<usize>,
     pub entry: Option<FarPtr>,
+    pub stack: Option<FarPtr>,
+    pub relocations: usize,
+    pub far_calls: Option<usize>,
+    pub fixup_errors: bool,
+    pub overlap_errors: bool,
+    pub loads: Vec<DataLoad>,
+    pub comdats: HashMap<String, Comdat>,
 }
 
 impl LinkState {
@@ -57,6 +75,13 @@ impl LinkState {
             symbols: SymbolTable::new(),
             segment_order: Vec::new(),
             entry: None,
+            stack: None,
+            relocations: 0,
+            far_calls: None,
+            fixup_errors: false,
+            overlap_errors: false,
+            loads: Vec::new(),
+            comdats: HashMap::new(),
         }
     }
 
@@ -78,6 +103,40 @@ impl LinkState {
             .map(|(i, _)| i + 1)
     }
 
+    /// The segment which holds the stack: the first segment combined as a stack, whatever
+    /// its name.
+    ///
+    pub fn stack_segment(&self) -> Option<usize> {
+        self.segments
+            .iter()
+            .enumerate()
+            .find(|(_, seg)| seg.combine == Combine::Stack && seg.debug.is_none())
+            .map(|(i, _)| i + 1)
+    }
+
+    /// The linear base of the frame a segment's offsets are relative to: its own base, or
+    /// that of the first segment of its pack.
+    ///
+    pub fn segment_frame_base(&self, segidx: usize) -> usize {
+        let segment = &self.segments[segidx];
+
+        if segment.pack != 0 {
+            self.segments[segment.pack].base & !0x000f
+        } else {
+            segment.base
+        }
+    }
+
+    /// The linear base of the frame a segment is addressed in: its group's if it is in one,
+    /// and otherwise its own frame base.
+    ///
+    pub fn segment_address_base(&self, segidx: usize) -> usize {
+        match self.segments[segidx].group {
+            0 => self.segment_frame_base(segidx),
+            group => self.groups[group].base,
+        }
+    }
+
     pub fn get_group_named(&mut self, grpname: usize) -> Option<usize> {
         self.groups
             .iter()
@@ -106,6 +165,20 @@ mod test {
         assert_eq!(state.get_segment_named(&SegName::new(4,8,6)), None);
     }
 
+    #[test]
+    fn stack_segment() {
+        let mut state = LinkState::new();
+        assert_eq!(state.stack_segment(), None);
+
+        let segment = Segment::new(SegName::new(1, 2, 3), 0x200, Align::Byte, Combine::Public);
+        state.segments.add(segment);
+
+        let segment = Segment::new(SegName::new(4, 5, 6), 0x400, Align::Para, Combine::Stack);
+        state.segments.add(segment);
+
+        assert_eq!(state.stack_segment(), Some(2));
+    }
+
     #[test]
     fn get_group_named() {
         let mut state = LinkState::new();
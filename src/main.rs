@@ -1,33 +1,63 @@
+mod binfile;
+mod codeview;
+mod comdat;
+mod devdriver;
 mod dosexe;
+mod exepack;
+mod gc;
 mod group;
+mod hexfile;
 mod index_map;
 mod library;
 mod linker_error;
+mod linkmap;
 mod linkstate;
 mod lnames;
 mod omf_vec;
 mod object;
 mod pass1;
 mod pass2;
+mod prescan;
 mod record;
+mod script;
 mod segment;
 mod symbols;
+mod symfile;
+mod tdinfo;
 
 #[cfg(test)]
 mod testlib;
 
 use clap::Parser;
-use std::fs::File;
-use std::io::Write;
+use dosexe::verify_file;
 use std::path::PathBuf;
 use std::process::exit;
 use library::Library;
 use linker_error::LinkerError;
+use linkmap::{LinkMap, MapStyle, write_linkmap, write_linkmap_json, write_why_linked};
 use linkstate::LinkState;
-use object::Object;
 use pass1::pass1;
 use pass2::pass2;
-use symbols::Symbol;
+use prescan::{default_jobs, map_in_parallel};
+use symfile::write_symfile;
+
+/// The kind of file to write.
+///
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// DOS MZ executable
+    Exe,
+    /// Raw binary image for a fixed load segment, as for a ROM or boot sector
+    Bin,
+    /// Intel HEX, for a fixed load segment
+    Hex,
+    /// Motorola S-records, for a fixed load segment
+    Srec,
+    /// DOS device driver, a flat image starting with the device header
+    Sys,
+    /// DOS device driver in an MZ executable, with the device header at the entry segment
+    SysExe,
+}
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -35,27 +65,159 @@ pub struct Args {
     pub output: Option<PathBuf>,
     #[arg(short = 'm')]
     pub linkmap: Option<PathBuf>,
+    /// Kind of file to write.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Exe)]
+    pub format: OutputFormat,
+    /// Segment a raw binary or hex image is loaded at, which segment fixups are resolved against.
+    #[arg(long, value_parser = parse_number)]
+    pub base_segment: Option<usize>,
+    /// Linear address a raw binary or hex image is loaded at, as an alternative to --base-segment.
+    #[arg(long, value_parser = parse_number, conflicts_with = "base_segment")]
+    pub origin: Option<usize>,
+    /// Split a raw binary image into ROMs of this many bytes, padded with FFH.
+    #[arg(long, value_parser = parse_number)]
+    pub rom_size: Option<usize>,
+    /// Layout of the text link map.
+    #[arg(long, value_enum, default_value_t = MapStyle::Tlink)]
+    pub map_style: MapStyle,
+    /// Also write the link map as JSON.
+    #[arg(long)]
+    pub map_json: Option<PathBuf>,
     #[arg(short)]
     pub libpath: Vec<PathBuf>,
     #[arg(short = 'L')]
     pub libs: Vec<PathBuf>,
+    /// Size of the stack in bytes, replacing the size of the stack segment, which may only
+    /// grow if modules initialize it. A program without a stack segment gets a stack of
+    /// this size after its image.
+    #[arg(long, value_parser = parse_number)]
+    pub stack: Option<usize>,
+    /// Most memory, in paragraphs beyond the image, the program should be given when it
+    /// loads (as /CPARMAXALLOC).
+    #[arg(long, value_parser = parse_number)]
+    pub max_alloc: Option<usize>,
+    /// Paragraphs of memory to reserve beyond the program's uninitialized data and stack,
+    /// as for a heap.
+    #[arg(long, value_parser = parse_number, default_value_t = 0)]
+    pub extra_alloc: usize,
+    /// Have DOS load the program at the top of memory (as /HIGH).
+    #[arg(long, conflicts_with_all = ["max_alloc", "extra_alloc", "exepack"])]
+    pub high: bool,
+    /// Pack adjacent code segments of the same class into shared frames of up to this many
+    /// bytes (as /PACKCODE; 65500 if no size is given).
+    #[arg(long, value_parser = parse_number, num_args = 0..=1, require_equals = true, default_missing_value = "65500")]
+    pub pack_code: Option<usize>,
+    /// Pack adjacent data segments of the same class outside groups into shared frames of
+    /// up to this many bytes (as /PACKDATA; 65536 if no size is given).
+    #[arg(long, value_parser = parse_number, num_args = 0..=1, require_equals = true, default_missing_value = "65536")]
+    pub pack_data: Option<usize>,
+    /// Rewrite far calls to procedures in the same frame as `nop; push cs; call near`, which
+    /// need no relocation (as /FARCALLTRANSLATION).
+    #[arg(long)]
+    pub far_call_translation: bool,
+    /// Make fixup overflows, and fixups outside their frame, errors rather than warnings.
+    #[arg(long)]
+    pub fixup_errors: bool,
+    /// Make data of one module overlapping data of another, outside common segments, an
+    /// error rather than a warning.
+    #[arg(long)]
+    pub overlap_errors: bool,
+    /// Number of threads reading and scanning object modules and libraries; by default, one
+    /// per CPU. The output is the same however many are used.
+    #[arg(long, value_parser = parse_number)]
+    pub jobs: Option<usize>,
+    /// Linker script controlling where segments are placed.
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+    /// Place segments of these classes first, in this order, as a script's order statement.
+    #[arg(long, value_name = "CLASS,...", value_delimiter = ',')]
+    pub order: Vec<String>,
+    /// Place a segment or group at a fixed paragraph, as a script's at statement.
+    #[arg(long, value_name = "NAME=PARAGRAPH")]
+    pub at: Vec<String>,
+    /// Move uninitialized segments (those without data, as class BSS or STACK usually are)
+    /// to the end of the image, so they take no space in the file.
+    #[arg(long)]
+    pub bss_last: bool,
+    /// Discard segment contributions nothing reachable from the entry point, the stack or
+    /// a --gc-root refers to.
+    #[arg(long)]
+    pub gc: bool,
+    /// A public symbol whose segment contribution garbage collection must keep.
+    #[arg(long, value_name = "SYMBOL", requires = "gc")]
+    pub gc_root: Vec<String>,
+    /// Append CodeView debug information to the executable.
+    #[arg(long)]
+    pub codeview: bool,
+    /// Compress the executable with EXEPACK.
+    #[arg(long)]
+    pub exepack: bool,
+    /// Append a Turbo Debugger symbol table to the executable, with the publics, line
+    /// numbers, and the scopes, locals and types of Borland debug comments (as TLINK /v).
+    #[arg(long, conflicts_with_all = ["codeview", "exepack"])]
+    pub td: bool,
+    /// Write a MAPSYM-format .SYM symbol file.
+    #[arg(long)]
+    pub sym: Option<PathBuf>,
+    /// Report why each library module was linked.
+    #[arg(long)]
+    pub why_linked: bool,
+    /// Check the header of an existing executable and print a summary of it, instead of
+    /// linking.
+    #[arg(long, value_name = "EXE")]
+    pub verify: Option<PathBuf>,
     pub objects: Vec<PathBuf>,
 }
 
+/// Parse a number from the command line, in decimal or, with a 0x prefix, hex.
+///
+fn parse_number(arg: &str) -> Result<usize, String> {
+    let result = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+
+    result.map_err(|_| format!("{} is not a number.", arg))
+}
+
 /// Parse the command line arguments, and construct missing but needed output
 /// filenames.
 ///
 fn get_args() -> Args {
     let mut args = Args::parse();
 
-    if args.objects.is_empty() {
+    if args.objects.is_empty() && args.verify.is_none() {
         eprintln!("No objects specified");
         exit(1);
     }
 
-    if args.output.is_none() {
+    let exe = matches!(args.format, OutputFormat::Exe | OutputFormat::SysExe);
+    let fixed = matches!(args.format, OutputFormat::Bin | OutputFormat::Hex | OutputFormat::Srec);
+
+    if !exe && (args.codeview || args.td || args.exepack || args.high) {
+        eprintln!("Debug information, EXEPACK and --high need an EXE file");
+        exit(1);
+    }
+
+    if !fixed && (args.base_segment.is_some() || args.origin.is_some()) {
+        eprintln!("--base-segment and --origin need a binary or hex format");
+        exit(1);
+    }
+
+    if args.format != OutputFormat::Bin && args.rom_size.is_some() {
+        eprintln!("--rom-size needs --format bin");
+        exit(1);
+    }
+
+    if args.output.is_none() && !args.objects.is_empty() {
         let mut output = args.objects[0].clone();
-        output.set_extension("exe");
+        output.set_extension(match args.format {
+            OutputFormat::Exe => "exe",
+            OutputFormat::Bin => "bin",
+            OutputFormat::Hex => "hex",
+            OutputFormat::Srec => "srec",
+            OutputFormat::Sys | OutputFormat::SysExe => "sys",
+        });
         args.output = Some(output);
     }
 
@@ -65,7 +227,7 @@ fn get_args() -> Args {
 /// Locate and preload libraries on the command line
 /// 
 fn get_libs(args: &Args) -> Result<Vec<Library>, LinkerError> {
-    let mut libs = Vec::new();
+    let mut paths = Vec::new();
 
     for lib in args.libs.iter() {
         let libpath = if lib.exists() {
@@ -74,150 +236,54 @@ fn get_libs(args: &Args) -> Result<Vec<Library>, LinkerError> {
             args.libpath.iter().map(|path| path.join(lib)).find(|path| path.exists())
         };
 
-        let library = match libpath {
-            Some(path) => Library::new(lib.as_os_str().to_str().unwrap(), path)?,
+        match libpath {
+            Some(path) => paths.push((lib.as_os_str().to_str().unwrap(), path)),
             None => return Err(LinkerError::new(&format!("library {:?} not found in current directory or library path.", lib))),
         };
-
-        libs.push(library);
     }
 
-    Ok(libs)
-}
-
-/// After pass 1, write out the link map if requested.
-///
-fn write_linkmap(linkmap: &PathBuf, linkstate: &LinkState, objects: &[Object]) -> Result<(), LinkerError> {
-    let mut fp = File::create(linkmap)?;
-
-    writeln!(&mut fp, "\n Start  Stop   Length Name               Class\n")?;
-    for segidx in linkstate.segment_order.iter().map(|x| *x) {
-        let seg = &linkstate.segments[segidx];
-
-        writeln!(&mut fp, " {:05X}H {:05X}H {:05X}H {:18} {}", 
-            seg.base,
-            if seg.length == 0 { seg.base } else { seg.base + seg.length - 1},
-            seg.length, 
-            linkstate.lnames.get(seg.name.nameidx), 
-            linkstate.lnames.get(seg.name.classidx))?;
-    }
-    
-    writeln!(&mut fp, "\n\nDetailed map of segments\n")?;
-
-    for segidx in linkstate.segment_order.iter() {
-        let seg = &linkstate.segments[*segidx];
-        let grp = if seg.group != 0 { linkstate.lnames.get(linkstate.groups[seg.group].name)  } else { "(none)" };
-
-        let base = if seg.group == 0 { seg.base } else { linkstate.groups[seg.group].base };
-        
-        for obj in objects.iter() {
-            for segdef in obj.segdefs.iter() {
-                if segdef.segidx == *segidx {
-                    let linear = seg.base + segdef.base;
-
-                    let frame = base >> 4;
-                    let offset = (base & 0x000f) + (linear - base);
-
-
-                    writeln!(&mut fp, " {:04X}:{:04X} {:04X} C={:6} S={:14} G={:7} M={:10} ACBP={:02X}", 
-                        frame, offset, 
-                        segdef.length,   
-                        linkstate.lnames.get(seg.name.classidx),
-                        linkstate.lnames.get(seg.name.nameidx),
-                        grp,
-                        obj.name.to_uppercase(),
-                        segdef.acbp
-                    )?;
-                }
-            }
-        }
-    }
-
-    struct SortSym {
-        name: String,
-        frame: usize,
-        offset: usize,
-        linear: usize,
-        used: bool,
-    }
+    let jobs = args.jobs.unwrap_or_else(default_jobs);
 
-    writeln!(&mut fp, "\n  Address         Publics by Name\n")?;
-
-    let mut symbols = linkstate.symbols.symbols.keys().map(|name| name.to_owned()).collect::<Vec<String>>();
-    symbols.sort();
-
-    let mut byvalue = Vec::new();
-
-    for name in symbols.iter() {
-        let sym = linkstate.symbols.symbols.get(name).unwrap();
-
-        let used = if let Symbol::Public(public) = sym {
-            public.used
-        } else {
-            true
-        };
-
-        let (frame,offset) = match &sym {
-            &Symbol::Public(p) => {
-                if p.segment != 0 {
-                    let linear = linkstate.segments[p.segment].base + p.offset as usize;
-
-                    let base = if p.group != 0 {
-                        linkstate.groups[p.group].base
-                    } else {
-                        linkstate.segments[p.segment].base
-                    };
-
-                    let frame = base >> 4;
-                    let offset = (base & 0x000f) + (linear - base);
-
-                    (frame, offset)
-                } else {
-                    (p.frame as usize, p.offset as usize)
-                }
-            },
-            &Symbol::_Common(_c) => { (0,0) },
-            _ => continue,
-        };
-
-        byvalue.push(SortSym{
-            name: name.to_owned(),
-            frame,
-            offset,
-            linear: (frame << 4) + offset,
-            used
-        });
-
-        let used = if used { "    " } else { "idle" };
-        writeln!(&mut fp, " {:04X}:{:04X} {used}  {}", frame, offset, name.to_uppercase())?;
-    }
-
-    byvalue.sort_by_key(|sym| sym.linear);
-
-    writeln!(&mut fp, "\n  Address         Publics by Value\n")?;
-
-    for sym in byvalue.iter() {
-        let used = if sym.used { "    " } else { "idle" };
-        writeln!(&mut fp, " {:04X}:{:04X} {used}  {}", sym.frame, sym.offset, sym.name.to_uppercase())?;
-    }
-    
-    Ok(())
+    map_in_parallel(&paths, jobs, |(name, path)| Library::new(name, path.clone()))
+        .into_iter()
+        .collect()
 }
 
 fn main() -> Result<(), LinkerError> {
     let args = get_args();
 
+    if let Some(exe) = &args.verify {
+        return verify_file(exe);
+    }
+
     let mut linkstate = LinkState::new();
     let mut objects = Vec::new();
     let libs = get_libs(&args)?;
 
     pass1(&mut linkstate, &mut objects, &libs, &args)?;
 
+    if args.why_linked {
+        write_why_linked(&mut std::io::stdout(), &objects)?;
+    }
+
+    //
+    // Write the maps even if pass 2 fails, as they are useful in tracking down the failure.
+    //
+    let result = pass2(&mut linkstate, &mut objects, &args);
+
+    let map = LinkMap::new(&linkstate, &objects);
+
     if let Some(linkmap) = &args.linkmap {
-        write_linkmap(linkmap, &linkstate, &objects)?;
+        write_linkmap(linkmap, &map, &objects, args.map_style)?;
     }
 
-    pass2(&mut linkstate, &mut objects, &args)?; 
+    if let Some(map_json) = &args.map_json {
+        write_linkmap_json(map_json, &map)?;
+    }
+
+    if let Some(sym) = &args.sym {
+        write_symfile(sym, &linkstate)?;
+    }
 
-    Ok(())
+    result
 }
@@ -1,11 +1,74 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+use crate::comdat::Comdat;
 use crate::index_map::IndexMap;
 use crate::linker_error::LinkerError;
 use crate::omf_vec::OmfVec;
 use crate::pass2::ThreadState;
-use crate::segment::SegDef;
+use crate::segment::{DebugInfo, SegDef};
+use crate::symbols::{Symbol, SymbolTable};
+
+/// Why a library module was pulled into the link: the symbol that was resolved
+/// by it, and the index (in the link's object list) of the module that
+/// referenced that symbol.
+///
+pub struct LinkReason {
+    pub symbol: String,
+    pub referrer: usize,
+}
+
+/// A source line number and the offset of its code, relative to the start of the
+/// linker-level segment.
+///
+pub struct LineNumber {
+    pub line: u16,
+    pub offset: usize,
+}
+
+/// The line numbers from LINNUM records for one segment of an object module. `source`
+/// is the 1-based index of the module's source file the lines are in, or 0 for the
+/// file named by THEADR.
+///
+pub struct LineNumbers {
+    pub segidx: usize,
+    pub source: usize,
+    pub group: usize,
+    pub lines: Vec<LineNumber>,
+}
+
+/// A source file named by a Borland COMENT record, with its DOS timestamp.
+///
+pub struct SourceFile {
+    pub name: String,
+    pub timestamp: u32,
+}
+
+/// A local symbol from a Borland debug comment. `class` is the Turbo Debugger symbol
+/// class; a static is at `value` in the module's SEGDEF `segment`, and for other classes
+/// `segment` is 0 and `value` is, say, the offset from BP or the register.
+///
+pub struct BorlandLocal {
+    pub name: String,
+    pub typeidx: usize,
+    pub class: u8,
+    pub segment: usize,
+    pub value: u16,
+}
+
+/// What a Borland debug comment (classes E0H to EFH) says about the module's types and
+/// scopes, in the order the comments appear. Type indices are the module's own; members
+/// belong to the type defined before them.
+///
+pub enum BorlandDebug {
+    PublicType { name: String, typeidx: usize },
+    Type { typeidx: usize, name: String, size: u16, id: u8, reference: usize },
+    Member { name: String, typeidx: usize, offset: u16 },
+    BeginScope { segment: usize, offset: u16 },
+    Locals(Vec<BorlandLocal>),
+    EndScope { offset: u16 },
+}
 
 //
 // Holds collections of data parsed from each object file.
@@ -13,10 +76,21 @@ use crate::segment::SegDef;
 pub struct Object {
     pub data: Option<Vec<u8>>,
     pub name: String,
+    pub filename: String,
+    pub library: Option<String>,
+    pub linked_by: Option<LinkReason>,
     pub lnames: IndexMap,
     pub segdefs: OmfVec<SegDef>,
     pub grpdefs: IndexMap,
     pub extdefs: OmfVec<String>,
+    pub linnums: Vec<LineNumbers>,
+    pub comdats: Vec<Comdat>,
+    pub local_symbols: HashMap<String, Symbol>,
+    pub source_files: OmfVec<SourceFile>,
+    pub current_source: usize,
+    pub borland_debug: Vec<BorlandDebug>,
+    pub debug_types: Vec<u8>,
+    pub debug_symbols: Vec<u8>,
     pub fixup_threads: ThreadState,
 }
 
@@ -29,10 +103,21 @@ impl Object {
         Object {
             data: None,
             name: "".to_owned(),
+            filename: "".to_owned(),
+            library: None,
+            linked_by: None,
             lnames: IndexMap::new(),
             segdefs: OmfVec::new(),
             grpdefs: IndexMap::new(),
             extdefs: OmfVec::new(),
+            linnums: Vec::new(),
+            comdats: Vec::new(),
+            local_symbols: HashMap::new(),
+            source_files: OmfVec::new(),
+            current_source: 0,
+            borland_debug: Vec::new(),
+            debug_types: Vec::new(),
+            debug_symbols: Vec::new(),
             fixup_threads: ThreadState::new(),
         }
     }
@@ -41,13 +126,26 @@ impl Object {
     // Construct around a filename.
     //
     pub fn from_filename(name: &PathBuf) -> Result<Self, LinkerError> {
+        let filename = name.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
         Ok(Object {
             data: Some(fs::read(name)?),
             name: "".to_owned(),
+            filename,
+            library: None,
+            linked_by: None,
             lnames: IndexMap::new(),
             segdefs: OmfVec::new(),
             grpdefs: IndexMap::new(),
             extdefs: OmfVec::new(),
+            linnums: Vec::new(),
+            comdats: Vec::new(),
+            local_symbols: HashMap::new(),
+            source_files: OmfVec::new(),
+            current_source: 0,
+            borland_debug: Vec::new(),
+            debug_types: Vec::new(),
+            debug_symbols: Vec::new(),
             fixup_threads: ThreadState::new(),
         })
     }
@@ -59,11 +157,75 @@ impl Object {
         Object {
             data: Some(data),
             name: "".to_owned(),
+            filename: "".to_owned(),
+            library: None,
+            linked_by: None,
             lnames: IndexMap::new(),
             segdefs: OmfVec::new(),
             grpdefs: IndexMap::new(),
             extdefs: OmfVec::new(),
+            linnums: Vec::new(),
+            comdats: Vec::new(),
+            local_symbols: HashMap::new(),
+            source_files: OmfVec::new(),
+            current_source: 0,
+            borland_debug: Vec::new(),
+            debug_types: Vec::new(),
+            debug_symbols: Vec::new(),
             fixup_threads: ThreadState::new(),
         }
     }
+
+    //
+    // The symbol an external of the module refers to: one local to the module, such as a
+    // local COMDAT, before a public of the same name.
+    //
+    pub fn symbol<'a>(&'a self, symbols: &'a SymbolTable, name: &str) -> Option<&'a Symbol> {
+        self.local_symbols.get(name).or_else(|| symbols.symbols.get(name))
+    }
+
+    //
+    // The SEGDEF holding the module's definition of a COMDAT, if it was chosen.
+    //
+    pub fn comdat_segdef(&self, name: &str) -> Option<usize> {
+        self.comdats.iter().find(|comdat| comdat.name == name).and_then(|comdat| comdat.segdef)
+    }
+
+    //
+    // The module's data for a kind of debug segment.
+    //
+    pub fn debug_data_mut(&mut self, debug: DebugInfo) -> &mut Vec<u8> {
+        match debug {
+            DebugInfo::Types => &mut self.debug_types,
+            DebugInfo::Symbols => &mut self.debug_symbols,
+        }
+    }
+
+    //
+    // The file the module came from: the object file, or the library containing it.
+    //
+    pub fn source_file(&self) -> &str {
+        self.library.as_deref().unwrap_or(&self.filename)
+    }
+
+    //
+    // The name of the source file a set of line numbers is in.
+    //
+    pub fn line_source(&self, linnums: &LineNumbers) -> &str {
+        if linnums.source == 0 {
+            &self.name
+        } else {
+            &self.source_files[linnums.source].name
+        }
+    }
+
+    //
+    // A printable name for the module, including the containing library if any.
+    //
+    pub fn display_name(&self) -> String {
+        match &self.library {
+            Some(library) => format!("{}({})", self.name.to_uppercase(), library.to_uppercase()),
+            None => self.name.to_uppercase(),
+        }
+    }
 }
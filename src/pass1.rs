@@ -1,14 +1,20 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::Args;
+use crate::comdat::{allocation_segment, local_comdat_nameidx, Allocation, Comdat, ComdatHeader};
+use crate::gc::collect_garbage;
 use crate::group::Group;
 use crate::library::Library;
 use crate::linker_error::LinkerError;
-use crate::linkstate::LinkState;
-use crate::object::Object;
+use crate::linkstate::{DataLoad, LinkState};
+use crate::pass2::accum_lidata;
+use crate::prescan::{default_jobs, map_in_parallel, read_extdef_names, read_lnames, read_objects, ModuleScan, PubDefRecord, Scanned, ScannedRecord, SegDefRecord};
+use crate::object::{BorlandDebug, BorlandLocal, LineNumber, LineNumbers, LinkReason, Object, SourceFile};
 use crate::record::{Record, RecordType};
-use crate::segment::{Segment, SegDef, SegName, Align, Combine};
+use crate::script::LinkScript;
+use crate::segment::{Segment, SegDef, SegName, Align, Combine, DebugInfo};
 use crate::symbols::Symbol;
+use crate::tdinfo;
 
 
 //
@@ -16,7 +22,7 @@ use crate::symbols::Symbol;
 //
 
 
-#[derive(PartialOrd, PartialEq, Eq, Clone, Copy)]
+#[derive(PartialOrd, PartialEq, Eq, Clone, Copy, Hash)]
 struct LibraryModule {
     lib: usize,
     modpage: usize,
@@ -38,6 +44,15 @@ impl Ord for LibraryModule {
     }
 }
 
+/// The module which referenced a symbol, while searching libraries: either an object
+/// from the command line (by index), or a library module already selected.
+///
+#[derive(Clone, Copy)]
+enum Referrer {
+    Object(usize),
+    Module(LibraryModule),
+}
+
 struct LibraryModules {
     mods: Vec<LibraryModule>,
 }
@@ -62,22 +77,59 @@ impl LibraryModules {
 /// Execute pass 1. 
 /// - Parse all objects from the command line.
 /// - Resolve unresolved externals from libraries.
+/// - Optionally discard unreferenced segment contributions.
 /// - Compute memory map.
+/// - Optionally pack segments into shared frames.
 /// 
 pub fn pass1(state: &mut LinkState, objects: &mut Vec<Object>, libs: &[Library], args: &Args) -> Result<(), LinkerError> {
+    let mut script = args.script.as_ref().map(LinkScript::read).transpose()?;
+
+    //
+    // Placements given on the command line add to the script, or make one if there is none.
+    //
+    if !args.order.is_empty() || !args.at.is_empty() {
+        script.get_or_insert_with(LinkScript::default).add_options(&args.order, &args.at)?;
+    }
+
     //
-    // Execute pass 1 on all command line object files
+    // Symbols the script defines are given their values once the memory map is built.
     //
-    for objname in args.objects.iter() {
-        let mut obj = Object::from_filename(objname)?;
+    if let Some(script) = &script {
+        for load in script.loads.iter() {
+            state.symbols.update(&load.symbol, Symbol::public(0, 0, 0, 0))?;
+        }
+    }
+
+    //
+    // Execute pass 1 on all command line object files. The files are read and scanned in
+    // parallel, and merged into the link in command line order.
+    //
+    let jobs = args.jobs.unwrap_or_else(default_jobs);
+
+    for (objname, read) in args.objects.iter().zip(read_objects(&args.objects, jobs)) {
+        let (mut obj, scan) = read?;
         let data = obj.data.take().unwrap();
-        pass1_object(state, &data, &mut obj, objname.as_os_str().to_str().unwrap())?;
+        pass1_object(state, &data, scan, &mut obj, objname.as_os_str().to_str().unwrap())?;
         obj.data = Some(data);
         objects.push(obj);
     }
 
-    pass1_add_library_modules(state, libs, objects)?;
-    pass1_build_memory_map(state)?;
+    pass1_add_library_modules(state, libs, objects, jobs)?;
+
+    if args.gc {
+        let removed = collect_garbage(state, objects, &args.gc_root)?;
+        println!("Garbage collection removed {} bytes.", removed);
+    }
+
+    if let Some(size) = args.stack {
+        pass1_size_stack(state, size)?;
+    }
+
+    pass1_build_memory_map(state, args.bss_last, script.as_ref())?;
+
+    if args.pack_code.is_some() || args.pack_data.is_some() {
+        pass1_pack_segments(state, args.pack_code, args.pack_data)?;
+    }
 
     Ok(())
 }
@@ -85,23 +137,37 @@ pub fn pass1(state: &mut LinkState, objects: &mut Vec<Object>, libs: &[Library],
 /// Given that the initial set of object modules from the command line have been processed,
 /// repeatedly search given libraries for unresolved externals until all externals have
 /// been resolved, or some cannot be resolved. Add the object modules from the libraries to
-/// the `objects` list.
+/// the `objects` list, recording in each why it was linked.
 ///
-fn pass1_add_library_modules(state: &mut LinkState, libs: &[Library], objects: &mut Vec<Object>) -> Result<(), LinkerError> {
+fn pass1_add_library_modules(state: &mut LinkState, libs: &[Library], objects: &mut Vec<Object>, jobs: usize) -> Result<(), LinkerError> {
     let mut mods = LibraryModules::new();
-
+    let mut reasons = HashMap::new();
 
     let mut undstart = 0;
     let mut undefined = 0;
 
-    let mut und = state.symbols.undefined_symbols().into_iter().map(|s| s.to_owned()).collect::<Vec<String>>();
-
+    //
+    // Start with the externals of the command line objects, in command line order,
+    // so the module which first referenced each symbol is known.
+    //
+    let mut und = Vec::new();
+    for (objidx, obj) in objects.iter().enumerate() {
+        for ext in pass1_obj_externs(obj.data.as_ref().unwrap())? {
+            und.push((ext, Referrer::Object(objidx)));
+        }
+    }
 
     loop {
         //
         // First, find all the known undefineds. 
         //
-        for ext in und {
+        let mut seen = HashSet::new();
+
+        for (ext, referrer) in und {
+            if !seen.insert(ext.clone()) {
+                continue;
+            }
+
             //
             // If it's in the symbol table, it's resolved by one of the already loaded 
             // modules.
@@ -119,7 +185,12 @@ fn pass1_add_library_modules(state: &mut LinkState, libs: &[Library], objects: &
 
             for (libidx, lib) in libs.iter().enumerate() {
                 if let Some(modpage) = lib.find_symbol_in_dictionary(&ext)? {
-                    mods.add(LibraryModule { lib: libidx, modpage });
+                    let module = LibraryModule { lib: libidx, modpage };
+
+                    if !mods.has(module) {
+                        mods.add(module);
+                        reasons.insert(module, (ext.clone(), referrer));
+                    }
                     found = true;
                     break;
                 }
@@ -150,14 +221,14 @@ fn pass1_add_library_modules(state: &mut LinkState, libs: &[Library], objects: &
         //
         // We added at least one more module, collect externs again.
         //
-        let mut new_externs = HashSet::new();
+        let mut new_externs = Vec::new();
         while undstart < mods.mods.len() {
-            let moddef = &mods.mods[undstart];            
+            let moddef = mods.mods[undstart];
             let obj = libs[moddef.lib].extract_module(moddef.modpage)?;
 
             let externs = pass1_obj_externs(&obj.data.unwrap())?;
             for ext in externs {
-                new_externs.insert(ext);
+                new_externs.push((ext, Referrer::Module(moddef)));
             }
 
             undstart += 1;
@@ -167,27 +238,69 @@ fn pass1_add_library_modules(state: &mut LinkState, libs: &[Library], objects: &
             break;
         }
         
-        und = new_externs.into_iter().map(|s| s.to_owned()).collect::<Vec<String>>();
+        und = new_externs;
     }
 
     mods.mods.sort();
 
-    for moddef in &mods.mods {
-        let mut obj = libs[moddef.lib].extract_module(moddef.modpage)?;
+    let first = objects.len();
+
+    let extracted = map_in_parallel(&mods.mods, jobs, |moddef| {
+        let obj = libs[moddef.lib].extract_module(moddef.modpage)?;
+        let scan = ModuleScan::new(obj.data.as_ref().unwrap());
+        Ok::<_, LinkerError>((obj, scan))
+    });
+
+    for (moddef, extracted) in mods.mods.iter().zip(extracted) {
+        let (mut obj, scan) = extracted?;
         let data = obj.data.take().unwrap();
         let name = &format!("{}@{:X}", libs[moddef.lib].name, moddef.modpage);
-        pass1_object(state, &data, &mut obj, name)?;
+        pass1_object(state, &data, scan, &mut obj, name)?;
         obj.data = Some(data);
         objects.push(obj);
     }
 
+    //
+    // Now that every library module has a place in the object list, record why
+    // each was linked.
+    //
+    for (i, moddef) in mods.mods.iter().enumerate() {
+        let (symbol, referrer) = reasons.remove(moddef).unwrap();
+
+        let referrer = match referrer {
+            Referrer::Object(objidx) => objidx,
+            Referrer::Module(module) => first + mods.mods.binary_search(&module).unwrap(),
+        };
+
+        objects[first + i].linked_by = Some(LinkReason { symbol, referrer });
+    }
+
+    Ok(())
+}
+
+/// A stack size on the command line replaces the size of the stack segment, before it
+/// is placed. The stack may not be made smaller than the data modules initialize in it.
+///
+fn pass1_size_stack(state: &mut LinkState, size: usize) -> Result<(), LinkerError> {
+    if let Some(seg) = state.stack_segment() {
+        let segment = &mut state.segments[seg];
+
+        if segment.has_data && size < segment.length {
+            return Err(LinkerError::new(&format!(
+                "stack size {:X}H is smaller than the initialized stack segment of {:X}H bytes.", size, segment.length
+            )));
+        }
+
+        segment.length = size;
+    }
+
     Ok(())
 }
 
 /// Once all object modules have been added, build the runtime memory map by placing all segments 
 /// in proper order and at proper alignment.
 /// 
-fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
+fn pass1_build_memory_map(state: &mut LinkState, bss_last: bool, script: Option<&LinkScript>) -> Result<(), LinkerError> {
     let mut order = Vec::new();
     let mut placed: Vec<bool> = (0..=state.segments.len()).map(|_| false).collect();
 
@@ -195,6 +308,13 @@ fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
     // Compute the new order.
     //
     for (index, seg) in state.segments.iter().enumerate().map(|(i, seg) | (i+1, seg)) {
+        //
+        // Debug information is not part of the memory image.
+        //
+        if seg.debug.is_some() {
+            continue;
+        }
+
         if !placed[index] {
             let class = seg.name.classidx;
 
@@ -209,7 +329,7 @@ fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
                 // Segments with a class get added, with all of the other segments of the class
                 // following.
                 //
-                for index  in state.segments.iter().enumerate().filter(|(_, seg)| seg.name.classidx == class).map(|(i, _) | i+1) {
+                for index  in state.segments.iter().enumerate().filter(|(_, seg)| seg.name.classidx == class && seg.debug.is_none()).map(|(i, _) | i+1) {
                     order.push(index);
                     placed[index] = true;            
                 }
@@ -218,17 +338,50 @@ fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
     }
 
     //
-    // Assign linear base addresses.
+    // A script may put some classes first.
     //
-    let mut next_base = 0;
+    if let Some(script) = script {
+        order.sort_by_key(|index| script.class_rank(state.lnames.get(state.segments[*index].name.classidx)));
+    }
 
-    for index in order.iter() {
-        let seg = &mut state.segments[*index];
+    pass1_assign_bases(state, &order, script)?;
 
-        next_base = seg.align.align_by(next_base);
-        seg.base = next_base;
-        next_base += seg.length;
-   }
+    //
+    // Optionally move uninitialized segments after all the initialized ones, so they are
+    // left out of the file and allocated by minalloc instead.
+    //
+    if bss_last {
+        let before = pass1_initialized_end(state, &order);
+
+        let (mut initialized, uninitialized): (Vec<usize>, Vec<usize>) = order.iter()
+            .partition(|index| !pass1_is_uninitialized(&state.segments[**index]));
+        initialized.extend(uninitialized);
+        order = initialized;
+
+        pass1_assign_bases(state, &order, script)?;
+
+        for group in state.groups.iter() {
+            let start = group.iter().map(|seg| state.segments[seg].base).min().unwrap_or(0);
+            let end = group.iter().map(|seg| state.segments[seg].base + state.segments[seg].length).max().unwrap_or(0);
+
+            if end - start > 0x10000 {
+                return Err(LinkerError::new(&format!("group {} is larger than 64K with uninitialized segments at the end of the image.", state.lnames.get(group.name))));
+            }
+        }
+
+        let after = pass1_initialized_end(state, &order);
+        println!("Moving uninitialized segments to the end of the image saved {} bytes.", before.saturating_sub(after));
+    }
+
+    //
+    // With a script, segments may be placed out of order, so the memory map is sorted
+    // by address, and checked for overlaps.
+    //
+    if let Some(script) = script {
+        order.sort_by_key(|index| state.segments[*index].base);
+        pass1_place_loads(state, &order, script)?;
+        pass1_check_overlaps(state, &order)?;
+    }
 
     state.segment_order = order;
 
@@ -247,6 +400,205 @@ fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
     Ok(())
 }
 
+/// Assign linear base addresses to segments in memory map order, placing them as the
+/// script, if any, says.
+///
+fn pass1_assign_bases(state: &mut LinkState, order: &[usize], script: Option<&LinkScript>) -> Result<(), LinkerError> {
+    let no_script = LinkScript::default();
+    let script = script.unwrap_or(&no_script);
+
+    let mut next_base = 0;
+    let mut region_next = script.regions.iter().map(|region| region.start).collect::<Vec<_>>();
+    let mut groups_placed = HashSet::new();
+
+    for index in order.iter() {
+        let seg = &state.segments[*index];
+        let name = state.lnames.get(seg.name.nameidx);
+        let class = state.lnames.get(seg.name.classidx);
+        let placement = script.placements.get(name);
+
+        //
+        // Segments in a region follow the last segment in that region; others follow the
+        // last segment not in a region.
+        //
+        let region = placement.and_then(|placement| placement.region.as_deref())
+            .or_else(|| script.placements.get(class).and_then(|placement| placement.region.as_deref()))
+            .and_then(|region| script.regions.iter().position(|r| r.name == region));
+
+        let mut base = region.map(|region| region_next[region]).unwrap_or(next_base);
+
+        if let Some(placement) = placement {
+            base += placement.pad;
+
+            if let Some(align) = placement.align {
+                base = base.next_multiple_of(align);
+            }
+        }
+
+        //
+        // A fixed address for a group places the group's first segment.
+        //
+        let group_at = if seg.group != 0 && groups_placed.insert(seg.group) {
+            script.placements.get(state.lnames.get(state.groups[seg.group].name)).and_then(|placement| placement.at)
+        } else {
+            None
+        };
+
+        if let Some(para) = placement.and_then(|placement| placement.at).or(group_at) {
+            if para << 4 < base {
+                return Err(LinkerError::new(&format!("cannot place segment {} at paragraph {:04X}H, below {:05X}H where it would otherwise start.", name, para, base)));
+            }
+
+            base = para << 4;
+        }
+
+        base = seg.align.align_by(base);
+        let end = base + seg.length;
+
+        if let Some(region) = region {
+            let region = &script.regions[region];
+
+            if end > region.start + region.size {
+                return Err(LinkerError::new(&format!("segment {} does not fit in region {}.", name, region.name)));
+            }
+        }
+
+        match region {
+            Some(region) => region_next[region] = end,
+            None => next_base = end,
+        }
+
+        state.segments[*index].base = base;
+    }
+
+    Ok(())
+}
+
+/// Place the initial data of segments the script loads elsewhere after the other segments
+/// in the region they are loaded in, and give the script's symbol for each its paragraph.
+///
+fn pass1_place_loads(state: &mut LinkState, order: &[usize], script: &LinkScript) -> Result<(), LinkerError> {
+    for load in script.loads.iter() {
+        let segment = order.iter()
+            .find(|index| state.lnames.get(state.segments[**index].name.nameidx) == load.segment)
+            .copied()
+            .ok_or_else(|| LinkerError::new(&format!("script loads segment {}, which is not in the program.", load.segment)))?;
+
+        let region = script.region(&load.region).unwrap();
+        let region_end = region.start + region.size;
+
+        let used = order.iter()
+            .map(|index| &state.segments[*index])
+            .filter(|seg| seg.base >= region.start && seg.base < region_end)
+            .map(|seg| seg.base + seg.length)
+            .chain(state.loads.iter().filter(|load| load.base >= region.start && load.base < region_end).map(|load| load.base + state.segments[load.segment].length))
+            .max()
+            .unwrap_or(region.start);
+
+        let base = used.next_multiple_of(16);
+
+        if base + state.segments[segment].length > region_end {
+            return Err(LinkerError::new(&format!("data for segment {} does not fit in region {}.", load.segment, region.name)));
+        }
+
+        if let Some(Symbol::Public(public)) = state.symbols.symbols.get_mut(&load.symbol) {
+            public.offset = (base >> 4) as u16;
+        }
+
+        state.loads.push(DataLoad { segment, base });
+    }
+
+    Ok(())
+}
+
+/// Check that no two segments, or the initial data loaded for them, overlap. `order` is
+/// sorted by address.
+///
+fn pass1_check_overlaps(state: &LinkState, order: &[usize]) -> Result<(), LinkerError> {
+    let mut ranges = order.iter()
+        .map(|index| (state.segments[*index].base, state.segments[*index].length, *index))
+        .chain(state.loads.iter().map(|load| (load.base, state.segments[load.segment].length, load.segment)))
+        .filter(|(_, length, _)| *length != 0)
+        .collect::<Vec<_>>();
+
+    ranges.sort();
+
+    for pair in ranges.windows(2) {
+        let (base, length, first) = pair[0];
+        let (next, _, second) = pair[1];
+
+        if base + length > next {
+            return Err(LinkerError::new(&format!("segments {} and {} overlap at {:05X}H.",
+                state.lnames.get(state.segments[first].name.nameidx),
+                state.lnames.get(state.segments[second].name.nameidx),
+                next)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Pack adjacent segments of the same class into shared frames, as /PACKCODE and
+/// /PACKDATA do, so that fixups refer to fewer frames. Code segments (those of a class
+/// ending in CODE) are packed up to `code_limit` bytes per frame, and others up to
+/// `data_limit`. Segments in groups, which have frames of their own, and stacks are
+/// not packed. Nothing moves; the later segments of each pack just take the frame of
+/// the first.
+///
+fn pass1_pack_segments(state: &mut LinkState, code_limit: Option<usize>, data_limit: Option<usize>) -> Result<(), LinkerError> {
+    if code_limit.into_iter().chain(data_limit).any(|limit| limit > 0x10000) {
+        return Err(LinkerError::new("segments can only be packed into frames of up to 64K."));
+    }
+
+    let mut pack = 0;
+
+    for segidx in state.segment_order.clone() {
+        let seg = &state.segments[segidx];
+        let classidx = seg.name.classidx;
+
+        let limit = if state.lnames.get(classidx).to_uppercase().ends_with("CODE") { code_limit } else { data_limit };
+
+        let Some(limit) = limit.filter(|_| seg.group == 0 && seg.combine != Combine::Stack) else {
+            pack = 0;
+            continue;
+        };
+
+        let fits = pack != 0 && {
+            let first = &state.segments[pack];
+            let frame = first.base & !0x000f;
+
+            first.name.classidx == classidx && seg.base >= first.base && seg.base + seg.length - frame <= limit
+        };
+
+        if fits {
+            state.segments[segidx].pack = pack;
+        } else {
+            pack = segidx;
+        }
+    }
+
+    Ok(())
+}
+
+/// A segment is uninitialized if no data records were given for it. This is usually so
+/// of class BSS and STACK, but a module may still initialize one, and then its data must
+/// be in the file.
+///
+fn pass1_is_uninitialized(seg: &Segment) -> bool {
+    !seg.has_data
+}
+
+/// The end of the last initialized segment, which is as far as the file must hold the image.
+///
+fn pass1_initialized_end(state: &LinkState, order: &[usize]) -> usize {
+    order.iter()
+        .map(|index| &state.segments[*index])
+        .filter(|seg| !pass1_is_uninitialized(seg))
+        .map(|seg| seg.base + seg.length)
+        .max()
+        .unwrap_or(0)
+}
+
 /// Borland tlink orders object modules in order of appearance in their containing libraries.
 /// To emulate this, we need to be able to pull out the non-local externs from each module,
 /// so we can build the dependency graph up front before we actually place any object modules
@@ -255,42 +607,62 @@ fn pass1_build_memory_map(state: &mut LinkState) -> Result<(), LinkerError> {
 fn pass1_obj_externs(data: &[u8]) -> Result<Vec<String>, LinkerError> {
     let mut start = 0;
     let mut externs = Vec::new();
+    let mut lnames = Vec::new();
+    let mut locals = HashSet::new();
 
     while start < data.len() {
         let mut rec = Record::new(&data[start..])?;
         let reclen = rec.total_length();
 
         //
-        // The extern collection contains names from EXTDEF, COMDEF, LEXTDEF, and LCOMDEF; we 
-        // only care about the first two because the latter two are expected to be resolved
-        // in the same object module.
+        // The extern collection contains names from EXTDEF, COMDEF, CEXTDEF, LEXTDEF, and
+        // LCOMDEF; we only care about the first three because the latter two are expected to
+        // be resolved in the same object module. CEXTDEF names are in LNAMES and LLNAMES.
         //
         match rec.rectype {
-            RecordType::EXTDEF => externs.extend_from_slice(&pass1_extdef_names(&mut rec)?[..]),
+            RecordType::EXTDEF => externs.extend_from_slice(&read_extdef_names(&mut rec)?[..]),
             RecordType::COMDEF => externs.extend_from_slice(&pass1_comdef_names(&mut rec)?[..]),
+            RecordType::LNAMES |
+            RecordType::LLNAMES => lnames.extend(read_lnames(&mut rec)?),
+            RecordType::CEXTDEF => externs.extend_from_slice(&pass1_cextdef_names(&mut rec, &lnames)?[..]),
+            RecordType::COMDAT |
+            RecordType::COMDAT32 => {
+                if let Some(nameidx) = local_comdat_nameidx(&mut rec)? {
+                    locals.extend(lnames.get(nameidx.wrapping_sub(1)).cloned());
+                }
+            },
             _ =>{},
         }
 
         start += reclen;
     }
 
+    //
+    // References to the module's local COMDATs are resolved in the module.
+    //
+    externs.retain(|name| !locals.contains(name));
+
     Ok(externs)
 }
 
-/// Parse an EXTDEF record, returning just the names without updating any data structures. 
-/// 
-fn pass1_extdef_names(rec: &mut Record) -> Result<Vec<String>, LinkerError> {
+/// Parse a CEXTDEF record, returning just the names without updating any data structures.
+/// `lnames` are the names from the module's LNAMES and LLNAMES records so far.
+///
+fn pass1_cextdef_names(rec: &mut Record, lnames: &[String]) -> Result<Vec<String>, LinkerError> {
     let mut names = Vec::new();
 
     while !rec.end() {
-        let name = rec.counted_string()?;
+        let nameidx = rec.index()?;
 
         //
         // there is an unused type index after every name.
         //
         rec.index()?;
 
-        names.push(name);
+        match lnames.get(nameidx.wrapping_sub(1)) {
+            Some(name) => names.push(name.clone()),
+            None => return Err(LinkerError::new(&format!("invalid name index {} in CEXTDEF", nameidx))),
+        }
     }
 
     Ok(names)
@@ -349,15 +721,8 @@ fn pass1_theadr(obj: &mut Object, rec: &mut Record) -> Result<(), LinkerError> {
 // Handle an EXTDEF record, which maps an index in the object module to a symbol
 // name to be resolved elsewhere.
 //
-fn pass1_extdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
-    while !rec.end() {
-        let name = rec.counted_string()?;
-
-        //
-        // there is an unused type index after every name.
-        //
-        rec.index()?;
-
+fn pass1_extdef(obj: &mut Object, state: &mut LinkState, names: Vec<String>) -> Result<(), LinkerError> {
+    for name in names {
         //
         // Put the symbol in the symbol table, if it isn't already there,
         // as an undefined reference.
@@ -368,7 +733,33 @@ fn pass1_extdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
         //
         // The name goes in the object's external definitions.
         //
-        obj.extdefs.add(name.clone());
+        obj.extdefs.add(name);
+    }
+
+    Ok(())
+}
+
+// Handle a CEXTDEF record, which is an EXTDEF for COMDAT symbols, naming them by
+// their index in LNAMES and LLNAMES.
+//
+fn pass1_cextdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
+    while !rec.end() {
+        let nameidx = rec.index()?;
+
+        //
+        // there is an unused type index after every name.
+        //
+        rec.index()?;
+
+        if nameidx == 0 || !obj.lnames.is_valid_index(nameidx) {
+            return Err(LinkerError::new(&format!("invalid name index {} in CEXTDEF", nameidx)));
+        }
+
+        //
+        // The name may be of a local COMDAT of the module, so it is only entered in the
+        // symbol table once the module's COMDATs are known.
+        //
+        obj.extdefs.add(state.lnames.get(obj.lnames.get(nameidx)).to_owned());
     }
 
     Ok(())
@@ -376,10 +767,8 @@ fn pass1_extdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
 
 // Handle a PUBDEF record, which defines a symbol with an offset in a segment and/or group.
 //
-fn pass1_pubdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
-    let group = rec.index()?;
-    let segment = rec.index()?;
-    let baseframe = if segment == 0 { rec.word()? } else { 0 };
+fn pass1_pubdef(obj: &mut Object, state: &mut LinkState, pubdef: &PubDefRecord) -> Result<(), LinkerError> {
+    let PubDefRecord { group, segment, frame: baseframe, .. } = *pubdef;
 
     if !obj.grpdefs.is_valid_index(group) {
         println!("grpdefs {:?}", obj.grpdefs);
@@ -403,9 +792,8 @@ fn pass1_pubdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
         (segdef.base, segdef.length, segdef.segidx)
     };
 
-    while !rec.end() {
-        let name = rec.counted_string()?;
-        let offset = rec.word()?;
+    for (name, offset) in pubdef.publics.iter() {
+        let offset = *offset;
 
         let segoffs = if segment == 0 {
             offset
@@ -423,13 +811,254 @@ fn pass1_pubdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
             segoffs as u16
         };
 
+        let symbol = Symbol::public(group, segment, baseframe, segoffs);
+        state.symbols.update(name, symbol)?;
+    }
+
+    Ok(())
+}
+
+/// Handle a COMENT record. The comments the linker uses are Borland's: source file
+/// comments, which name the file that following LINNUM records refer to, and the debug
+/// comments describing types, scopes and local symbols for the Turbo Debugger table.
+///
+fn pass1_coment(obj: &mut Object, rec: &mut Record) -> Result<(), LinkerError> {
+    const CLASS_BORLAND_SOURCE_FILE: u8 = 0xe8;
+
+    if rec.end() {
+        return Ok(());
+    }
+
+    let _attrib = rec.byte()?;
+    let class = rec.byte()?;
+
+    if class == CLASS_BORLAND_SOURCE_FILE {
+        let _index = rec.byte()?;
+        let name = rec.counted_string()?;
+        let timestamp = rec.dword()?;
+
+        obj.current_source = match obj.source_files.iter().position(|file| file.name == name) {
+            Some(index) => index + 1,
+            None => obj.source_files.add(SourceFile { name, timestamp }),
+        };
+    } else if let Some(debug) = pass1_borland_debug(obj, rec, class)? {
+        obj.borland_debug.push(debug);
+    }
+
+    Ok(())
+}
+
+/// Decode a Borland debug comment, if `class` is one the Turbo Debugger table uses:
+///
+///     E1H public type     name, type index
+///     E2H member          name, type index, offset
+///     E3H type            type index, name, size, TD type id, index of the type referred to
+///     E4H enum member     name, value
+///     E5H begin scope     SEGDEF index, offset
+///     E6H locals          for each: name, type index, TD class, then a SEGDEF index and
+///                         offset for a static, or a value for any other class
+///     E7H end scope       offset
+///
+/// Names are counted strings, and offsets and values words.
+///
+fn pass1_borland_debug(obj: &Object, rec: &mut Record, class: u8) -> Result<Option<BorlandDebug>, LinkerError> {
+    let segment = |rec: &mut Record| -> Result<usize, LinkerError> {
+        let segment = rec.index()?;
+
+        if !obj.segdefs.is_valid_index(segment) {
+            return Err(LinkerError::new(&format!("invalid segment index {} in Borland debug comment", segment)));
+        }
+
+        Ok(segment)
+    };
+
+    let debug = match class {
+        0xe1 => BorlandDebug::PublicType { name: rec.counted_string()?, typeidx: rec.index()? },
+        0xe2 => BorlandDebug::Member { name: rec.counted_string()?, typeidx: rec.index()?, offset: rec.word()? },
+        0xe3 => BorlandDebug::Type {
+            typeidx: rec.index()?,
+            name: rec.counted_string()?,
+            size: rec.word()?,
+            id: rec.byte()?,
+            reference: rec.index()?,
+        },
+        0xe4 => BorlandDebug::Member { name: rec.counted_string()?, typeidx: 0, offset: rec.word()? },
+        0xe5 => BorlandDebug::BeginScope { segment: segment(rec)?, offset: rec.word()? },
+        0xe6 => {
+            let mut locals = Vec::new();
+
+            while !rec.end() {
+                let name = rec.counted_string()?;
+                let typeidx = rec.index()?;
+                let class = rec.byte()?;
+                let segment = if class == tdinfo::SYMBOL_STATIC { segment(rec)? } else { 0 };
+                let value = rec.word()?;
+
+                locals.push(BorlandLocal { name, typeidx, class, segment, value });
+            }
+
+            BorlandDebug::Locals(locals)
+        },
+        0xe7 => BorlandDebug::EndScope { offset: rec.word()? },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(debug))
+}
+
+/// Handle a LINNUM record, which maps source line numbers to offsets in a segment. The offsets
+/// are made relative to the linker-level segment, so they can be relocated once the memory
+/// map is built. `is32` selects the form with 32-bit offsets.
+///
+fn pass1_linnum(obj: &mut Object, rec: &mut Record, is32: bool) -> Result<(), LinkerError> {
+    let group = rec.index()?;
+    let segment = rec.index()?;
+
+    if !obj.grpdefs.is_valid_index(group) {
+        return Err(LinkerError::new(
+            &format!("invalid group index {} in LINNUM", group)
+        ));
+    }
+
+    if !obj.segdefs.is_valid_index(segment) {
+        return Err(LinkerError::new(
+            &format!("invalid segment index {} in LINNUM", segment)
+        ));
+    }
+
+    let group = obj.grpdefs.get(group);
+    let segdef = &obj.segdefs[segment];
+    let (segidx, segbase, seglen) = (segdef.segidx, segdef.base, segdef.length);
+
+    let mut lines = Vec::new();
+
+    while !rec.end() {
+        let line = rec.word()?;
+        let offset = if is32 { rec.dword()? as usize } else { rec.word()? as usize };
+
+        if offset > seglen {
+            return Err(LinkerError::new(&format!("line {} offset {:04X}H is outside of SEGDEF.", line, offset)));
+        }
+
+        lines.push(LineNumber { line, offset: segbase + offset });
+    }
+
+    //
+    // Compilers may split the line numbers for one segment over several records.
+    //
+    let source = obj.current_source;
+
+    match obj.linnums.iter_mut().find(|linnums| linnums.segidx == segidx && linnums.source == source) {
+        Some(linnums) => linnums.lines.extend(lines),
+        None => obj.linnums.push(LineNumbers { segidx, source, group, lines }),
+    }
+
+    Ok(())
+}
+
+/// Note which segment an LEDATA or LIDATA record gives data for. The data itself is for
+/// pass 2.
+///
+fn pass1_data(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
+    let segment = rec.index()?;
+
+    if !obj.segdefs.is_valid_index(segment) {
+        return Err(LinkerError::new(&format!("invalid segment index {} in data record", segment)));
+    }
+
+    state.segments[obj.segdefs[segment].segidx].has_data = true;
+    Ok(())
+}
+
+/// Handle a COMDAT record, collecting the COMDAT's data. Which module's definition of
+/// each COMDAT is kept is decided once the whole module has been read.
+///
+fn pass1_comdat(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
+    let header = ComdatHeader::read(rec, obj, state)?;
+
+    let data = if header.iterated {
+        let mut data = Vec::new();
+
+        while !rec.end() {
+            accum_lidata(rec, &mut data)?;
+        }
+
+        data
+    } else {
+        rec.rest().to_vec()
+    };
+
+    if header.offset + data.len() > 0x10000 {
+        return Err(LinkerError::new(&format!("COMDAT {} is larger than 64K.", header.name)));
+    }
+
+    let index = match obj.comdats.iter().position(|comdat| comdat.name == header.name) {
+        Some(index) => index,
+        None => {
+            obj.comdats.push(Comdat::new(&header));
+            obj.comdats.len() - 1
+        },
+    };
+
+    obj.comdats[index].add_data(header.offset, &data);
+
+    Ok(())
+}
+
+/// Choose between a module's COMDATs and those of earlier modules. Each COMDAT that is
+/// kept gets a SEGDEF of its own in the segment it is allocated to, and defines its
+/// symbol at the start of it.
+///
+fn pass1_select_comdats(obj: &mut Object, state: &mut LinkState) -> Result<(), LinkerError> {
+    for index in 0..obj.comdats.len() {
+        let comdat = &obj.comdats[index];
+
+        if !comdat.local {
+            if let Some(chosen) = state.comdats.get(&comdat.name) {
+                chosen.check_duplicate(comdat)?;
+                continue;
+            }
+        }
+
+        let (segidx, align, acbp) = match comdat.allocation {
+            Allocation::Explicit => {
+                let segdef = &obj.segdefs[comdat.segment];
+                let align = comdat.align.unwrap_or(segdef.align);
+                (segdef.segidx, align, align.to_acbp() | (segdef.acbp & 0x1f))
+            },
+            allocation => {
+                let align = comdat.align.unwrap_or(Align::Byte);
+                (allocation_segment(state, allocation), align, align.to_acbp() | 0x08)
+            },
+        };
+
+        let segment = &mut state.segments[segidx];
+        let mut segdef = SegDef::new(segidx, comdat.data.len(), acbp, align, segment.combine);
+        segdef.base = segment.add_segdef(&segdef)?;
+        segment.has_data = true;
+
         //
-        // There is an unused type index after each symbol.
+        // A local COMDAT, as for a static function, is known only to its own module.
         //
-        rec.index()?;
+        let symbol = Symbol::public(comdat.group, segidx, 0, segdef.base as u16);
 
-        let symbol = Symbol::public(group, segment, baseframe, segoffs);
-        state.symbols.update(&name, symbol)?;
+        if comdat.local {
+            obj.local_symbols.insert(comdat.name.clone(), symbol);
+        } else {
+            state.symbols.update(&comdat.name, symbol)?;
+            state.comdats.insert(comdat.name.clone(), comdat.clone());
+        }
+
+        obj.comdats[index].segdef = Some(obj.segdefs.add(segdef));
+    }
+
+    //
+    // Externals which are not the module's own local COMDATs are resolved elsewhere.
+    //
+    for extidx in 1..=obj.extdefs.len() {
+        if !obj.local_symbols.contains_key(&obj.extdefs[extidx]) {
+            state.symbols.update(&obj.extdefs[extidx], Symbol::Undefined)?;
+        }
     }
 
     Ok(())
@@ -439,44 +1068,26 @@ fn pass1_pubdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
 /// stored in a global table, and each object contains a map from the object-based
 /// index of the name to its index in the global table.
 /// 
-fn pass1_lnames(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
-    while !rec.end() {
-        let lname = rec.counted_string()?;
-        let index = state.lnames.find_or_add(&lname);
+fn pass1_lnames(obj: &mut Object, state: &mut LinkState, names: &[String]) {
+    for lname in names {
+        let index = state.lnames.find_or_add(lname);
         obj.lnames.add(index);
     }
-    
-    Ok(())
 }
 
 /// Process a SEGDEF record. Complete segments are held at the linker level, and object modules
 /// contain the bounds of the segment owned by the module.
 /// 
-fn pass1_segdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Result<(), LinkerError> {
-    let acbp = rec.byte()?;
-
-    let align = Align::from_acbp(acbp)?;
-    let combine = Combine::from_acbp(acbp)?;
+fn pass1_segdef(obj: &mut Object, state: &mut LinkState, segdef: &SegDefRecord) -> Result<(), LinkerError> {
+    //
+    // Name indices are in the object file's lnames table.
+    //
+    let SegDefRecord { acbp, align, combine, length, nameidx, classidx, ovlyidx } = *segdef;
 
     if align == Align::Absolute {
-        //
-        // We do not support it, but if align is Absolute, there are an absolute frame and offset.
-        //
-        let _frame = rec.word()?;
-        let _offset = rec.byte()?;
-
         eprintln!("warning: segment has unsupported absolute aligment in module {}.", obj.name);
     }
 
-    let length = rec.word()?;
-
-    //
-    // Name indices in the object file's lnames table.
-    //
-    let nameidx = rec.index()?;
-    let classidx = rec.index()?;
-    let ovlyidx = rec.index()?;
-
     if !(obj.lnames.is_valid_index(nameidx) && obj.lnames.is_valid_index(classidx) && obj.lnames.is_valid_index(ovlyidx)) {
         return Err(LinkerError::new(
             &format!("invalid name triplet {}.{}.{} for SEGDEF", nameidx, classidx, ovlyidx)
@@ -502,19 +1113,27 @@ fn pass1_segdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
         length as usize
     };
 
+    let debug = DebugInfo::from_names(state.lnames.get(nameidx), state.lnames.get(classidx));
+
     //
     // Get or add the linker-level segment.
     //
     let index = if let Some(index) = state.get_segment_named(&segname) {
         index
     } else {
-        let segment = Segment::new(segname, 0, align, combine);
+        let mut segment = Segment::new(segname, 0, align, combine);
+        segment.debug = debug;
         state.segments.add(segment)
     };
 
     let mut segdef = SegDef::new(index, length, acbp, align, combine);
 
-    segdef.base = state.segments[index].add_segdef(&segdef)?;
+    //
+    // Debug segments are not combined; each module's data is kept with the module.
+    //
+    if debug.is_none() {
+        segdef.base = state.segments[index].add_segdef(&segdef)?;
+    }
 
     
     obj.segdefs.add(segdef);
@@ -585,33 +1204,48 @@ fn pass1_grpdef(obj: &mut Object, state: &mut LinkState, rec: &mut Record) -> Re
     Ok(())
 }
 
-/// Parse one object file in the context of pass 1. 
+/// Parse one object file in the context of pass 1, merging the tables from the scan of
+/// its records into the link.
 ///
-fn pass1_object(state: &mut LinkState, data: &[u8], obj: &mut Object, name: &str) -> Result<(), LinkerError> {
-    let mut start = 0;
-
-    while start < data.len() {
-        let mut rec = Record::new(&data[start..])?;
-        let reclen = rec.total_length();
-
-        let result = match rec.rectype {
-            RecordType::THEADR => pass1_theadr(obj, &mut rec),
-            RecordType::EXTDEF => pass1_extdef(obj, state, &mut rec),
-            RecordType::COMENT => Ok(()),
-            RecordType::PUBDEF => pass1_pubdef(obj, state, &mut rec),
-            RecordType::LNAMES => pass1_lnames(obj, state, &mut rec),
-            RecordType::SEGDEF => pass1_segdef(obj, state, &mut rec),
-            RecordType::GRPDEF => pass1_grpdef(obj, state, &mut rec),
-            RecordType::MODEND => break,
-            
-            //
-            // These records are for pass 2.
-            //
-            RecordType::LEDATA |
-            RecordType::LIDATA |
-            RecordType::FIXUPP => Ok(()),
-
-            _ => Err(LinkerError::new(&format!("unhandled record {:?}", rec.rectype))),
+pub fn pass1_object(state: &mut LinkState, data: &[u8], scan: ModuleScan, obj: &mut Object, name: &str) -> Result<(), LinkerError> {
+    for ScannedRecord { start, rectype, scanned } in scan.records {
+        let result = match scanned {
+            Scanned::Error(err) => Err(err),
+            Scanned::Names(names) if rectype == Some(RecordType::EXTDEF) => pass1_extdef(obj, state, names),
+            Scanned::Names(names) => {
+                pass1_lnames(obj, state, &names);
+                Ok(())
+            },
+            Scanned::SegDef(segdef) => pass1_segdef(obj, state, &segdef),
+            Scanned::PubDef(pubdef) => pass1_pubdef(obj, state, &pubdef),
+            Scanned::Other => {
+                let mut rec = Record::new(&data[start..])?;
+
+                match rec.rectype {
+                    RecordType::THEADR => pass1_theadr(obj, &mut rec),
+                    RecordType::COMENT => pass1_coment(obj, &mut rec),
+                    RecordType::LINNUM => pass1_linnum(obj, &mut rec, false),
+                    RecordType::LINNUM32 => pass1_linnum(obj, &mut rec, true),
+                    RecordType::CEXTDEF => pass1_cextdef(obj, state, &mut rec),
+                    RecordType::COMDAT |
+                    RecordType::COMDAT32 => pass1_comdat(obj, state, &mut rec),
+                    RecordType::GRPDEF => pass1_grpdef(obj, state, &mut rec),
+                    RecordType::LEDATA |
+                    RecordType::LIDATA => pass1_data(obj, state, &mut rec),
+                    RecordType::MODEND => break,
+
+                    //
+                    // These records are for pass 2.
+                    //
+                    RecordType::FIXUPP |
+                    RecordType::BAKPAT |
+                    RecordType::BAKPAT32 |
+                    RecordType::NBKPAT |
+                    RecordType::NBKPAT32 => Ok(()),
+
+                    _ => Err(LinkerError::new(&format!("unhandled record {:?}", rec.rectype))),
+                }
+            },
         };
 
         match result { 
@@ -624,17 +1258,17 @@ fn pass1_object(state: &mut LinkState, data: &[u8], obj: &mut Object, name: &str
             },
             Ok(_) => {},
         };
-
-        start += reclen;
     }
 
-    Ok(())
+    pass1_select_comdats(obj, state)
+        .map_err(|err| LinkerError::new(&format!("pass1: module {}: {}", name, err)))
 }
 
 #[cfg(test)]
 mod test {
     use crate::group::Group;
     use crate::symbols::Symbol;
+    use crate::testlib::get_testlib;
 
     use super::*;
 
@@ -671,7 +1305,7 @@ mod test {
         //
         state.lnames.add("XYZ");
 
-        pass1_lnames(&mut obj, &mut state, &mut rec)?;
+        pass1_lnames(&mut obj, &mut state, &read_lnames(&mut rec)?);
 
         assert_eq!(obj.lnames.len(), 2);
         assert_eq!(obj.lnames.get(1), 2);
@@ -705,7 +1339,7 @@ mod test {
         //
         state.segments.push(seg);
 
-        pass1_segdef(&mut obj, &mut state, &mut rec)?;
+        pass1_segdef(&mut obj, &mut state, &SegDefRecord::read(&mut rec)?)?;
 
         assert_eq!(state.segments.len(), 2);
         assert_eq!(obj.segdefs.len(), 1);
@@ -750,7 +1384,7 @@ mod test {
         //
         state.segments.push(seg);
 
-        pass1_segdef(&mut obj, &mut state, &mut rec)?;
+        pass1_segdef(&mut obj, &mut state, &SegDefRecord::read(&mut rec)?)?;
 
         assert_eq!(state.segments.len(), 2);
         assert_eq!(obj.segdefs.len(), 1);
@@ -877,7 +1511,7 @@ mod test {
         let mut obj = Object::new();
         let mut state: LinkState = LinkState::new();
 
-        pass1_extdef(&mut obj, &mut state, &mut rec)?;
+        pass1_extdef(&mut obj, &mut state, read_extdef_names(&mut rec)?)?;
 
         assert_eq!(obj.extdefs.len(), 2);
         assert_eq!(obj.extdefs[1], "ABC");
@@ -937,7 +1571,7 @@ mod test {
         let segidx = state.segments.add(segment);
         obj.segdefs.add(SegDef::new(segidx, 0x6000, acbp, Align::Byte, Combine::Public));
 
-        pass1_pubdef(&mut obj, &mut state, &mut rec)?;
+        pass1_pubdef(&mut obj, &mut state, &PubDefRecord::read(&mut rec)?)?;
 
         let symbol = state.symbols.symbols.get("ABC");
 
@@ -968,4 +1602,350 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn library_modules_record_why_linked() -> Result<(), LinkerError> {
+        let data = [
+            0x80, 0x06, 0x00, 0x04, 0x4d, 0x41, 0x49, 0x4e, 0x00,   // THEADR MAIN
+            0x8c, 0x06, 0x00, 0x03, 0x46, 0x4f, 0x4f, 0x00, 0x00,   // EXTDEF FOO
+            0x8a, 0x02, 0x00, 0x00, 0x00,                           // MODEND
+        ];
+
+        let mut state = LinkState::new();
+        let mut obj = Object::from_bytes(data.to_vec());
+        pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, "main.obj")?;
+
+        let mut objects = vec![obj];
+        let libs = [Library::from_data(get_testlib(), "testlib")?];
+
+        pass1_add_library_modules(&mut state, &libs, &mut objects, 1)?;
+
+        assert_eq!(objects.len(), 2);
+        assert!(objects[0].linked_by.is_none());
+        assert_eq!(objects[1].display_name(), "MOD1.ASM(TESTLIB)");
+
+        let why = objects[1].linked_by.as_ref().unwrap();
+        assert_eq!(why.symbol, "FOO");
+        assert_eq!(why.referrer, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn linnum() -> Result<(), LinkerError> {
+        let rec = [
+            0x94, 0x0b, 0x00,
+            0x00,                           // base group index
+            0x01,                           // base segment index
+            0x03, 0x00, 0x10, 0x00,         // line 3 at 0010H
+            0x04, 0x00, 0x14, 0x00,         // line 4 at 0014H
+            0x00 ];
+        let mut rec = Record::new(&rec)?;
+
+        let mut obj = Object::new();
+        let mut segdef = SegDef::new(1, 0x20, 0x28, Align::Byte, Combine::Public);
+        segdef.base = 0x100;
+        obj.segdefs.add(segdef);
+
+        pass1_linnum(&mut obj, &mut rec, false)?;
+
+        assert_eq!(obj.linnums.len(), 1);
+        assert_eq!(obj.linnums[0].segidx, 1);
+        assert_eq!(obj.linnums[0].lines.len(), 2);
+        assert_eq!(obj.linnums[0].lines[1].line, 4);
+        assert_eq!(obj.linnums[0].lines[1].offset, 0x114);
+
+        //
+        // Offsets outside the segment are an error.
+        //
+        let rec = [ 0x94, 0x07, 0x00, 0x00, 0x01, 0x05, 0x00, 0x21, 0x00, 0x00 ];
+        let mut rec = Record::new(&rec)?;
+        assert!(pass1_linnum(&mut obj, &mut rec, false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn borland_source_file() -> Result<(), LinkerError> {
+        let mut obj = Object::new();
+        obj.name = "main.c".to_owned();
+        obj.segdefs.add(SegDef::new(1, 0x20, 0x28, Align::Byte, Combine::Public));
+
+        let linnum = [ 0x94, 0x07, 0x00, 0x00, 0x01, 0x03, 0x00, 0x10, 0x00, 0x00 ];
+
+        pass1_linnum(&mut obj, &mut Record::new(&linnum)?, false)?;
+
+        let coment = [
+            0x88, 0x0f, 0x00,
+            0x00, 0xe8,                     // attributes, class E8H
+            0x00,                           // index
+            0x06, b'd', b'e', b'f', b's', b'.', b'h',
+            0x21, 0x43, 0x65, 0x87,         // timestamp
+            0x00 ];
+
+        pass1_coment(&mut obj, &mut Record::new(&coment)?)?;
+        pass1_linnum(&mut obj, &mut Record::new(&linnum)?, false)?;
+
+        assert_eq!(obj.source_files.len(), 1);
+        assert_eq!(obj.source_files[1].timestamp, 0x87654321);
+        assert_eq!(obj.linnums.len(), 2);
+        assert_eq!(obj.line_source(&obj.linnums[0]), "main.c");
+        assert_eq!(obj.line_source(&obj.linnums[1]), "defs.h");
+
+        Ok(())
+    }
+
+    #[test]
+    fn borland_debug_comments() -> Result<(), LinkerError> {
+        let mut obj = Object::new();
+        obj.segdefs.add(SegDef::new(1, 0x20, 0x28, Align::Byte, Combine::Public));
+
+        let coment = |class: u8, body: &[u8]| [&[0x88, body.len() as u8 + 3, 0x00, 0x00, class][..], body, &[0x00]].concat();
+
+        let comments = [
+            coment(0xe3, &[0x01, 0x04, b'n', b'o', b'd', b'e', 0x02, 0x00, 0x1e, 0x00]),
+            coment(0xe2, &[0x04, b'n', b'e', b'x', b't', 0x02, 0x00, 0x00]),
+            coment(0xe5, &[0x01, 0x10, 0x00]),
+            coment(0xe6, &[0x01, b'i', 0x00, 0x02, 0xfe, 0xff, 0x01, b's', 0x01, 0x00, 0x01, 0x08, 0x00]),
+            coment(0xe7, &[0x20, 0x00]),
+            coment(0xea, &[0x01]),
+        ];
+
+        for comment in comments.iter() {
+            pass1_coment(&mut obj, &mut Record::new(comment)?)?;
+        }
+
+        assert_eq!(obj.borland_debug.len(), 5);
+        assert!(matches!(&obj.borland_debug[0], BorlandDebug::Type { typeidx: 1, name, size: 2, id: 0x1e, reference: 0 } if name == "node"));
+        assert!(matches!(&obj.borland_debug[1], BorlandDebug::Member { name, typeidx: 2, offset: 0 } if name == "next"));
+        assert!(matches!(obj.borland_debug[2], BorlandDebug::BeginScope { segment: 1, offset: 0x10 }));
+        assert!(matches!(obj.borland_debug[4], BorlandDebug::EndScope { offset: 0x20 }));
+
+        let BorlandDebug::Locals(locals) = &obj.borland_debug[3] else { panic!("expected locals") };
+        assert_eq!(locals.iter().map(|local| (local.name.as_str(), local.class, local.segment, local.value)).collect::<Vec<_>>(),
+            [("i", 2, 0, 0xfffe), ("s", tdinfo::SYMBOL_STATIC, 1, 8)]);
+
+        //
+        // A scope must be in one of the module's SEGDEFs.
+        //
+        assert!(pass1_coment(&mut obj, &mut Record::new(&coment(0xe5, &[0x02, 0x00, 0x00]))?)
+            .is_err_and(|err| err.to_string() == "invalid segment index 2 in Borland debug comment"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_size() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let stack = state.lnames.add("STACK");
+        state.segments.add(Segment::new(SegName::new(0, stack, 0), 0x200, Align::Para, Combine::Stack));
+
+        pass1_size_stack(&mut state, 0x100)?;
+        assert_eq!(state.segments[1].length, 0x100);
+
+        //
+        // A stack initialized as by DB 100H DUP('STACK') may only grow.
+        //
+        state.segments[1].length = 0x500;
+        state.segments[1].has_data = true;
+
+        assert!(pass1_size_stack(&mut state, 0x100).is_err_and(|err|
+            err.to_string() == "stack size 100H is smaller than the initialized stack segment of 500H bytes."));
+        assert_eq!(state.segments[1].length, 0x500);
+
+        pass1_size_stack(&mut state, 0x800)?;
+        assert_eq!(state.segments[1].length, 0x800);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bss_last() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let code = state.lnames.add("CODE");
+        let bss = state.lnames.add("BSS");
+        let far_data = state.lnames.add("FAR_DATA");
+
+        let mut text = Segment::new(SegName::new(0, code, 0), 0x10, Align::Byte, Combine::Public);
+        text.has_data = true;
+        state.segments.add(text);
+        state.segments.add(Segment::new(SegName::new(0, bss, 0), 0x100, Align::Para, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(0, far_data, 0), 0x20, Align::Para, Combine::Public));
+        let mut data = Segment::new(SegName::new(0, far_data, 0), 0x30, Align::Para, Combine::Public);
+        data.has_data = true;
+        state.segments.add(data);
+
+        pass1_build_memory_map(&mut state, false, None)?;
+        assert_eq!(state.segment_order, vec![1, 2, 3, 4]);
+        assert_eq!(pass1_initialized_end(&state, &state.segment_order), 0x160);
+
+        pass1_build_memory_map(&mut state, true, None)?;
+        assert_eq!(state.segment_order, vec![1, 4, 2, 3]);
+        assert_eq!(state.segments[4].base, 0x10);
+        assert_eq!(state.segments[2].base, 0x40);
+        assert_eq!(pass1_initialized_end(&state, &state.segment_order), 0x40);
+
+        //
+        // A BSS segment which a module initializes stays where it is.
+        //
+        state.segments[2].has_data = true;
+        pass1_build_memory_map(&mut state, true, None)?;
+        assert_eq!(state.segment_order, vec![1, 2, 4, 3]);
+        assert_eq!(pass1_initialized_end(&state, &state.segment_order), 0x140);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pack_segments() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let code = state.lnames.add("CODE");
+        let far_data = state.lnames.add("FAR_DATA");
+
+        state.segments.add(Segment::new(SegName::new(0, code, 0), 0x12, Align::Byte, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(0, code, 0), 0x04, Align::Para, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(0, far_data, 0), 0x20, Align::Para, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(0, code, 0), 0x8000, Align::Para, Combine::Public));
+
+        pass1_build_memory_map(&mut state, false, None)?;
+        assert_eq!(state.segment_order, vec![1, 2, 4, 3]);
+
+        pass1_pack_segments(&mut state, Some(0x8000), None)?;
+        assert_eq!(state.segments[2].pack, 1);
+        assert_eq!(state.segments[4].pack, 0);
+        assert_eq!(state.segments[3].pack, 0);
+        assert_eq!(state.segment_frame_base(2), 0);
+        assert_eq!(state.segment_frame_base(4), 0x30);
+
+        assert!(pass1_pack_segments(&mut state, Some(0x10001), None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_memory_map() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let names = ["_TEXT", "CODE", "_DATA", "DATA", "_BSS", "BSS"].map(|name| state.lnames.add(name));
+
+        for (i, length) in [0x10, 0x08, 0x20].into_iter().enumerate() {
+            let mut seg = Segment::new(SegName::new(names[i * 2], names[i * 2 + 1], 0), length, Align::Byte, Combine::Public);
+            seg.has_data = i != 2;
+            state.segments.add(seg);
+        }
+
+        state.symbols.update("DATA_LOAD", Symbol::public(0, 0, 0, 0))?;
+
+        let script = LinkScript::parse("
+            order DATA CODE
+            region ROM 0x100 0x1000
+            place CODE in ROM
+            at _BSS 0x10
+            pad _DATA 4
+            load _DATA in ROM as DATA_LOAD
+        ")?;
+
+        pass1_build_memory_map(&mut state, false, Some(&script))?;
+
+        assert_eq!(state.segment_order, vec![2, 3, 1]);
+        assert_eq!(state.segments[2].base, 0x4);
+        assert_eq!(state.segments[3].base, 0x100);
+        assert_eq!(state.segments[1].base, 0x1000);
+
+        assert_eq!(state.loads[0].segment, 2);
+        assert_eq!(state.loads[0].base, 0x1010);
+        assert_eq!(state.symbols.symbols["DATA_LOAD"], Symbol::public(0, 0, 0, 0x101));
+
+        let script = LinkScript::parse("pad _DATA 0x20\nat _BSS 1")?;
+        state.loads.clear();
+        assert!(pass1_build_memory_map(&mut state, false, Some(&script)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn comdats() -> Result<(), LinkerError> {
+        //
+        // LLNAMES _inl, then _inl as far code in two records: mov ax,1; retf.
+        //
+        let llnames = [0xca, 0x06, 0x00, 0x04, 0x5f, 0x69, 0x6e, 0x6c, 0x8a];
+        let pick_any = [
+            0xc2, 0x0b, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x01, 0xb8, 0x01, 0x00, 0x68,
+            0xc2, 0x09, 0x00, 0x01, 0x11, 0x00, 0x03, 0x00, 0x00, 0x01, 0xcb, 0x54,
+        ];
+        let no_match = [0xc2, 0x09, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0xcb, 0x68];
+        let modend = [0x8a, 0x02, 0x00, 0x00, 0x74];
+
+        let mut state = LinkState::new();
+        let mut objects = Vec::new();
+
+        for _ in 0..2 {
+            let data = [&llnames[..], &pick_any, &modend].concat();
+            let mut obj = Object::from_bytes(data.clone());
+            pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, "test")?;
+            objects.push(obj);
+        }
+
+        let segdef = objects[0].comdat_segdef("_inl").unwrap();
+        assert_eq!(objects[0].segdefs[segdef].length, 4);
+        assert_eq!(objects[1].comdat_segdef("_inl"), None);
+
+        let segment = &state.segments[objects[0].segdefs[segdef].segidx];
+        assert_eq!(state.lnames.get(segment.name.nameidx), "COMDAT_CODE");
+        assert_eq!(segment.length, 4);
+        assert!(matches!(state.symbols.symbols.get("_inl"), Some(Symbol::Public(_))));
+
+        let mut state = LinkState::new();
+        let data = [&llnames[..], &no_match, &modend].concat();
+        pass1_object(&mut state, &data, ModuleScan::new(&data), &mut Object::from_bytes(data.clone()), "a")?;
+        assert!(pass1_object(&mut state, &data, ModuleScan::new(&data), &mut Object::from_bytes(data.clone()), "b")
+            .is_err_and(|err| err.to_string() == "pass1: module b: COMDAT _inl is multiply defined."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_comdats() -> Result<(), LinkerError> {
+        //
+        // Two modules each with a local _inl, retf, which they reference by CEXTDEF.
+        //
+        let llnames = [0xca, 0x06, 0x00, 0x04, 0x5f, 0x69, 0x6e, 0x6c, 0x8a];
+        let cextdef = [0xbc, 0x03, 0x00, 0x01, 0x00, 0x40];
+        let local = [0xc2, 0x09, 0x00, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0xcb, 0x64];
+        let modend = [0x8a, 0x02, 0x00, 0x00, 0x74];
+
+        let mut state = LinkState::new();
+        let mut objects = Vec::new();
+
+        for name in ["a", "b"] {
+            let data = [&llnames[..], &cextdef, &local, &modend].concat();
+            assert!(pass1_obj_externs(&data)?.is_empty());
+
+            let mut obj = Object::from_bytes(data.clone());
+            pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, name)?;
+            objects.push(obj);
+        }
+
+        let bases = objects.iter()
+            .map(|obj| {
+                assert!(matches!(obj.symbol(&state.symbols, "_inl"), Some(Symbol::Public(_))));
+                obj.segdefs[obj.comdat_segdef("_inl").unwrap()].base
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(bases, [0, 1]);
+        assert!(!state.symbols.symbols.contains_key("_inl"));
+
+        //
+        // Another module's external is not satisfied by either.
+        //
+        let extdef = [0x8c, 0x07, 0x00, 0x04, 0x5f, 0x69, 0x6e, 0x6c, 0x00, 0xc7];
+        let data = [&extdef[..], &modend].concat();
+        assert_eq!(pass1_obj_externs(&data)?, ["_inl"]);
+
+        let mut obj = Object::from_bytes(data.clone());
+        pass1_object(&mut state, &data, ModuleScan::new(&data), &mut obj, "c")?;
+        assert_eq!(obj.symbol(&state.symbols, "_inl"), Some(&Symbol::Undefined));
+
+        Ok(())
+    }
 }
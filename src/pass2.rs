@@ -1,20 +1,26 @@
-use crate::Args;
+use crate::{Args, OutputFormat};
+use crate::binfile;
+use crate::codeview;
+use crate::comdat::ComdatHeader;
+use crate::devdriver;
 use crate::dosexe::{DosExe, Relocation};
+use crate::hexfile;
 use crate::linker_error::LinkerError;
 use crate::linkstate::{FarPtr, LinkState};
 use crate::object::Object;
 use crate::record::{Record, RecordType};
-use crate::segment::SegName;
+use crate::segment::{Combine, DebugInfo};
 use crate::symbols::{Symbol};
+use crate::tdinfo;
 
-use std::cmp::max;
+use std::cmp::{max, min};
 
 //
 // Pass 2 logic
 //
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-enum TargetType {
+pub enum TargetType {
     SEGDEF,
     GRPDEF,
     EXTDEF,
@@ -48,7 +54,7 @@ impl TargetThread {
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-enum FrameType {
+pub enum FrameType {
     SEGDEF,
     GRPDEF,
     EXTDEF,
@@ -105,6 +111,114 @@ impl ThreadState {
             frame_threads
         }
     }
+
+    /// Process a thread subrecord of a FIXUPP.
+    /// 
+    pub fn read_thread(&mut self, rec: &mut Record, b0: u8) -> Result<(), LinkerError> {
+        let is_frame_thread = (b0 & 0x40) != 0;
+        let thread = (b0 & 0x03) as usize;
+
+        if is_frame_thread {
+            //
+            // Frame thread
+            //
+            let frame_type = FrameType::from_byte((b0 >> 2) & 0x07)?;
+
+            let frame_index = match frame_type {
+                FrameType::SEGDEF |
+                FrameType::GRPDEF |
+                FrameType::EXTDEF => rec.index()?,
+                _ => 0,
+            };
+
+            self.frame_threads[thread] = FrameThread{ frame_type: Some(frame_type), frame_index };
+        } else {
+            //
+            // Target thread
+            //
+            let target_type = TargetType::from_byte((b0 >> 2) & 0x03)?;
+
+            let target_index = match target_type {
+                TargetType::Frame => return Err(LinkerError::new("TargetType::Frame is not supported.")),
+                _ => rec.index()?,
+            };
+
+            self.target_threads[thread] = TargetThread{ target_type: Some(target_type), target_index };
+        }   
+
+        Ok(())
+    }
+
+    /// Read the frame and target of a fixup, from the fix data byte on, resolving
+    /// references to threads.
+    ///
+    pub fn read_fixup_data(&self, rec: &mut Record) -> Result<FixupData, LinkerError> {
+        let fixdat = rec.byte()?;
+        let is_frame_thread = (fixdat & 0x80) != 0;
+        let is_target_thread = (fixdat & 0x08) != 0;
+        let has_target_disp = (fixdat & 0x04) == 0;
+
+        //
+        // Frame data
+        //
+        let (frame_type, frame_index) = if is_frame_thread {
+            let threadidx = ((fixdat >> 4) & 0x03) as usize;
+            let thread = &self.frame_threads[threadidx];
+
+            match (thread.frame_type, thread.frame_index) {
+                (Some(frame_type), frame_index) => (frame_type, frame_index),
+                _ => return Err(LinkerError::new(&format!("use of undefined frame thread {}", threadidx))),
+            }
+        } else {
+            let frame_type = FrameType::from_byte((fixdat >> 4) & 0x07)?;
+
+            let frame_index = match frame_type {
+                FrameType::SEGDEF |
+                FrameType::GRPDEF |
+                FrameType::EXTDEF => rec.index()?,
+                FrameType::ExplicitFrame => rec.word()? as usize,
+                _ => 0,
+            };
+
+            (frame_type, frame_index)
+        };
+
+        //
+        // Target data 
+        //
+        let (target_type, target_index) = if is_target_thread {
+            let threadidx = (fixdat & 0x03) as usize;
+            let thread = &self.target_threads[threadidx];
+
+            match (thread.target_type, thread.target_index) {
+                (Some(target_type), target_index) => (target_type, target_index),
+                _ => return Err(LinkerError::new(&format!("use of undefined target thread {}", threadidx))),
+            }
+        } else {
+            let target_type = TargetType::from_byte(fixdat & 0x03)?;
+
+            let target_index = match target_type {
+                TargetType::Frame => return Err(LinkerError::new("TargetType::Frame is not supported.")),
+                _ => rec.index()?,
+            };
+
+            (target_type, target_index)
+        };
+
+        let target_disp = if has_target_disp { rec.word()? } else { 0 };
+
+        Ok(FixupData { frame_type, frame_index, target_type, target_index, target_disp })
+    }
+}
+
+/// The frame and target of a fixup, as given in the record.
+///
+pub struct FixupData {
+    pub frame_type: FrameType,
+    pub frame_index: usize,
+    pub target_type: TargetType,
+    pub target_index: usize,
+    pub target_disp: u16,
 }
 
 #[derive(Debug)]
@@ -127,10 +241,93 @@ impl Locat {
     }
 }
 
+/// The most recent LEDATA or LIDATA, which FIXUPP records apply to. For debug segments,
+/// `base` is the offset in the module's debug data rather than in the image. The data
+/// of a discarded SEGDEF is dropped, along with its fixups. For iterated data, `iterated`
+/// holds the offset in the record of the byte each byte of the expanded data came from.
+/// `code` is whether the data is in a segment of a code class.
+///
 struct LastDataRegion {
     frame: u16,
     base: usize,
     length: usize,
+    debug: Option<DebugInfo>,
+    discarded: bool,
+    iterated: Option<Vec<usize>>,
+    code: bool,
+}
+
+impl LastDataRegion {
+    /// Return the locations a fixup at `offset` in the data record applies to. As in MS
+    /// LINK, a fixup in iterated data is at an offset in the record's data blocks, and
+    /// applies to every copy of that byte in the expanded data.
+    ///
+    fn fixup_locations(&self, offset: usize) -> Result<Vec<usize>, LinkerError> {
+        let Some(iterated) = &self.iterated else {
+            return Ok(vec![self.base + offset]);
+        };
+
+        let locations: Vec<usize> = iterated.iter()
+            .enumerate()
+            .filter(|(_, source)| **source == offset)
+            .map(|(at, _)| self.base + at)
+            .collect();
+
+        if locations.is_empty() {
+            Err(LinkerError::new(&format!("fixup offset {:03X}H is not in the data of an LIDATA record.", offset)))
+        } else {
+            Ok(locations)
+        }
+    }
+}
+
+/// A value from a BAKPAT or NBKPAT record to add into the image once the module's data
+/// is in place.
+///
+struct BackPatch {
+    base: usize,
+    size: usize,
+    value: u32,
+}
+
+/// Which module first initialized each byte of the image, so that data from one module
+/// overwriting another's can be reported. An owner of zero means the byte is not yet
+/// initialized; otherwise it is one more than the index of the module in `modules`.
+///
+struct InitMap {
+    owners: Vec<u32>,
+    modules: Vec<String>,
+}
+
+impl InitMap {
+    fn new(size: usize) -> InitMap {
+        InitMap { owners: vec![0; size], modules: Vec::new() }
+    }
+
+    /// Start recording the data of the next module.
+    ///
+    fn start_module(&mut self, name: &str) {
+        self.modules.push(name.to_owned());
+    }
+
+    /// Record that the current module initialized `length` bytes at `base`. If another
+    /// module already initialized any of them, return the first such location and the
+    /// name of that module.
+    ///
+    fn initialize(&mut self, base: usize, length: usize) -> Option<(usize, &str)> {
+        let current = self.modules.len() as u32;
+        let mut overlap = None;
+
+        for (at, owner) in self.owners[base..base + length].iter_mut().enumerate() {
+            if *owner != 0 && *owner != current && overlap.is_none() {
+                overlap = Some((base + at, *owner));
+            }
+
+            *owner = current;
+        }
+
+        overlap.map(|(at, owner)| (at, self.modules[owner as usize - 1].as_str()))
+    }
 }
 
 /// Execute pass 2. 
@@ -138,82 +335,233 @@ struct LastDataRegion {
 /// - Build final executable.
 /// 
 pub fn pass2(state: &mut LinkState, objects: &mut Vec<Object>, args: &Args) -> Result<(), LinkerError> {
+    state.far_calls = args.far_call_translation.then_some(0);
+    state.fixup_errors = args.fixup_errors;
+    state.overlap_errors = args.overlap_errors;
+
     //
     // Allocate the memory image.
     //
     let lastseg = &state.segments[*state.segment_order.last().unwrap()];
-    let memsize = lastseg.base + lastseg.length;
+    let memsize = state.loads.iter()
+        .map(|load| load.base + state.segments[load.segment].length)
+        .fold(lastseg.base + lastseg.length, max);
     let mut image = Vec::new();
     let mut highwater = 0;
 
     image.resize(memsize, 0u8);
 
     let mut relocs = Vec::new();
+    let mut initmap = InitMap::new(image.len());
 
     //
     // Execute pass 2 on all object files
     //
     for obj in objects.iter_mut() {
         let data = obj.data.take().unwrap();
-        pass2_object(state, &data, obj, &mut image, &mut relocs, &mut highwater)?;
+        initmap.start_module(&obj.name);
+        pass2_object(state, &data, obj, &mut image, &mut initmap, &mut relocs, &mut highwater)?;
         obj.data = Some(data);
     }
 
     //
-    // Trim the image of trailing, uninitialized data, and set the EXE header minalloc
-    // to require that much extra memory.
+    // Move the initial data of segments loaded elsewhere to where it is stored. Where the
+    // segments run is then uninitialized.
     //
-    let mut exe = DosExe::new(&image[..highwater]);
+    if !state.loads.is_empty() {
+        let mut stored = vec![false; state.segments.len() + 1];
 
-    const PARA_SIZE: usize = 16;
-    let minalloc = (image.len() - highwater + PARA_SIZE - 1) / PARA_SIZE;
+        for load in state.loads.iter() {
+            let seg = &state.segments[load.segment];
+            image.copy_within(seg.base..seg.base + seg.length, load.base);
+            image[seg.base..seg.base + seg.length].fill(0);
+            stored[load.segment] = true;
 
-    exe.set_min_alloc(minalloc as u16);
-    exe.set_max_alloc(0xffff);
+            for reloc in relocs.iter_mut() {
+                let at = ((reloc.seg as usize) << 4) + reloc.offset as usize;
 
-    for reloc in relocs {
-        exe.add_relocation(reloc);
+                if at >= seg.base && at < seg.base + seg.length {
+                    let at = at - seg.base + load.base;
+                    *reloc = Relocation { seg: (at >> 4) as u16, offset: (at & 0x000f) as u16 };
+                }
+            }
+        }
+
+        highwater = state.segment_order.iter()
+            .filter(|index| !stored[**index] && state.segments[**index].has_data)
+            .map(|index| std::cmp::min(highwater, state.segments[*index].base + state.segments[*index].length))
+            .chain(state.loads.iter().map(|load| load.base + state.segments[load.segment].length))
+            .fold(0, max);
     }
 
     //
-    // Figure out the stack, if any
+    // The stack is at the top of the stack segment. A program without one gets a stack of
+    // the size given on the command line after the end of the program, as a .COM file's
+    // stack is after its image.
     //
-    let nameidx = state.lnames.find_or_add("_STACK");
-    let classidx = state.lnames.find_or_add("STACK");
-    let ovlyidx = state.lnames.find_or_add("");
-
-    let name = SegName{ nameidx, classidx, ovlyidx };
+    const PARA_SIZE: usize = 16;
+    let mut memsize = image.len();
 
-    if let Some(seg) = state.get_segment_named(&name) {
+    if let Some(seg) = state.stack_segment() {
         let segment = &state.segments[seg];
         let frame = (segment.base >> 4) as u16;
-        let mut offset = segment.length + (segment.base & 0x000f);
-        if offset > 0xfffe {
-            offset = 0xfffe;
+        let offset = segment.length + (segment.base & 0x000f);
+
+        if offset > 0x10000 {
+            return Err(LinkerError::new(&format!("stack segment {} crosses a 64K frame.", state.segname(&segment.name))));
         }
 
-        exe.set_stack(frame, offset as u16);
-    } else {
+        state.stack = Some(FarPtr::new(frame, std::cmp::min(offset, 0xfffe) as u16));
+    } else if let Some(size) = args.stack {
+        if size > 0x10000 {
+            return Err(LinkerError::new(&format!("stack of {:X}H bytes crosses a 64K frame.", size)));
+        }
+
+        let frame = memsize.div_ceil(PARA_SIZE);
+        memsize = frame * PARA_SIZE + size;
+
+        state.stack = Some(FarPtr::new(frame as u16, std::cmp::min(size, 0xfffe) as u16));
+    } else if args.format == OutputFormat::Exe {
         eprintln!("warning: no stack.");
     }
 
+    //
+    // A device driver is loaded at offset 0 of a segment DOS chooses, and is not
+    // relocated, so it can have no segment fixups.
+    //
+    if args.format == OutputFormat::Sys {
+        if let Some(reloc) = relocs.first() {
+            return Err(LinkerError::new(&format!(
+                "device driver has {} segment fixups, which DOS will not relocate; the first is at {:04X}:{:04X}.",
+                relocs.len(), reloc.seg, reloc.offset
+            )));
+        }
+
+        if highwater > 0x10000 {
+            return Err(LinkerError::new("device driver is larger than 64K."));
+        }
+
+        devdriver::check_device_header(&image[..highwater], 0)?;
+        return binfile::write_binary(args.output.as_ref().unwrap(), &image[..highwater], None);
+    }
+
+    //
+    // A raw binary or hex file has no header for a loader to relocate it with, so its
+    // segment fixups are resolved against the segment it will be loaded at.
+    //
+    if matches!(args.format, OutputFormat::Bin | OutputFormat::Hex | OutputFormat::Srec) {
+        let base_segment = match (args.base_segment, args.origin) {
+            (Some(seg), _) => seg,
+            (None, Some(origin)) if origin % PARA_SIZE != 0 => {
+                return Err(LinkerError::new(&format!("origin {:X}H is not on a paragraph boundary.", origin)));
+            },
+            (None, Some(origin)) => origin / PARA_SIZE,
+            (None, None) => 0,
+        };
+
+        if base_segment > 0xffff {
+            return Err(LinkerError::new(&format!("base segment {:X}H is outside 1M.", base_segment)));
+        }
+
+        binfile::relocate(&mut image, &relocs, base_segment as u16)?;
+        state.relocations = 0;
+
+        let output = args.output.as_ref().unwrap();
+        let image = &image[..highwater];
+
+        return match args.format {
+            OutputFormat::Hex => hexfile::write_intel_hex(output, image, base_segment as u16, state.entry),
+            OutputFormat::Srec => hexfile::write_srecords(output, image, base_segment as u16, state.entry),
+            _ => binfile::write_binary(output, image, args.rom_size),
+        };
+    }
+
+    //
+    // Trim the image of trailing, uninitialized data, and set the EXE header minalloc
+    // to require that much extra memory, plus any asked for on the command line.
+    //
+    let (highwater, minalloc, maxalloc) = exe_allocation(&mut image, memsize, highwater, args.extra_alloc, args.max_alloc, args.high)?;
+    let mut exe = DosExe::new(&image[..highwater]);
+
+    exe.set_min_alloc(minalloc as u16);
+    exe.set_max_alloc(maxalloc as u16);
+    exe.set_exepack(args.exepack);
+
+    state.relocations = relocs.len();
+
+    for reloc in relocs {
+        exe.add_relocation(reloc);
+    }
+
+    if let Some(stack) = &state.stack {
+        exe.set_stack(stack.seg, stack.offset);
+    }
+
     if let Some(entry) = &state.entry {
         exe.set_entry_point(&entry)?;
-    } else {    
+    } else if args.format == OutputFormat::Exe {
         eprintln!("warning: program has no entry point.");
     }
 
+    //
+    // An EXE device driver's entry point gives the segment its device header starts.
+    //
+    if args.format == OutputFormat::SysExe {
+        let entry = state.entry.ok_or_else(|| LinkerError::new("EXE device driver has no entry point to find its device header by."))?;
+        devdriver::check_device_header(&image[..highwater], (entry.seg as usize) << 4)?;
+    }
+
+    if args.codeview {
+        exe.set_debug_info(codeview::build(state, objects)?);
+    } else if args.td {
+        exe.set_debug_info(tdinfo::build(state, objects, exe.file_size())?);
+    }
+
     exe.write(args.output.as_ref().unwrap())?;
 
     Ok(())
 }
 
+/// Work out how much of the image an EXE file holds, and the minimum and maximum memory
+/// (in paragraphs) the header asks for beyond it. The minimum covers the uninitialized data
+/// at the end of the image, plus `extra_alloc`. A program loaded high gets no memory beyond
+/// its image, so its uninitialized data and stack are padded out with zeros instead.
+///
+fn exe_allocation(image: &mut Vec<u8>, memsize: usize, highwater: usize, extra_alloc: usize, max_alloc: Option<usize>, high: bool) -> Result<(usize, usize, usize), LinkerError> {
+    const PARA_SIZE: usize = 16;
+
+    let highwater = if high {
+        image.resize(memsize, 0);
+        memsize
+    } else {
+        highwater
+    };
+
+    let minalloc = (memsize - highwater).div_ceil(PARA_SIZE) + extra_alloc;
+    let maxalloc = if high { 0 } else { max_alloc.unwrap_or(0xffff) };
+
+    if minalloc > 0xffff {
+        return Err(LinkerError::new(&format!("program needs {:X}H paragraphs beyond its image, more than an EXE header can ask for.", minalloc)));
+    }
+
+    if maxalloc > 0xffff {
+        return Err(LinkerError::new(&format!("maximum allocation of {:X}H paragraphs is more than an EXE header can ask for.", maxalloc)));
+    }
+
+    if maxalloc < minalloc && !high {
+        return Err(LinkerError::new(&format!("maximum allocation of {:X}H paragraphs is less than the {:X}H paragraphs the program needs beyond its image.", maxalloc, minalloc)));
+    }
+
+    Ok((highwater, minalloc, maxalloc))
+}
+
 /// Handle one pass 2 object file.
 /// 
-fn pass2_object(state: &mut LinkState, data: &[u8], obj: &mut Object, image: &mut [u8], relocs: &mut Vec<Relocation>, highwater: &mut usize) -> Result<(), LinkerError> {
+fn pass2_object(state: &mut LinkState, data: &[u8], obj: &mut Object, image: &mut [u8], initmap: &mut InitMap, relocs: &mut Vec<Relocation>, highwater: &mut usize) -> Result<(), LinkerError> {
     let mut start = 0;
-    let mut lastdata = LastDataRegion{ frame: 0, base: 0, length: 0 };
+    let mut lastdata = LastDataRegion{ frame: 0, base: 0, length: 0, debug: None, discarded: false, iterated: None, code: false };
     let mut modend = false;
+    let mut patches = Vec::new();
 
     while !modend && start < data.len() {
         let mut rec = Record::new(&data[start..])?;
@@ -227,16 +575,26 @@ fn pass2_object(state: &mut LinkState, data: &[u8], obj: &mut Object, image: &mu
             RecordType::EXTDEF |
             RecordType::COMENT |
             RecordType::PUBDEF |
+            RecordType::LINNUM |
+            RecordType::LINNUM32 |
             RecordType::LNAMES |
+            RecordType::LLNAMES |
+            RecordType::CEXTDEF |
             RecordType::SEGDEF |
             RecordType::GRPDEF => Ok(()),
             
             //
             // These records are for pass 2.
             //
-            RecordType::LEDATA => pass2_ledata(&mut rec, state, obj, image, &mut lastdata),
-            RecordType::LIDATA => pass2_lidata(&mut rec, state, obj, image, &mut lastdata),
+            RecordType::LEDATA => pass2_ledata(&mut rec, state, obj, image, initmap, &mut lastdata),
+            RecordType::LIDATA => pass2_lidata(&mut rec, state, obj, image, initmap, &mut lastdata),
+            RecordType::COMDAT |
+            RecordType::COMDAT32 => pass2_comdat(&mut rec, state, obj, image, initmap, &mut lastdata),
             RecordType::FIXUPP => pass2_fixupp(&mut rec, state, obj, image, &lastdata, relocs),
+            RecordType::BAKPAT |
+            RecordType::BAKPAT32 => pass2_bakpat(&mut rec, state, obj, &mut patches),
+            RecordType::NBKPAT |
+            RecordType::NBKPAT32 => pass2_nbkpat(&mut rec, state, obj, &mut patches),
             RecordType::MODEND => { 
                 modend = true; 
                 pass2_modend(&mut rec, state, obj, &lastdata)
@@ -254,10 +612,14 @@ fn pass2_object(state: &mut LinkState, data: &[u8], obj: &mut Object, image: &mu
             Ok(_) => {},
         };
 
-        *highwater = max(*highwater, lastdata.base + lastdata.length);
+        if lastdata.debug.is_none() {
+            *highwater = max(*highwater, lastdata.base + lastdata.length);
+        }
 
         start += reclen;
     }
+
+    apply_backpatches(image, &patches);
     
     Ok(())
 }
@@ -286,26 +648,122 @@ fn base_of_obj_seg_offset(obj: &Object, segidx: usize, offset: usize, state: &Li
     Ok(base)
 }
 
+/// Whether the SEGDEF with the given index is in a segment of a code class.
+///
+fn code_segdef(state: &LinkState, obj: &Object, segidx: usize) -> bool {
+    let segment = &state.segments[obj.segdefs[segidx].segidx];
+    state.lnames.get(segment.name.classidx).to_uppercase().ends_with("CODE")
+}
+
+/// If the SEGDEF with the given index is a debug segment, return which kind.
+///
+fn debug_segdef(state: &LinkState, obj: &Object, segidx: usize) -> Option<DebugInfo> {
+    if obj.segdefs.is_valid_index(segidx) {
+        state.segments[obj.segdefs[segidx].segidx].debug
+    } else {
+        None
+    }
+}
+
+/// If the SEGDEF with the given index was discarded by garbage collection, note that the
+/// data is dropped.
+///
+fn discarded_segdef(obj: &Object, segidx: usize, lastdata: &mut LastDataRegion) -> bool {
+    let discarded = obj.segdefs.is_valid_index(segidx) && obj.segdefs[segidx].discarded;
+
+    if discarded {
+        *lastdata = LastDataRegion{ frame: 0, base: 0, length: 0, debug: None, discarded: true, iterated: None, code: false };
+    }
+
+    discarded
+}
+
+/// Install data for a debug segment into the module's debug data, rather than the image.
+///
+fn pass2_debug_data(obj: &mut Object, debug: DebugInfo, segidx: usize, offset: usize, data: &[u8], lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
+    let seglen = obj.segdefs[segidx].length;
+
+    if offset + data.len() > seglen {
+        return Err(LinkerError::new(
+            &format!("invalid data range {:05X}H..{:05X}H in debug segment.", offset, offset + data.len())
+        ));
+    }
+
+    let debug_data = obj.debug_data_mut(debug);
+    if debug_data.len() < offset + data.len() {
+        debug_data.resize(offset + data.len(), 0);
+    }
+
+    debug_data[offset..offset+data.len()].copy_from_slice(data);
+
+    *lastdata = LastDataRegion{ frame: 0, base: offset, length: data.len(), debug: Some(debug), discarded: false, iterated: None, code: false };
+
+    Ok(())
+}
+
 /// Handle an LEDATA record, which contains literal data to be copied into the final executable.
 ///
-fn pass2_ledata(rec: &mut Record, state: &LinkState, obj: &Object, image: &mut [u8], lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
+fn pass2_ledata(rec: &mut Record, state: &LinkState, obj: &mut Object, image: &mut [u8], initmap: &mut InitMap, lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
     let segidx = rec.index()?;
     let offset = rec.word()?;
     let data = rec.rest();
 
+    if let Some(debug) = debug_segdef(state, obj, segidx) {
+        return pass2_debug_data(obj, debug, segidx, offset as usize, data, lastdata);
+    }
+
+    if discarded_segdef(obj, segidx, lastdata) {
+        return Ok(());
+    }
+
     let base = base_of_obj_seg_offset(obj, segidx, offset as usize, state, data.len(), "LEDATA")?;
     let frame = fixup_segdef_frame(state, obj, segidx)?;
 
-    image[base..base+data.len()].copy_from_slice(&data);
+    install_data(state, obj, segidx, image, initmap, base, &data)?;
 
-    *lastdata = LastDataRegion{ frame, base, length: data.len() };
+    *lastdata = LastDataRegion{ frame, base, length: data.len(), debug: None, discarded: false, iterated: None, code: code_segdef(state, obj, segidx) };
  
     Ok(())
 }
 
+/// Copy a module's data for a SEGDEF into the image, warning if another module already
+/// initialized any of it, or failing if overlaps are errors. Overlap is expected in common
+/// segments, which are overlaid.
+///
+fn install_data(state: &LinkState, obj: &Object, segidx: usize, image: &mut [u8], initmap: &mut InitMap, base: usize, data: &[u8]) -> Result<(), LinkerError> {
+    image[base..base+data.len()].copy_from_slice(data);
+
+    let segment = &state.segments[obj.segdefs[segidx].segidx];
+
+    if segment.combine == Combine::Common {
+        return Ok(());
+    }
+
+    if let Some((at, owner)) = initmap.initialize(base, data.len()) {
+        let frame = segment.base >> 4;
+        let message = format!("data of module {} overlaps data of module {} at {:04X}:{:04X} in segment {}.",
+            obj.name, owner, frame, at - (frame << 4), state.lnames.get(segment.name.nameidx));
+
+        if state.overlap_errors {
+            return Err(LinkerError::new(&message));
+        }
+
+        eprintln!("warning: {}", message);
+    }
+
+    Ok(())
+}
+
 /// Expand an LIDATA block (recursively) into the accumulator vector of bytes.
 ///
-fn accum_lidata(rec: &mut Record, accum: &mut Vec<u8>) -> Result<(), LinkerError> {
+pub fn accum_lidata(rec: &mut Record, accum: &mut Vec<u8>) -> Result<(), LinkerError> {
+    accum_lidata_sources(rec, 0, accum, &mut Vec::new())
+}
+
+/// Expand an LIDATA block (recursively) into the accumulator vector of bytes, noting in
+/// `sources` the offset from `start` in the record of the byte each came from.
+///
+fn accum_lidata_sources(rec: &mut Record, start: usize, accum: &mut Vec<u8>, sources: &mut Vec<usize>) -> Result<(), LinkerError> {
     //
     // A block is: 2 bytes of repeat count, 2 bytes of block count, and content.
     // If block count is zero, then content is a counted byte array.
@@ -315,20 +773,25 @@ fn accum_lidata(rec: &mut Record, accum: &mut Vec<u8>) -> Result<(), LinkerError
     let block_count = rec.word()? as usize;
 
     let mut iterbytes = Vec::new();
+    let mut itersources = Vec::new();
     
-    let bytes = if block_count == 0 {
-        rec.counted_bytes()?
+    let (bytes, bytesources) = if block_count == 0 {
+        let at = rec.offset() + 1 - start;
+        let bytes = rec.counted_bytes()?;
+        itersources.extend(at..at + bytes.len());
+        (bytes, &itersources)
     } else {
 
         for _ in 0..block_count {
-            accum_lidata(rec, &mut iterbytes)?;
+            accum_lidata_sources(rec, start, &mut iterbytes, &mut itersources)?;
         }
 
-        &iterbytes
+        (&iterbytes[..], &itersources)
     };
 
     for _ in 0..repeat_count {
-        accum.extend_from_slice(&bytes);
+        accum.extend_from_slice(bytes);
+        sources.extend_from_slice(bytesources);
     }
 
     Ok(())
@@ -336,31 +799,183 @@ fn accum_lidata(rec: &mut Record, accum: &mut Vec<u8>) -> Result<(), LinkerError
 
 /// Handle expanding and installing iterated data.
 ///
-fn pass2_lidata(rec: &mut Record, state: &LinkState, obj: &Object, image: &mut [u8], lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
+fn pass2_lidata(rec: &mut Record, state: &LinkState, obj: &mut Object, image: &mut [u8], initmap: &mut InitMap, lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
     let segidx = rec.index()?;
     let offset = rec.word()? as usize;
+    let start = rec.offset();
 
     let mut data = Vec::new();
+    let mut sources = Vec::new();
 
     while !rec.end() {
-        accum_lidata(rec, &mut data)?;
+        accum_lidata_sources(rec, start, &mut data, &mut sources)?;
+    }
+
+    if let Some(debug) = debug_segdef(state, obj, segidx) {
+        pass2_debug_data(obj, debug, segidx, offset, &data, lastdata)?;
+        lastdata.iterated = Some(sources);
+        return Ok(());
+    }
+
+    if discarded_segdef(obj, segidx, lastdata) {
+        return Ok(());
     }
 
     let base = base_of_obj_seg_offset(obj, segidx, offset as usize, state, data.len(), "LIDATA")?;
     let frame = fixup_segdef_frame(state, obj, segidx)?;
 
-    image[base..base+data.len()].copy_from_slice(&data);
+    install_data(state, obj, segidx, image, initmap, base, &data)?;
 
-    *lastdata = LastDataRegion{ frame, base, length: data.len() };
+    *lastdata = LastDataRegion{ frame, base, length: data.len(), debug: None, discarded: false, iterated: Some(sources), code: code_segdef(state, obj, segidx) };
 
     Ok(())
 }
 
-/// Given the index of a segdef, return the canonic frame of the containing segment.
+/// Handle a COMDAT record, installing its data if this module's definition of the COMDAT
+/// was chosen. Otherwise, the data and the fixups which follow it are dropped.
+///
+fn pass2_comdat(rec: &mut Record, state: &LinkState, obj: &mut Object, image: &mut [u8], initmap: &mut InitMap, lastdata: &mut LastDataRegion) -> Result<(), LinkerError> {
+    let header = ComdatHeader::read(rec, obj, state)?;
+
+    let (data, iterated) = if header.iterated {
+        let start = rec.offset();
+        let mut data = Vec::new();
+        let mut sources = Vec::new();
+
+        while !rec.end() {
+            accum_lidata_sources(rec, start, &mut data, &mut sources)?;
+        }
+
+        (data, Some(sources))
+    } else {
+        (rec.rest().to_vec(), None)
+    };
+
+    let Some(segidx) = obj.comdat_segdef(&header.name) else {
+        *lastdata = LastDataRegion{ frame: 0, base: 0, length: 0, debug: None, discarded: true, iterated: None, code: false };
+        return Ok(());
+    };
+
+    if discarded_segdef(obj, segidx, lastdata) {
+        return Ok(());
+    }
+
+    let base = base_of_obj_seg_offset(obj, segidx, header.offset, state, data.len(), "COMDAT")?;
+    let frame = fixup_segdef_frame(state, obj, segidx)?;
+
+    install_data(state, obj, segidx, image, initmap, base, &data)?;
+
+    *lastdata = LastDataRegion{ frame, base, length: data.len(), debug: None, discarded: false, iterated, code: code_segdef(state, obj, segidx) };
+
+    Ok(())
+}
+
+//
+// BAKPAT and NBKPAT records patch values, such as forward jump displacements, into data
+// already emitted for a SEGDEF or a COMDAT respectively. Their layout is
+//
+//     BAKPAT: index segment, db location, then pairs of dw/dd offset, dw/dd value
+//     NBKPAT: db location, index name, then pairs of dw/dd offset, dw/dd value
+//
+// where the location is 0 for a byte, 1 for a word or 2 (32-bit records only) for a
+// dword. The value is added to the location. The patches are applied once all of the
+// module's data has been installed, as the data they patch may follow them.
+//
+
+/// Return the size of a BAKPAT or NBKPAT location.
+///
+fn backpatch_size(location: u8, is32: bool) -> Result<usize, LinkerError> {
+    match location {
+        0 => Ok(1),
+        1 => Ok(2),
+        2 if is32 => Ok(4),
+        _ => Err(LinkerError::new(&format!("invalid back patch location type {:02X}H.", location))),
+    }
+}
+
+/// Read the offset and value pairs of a BAKPAT or NBKPAT record patching the given SEGDEF.
+///
+fn read_backpatches(rec: &mut Record, state: &LinkState, obj: &Object, segidx: usize, size: usize, is32: bool, patches: &mut Vec<BackPatch>) -> Result<(), LinkerError> {
+    let rectype = if is32 { "BAKPAT32" } else { "BAKPAT" };
+
+    while !rec.end() {
+        let (offset, value) = if is32 {
+            (rec.dword()? as usize, rec.dword()?)
+        } else {
+            (rec.word()? as usize, rec.word()? as u32)
+        };
+
+        let base = base_of_obj_seg_offset(obj, segidx, offset, state, size, rectype)?;
+        patches.push(BackPatch{ base, size, value });
+    }
+
+    Ok(())
+}
+
+/// Handle a BAKPAT record, which patches data in a SEGDEF of the module.
+///
+fn pass2_bakpat(rec: &mut Record, state: &LinkState, obj: &Object, patches: &mut Vec<BackPatch>) -> Result<(), LinkerError> {
+    let is32 = rec.rectype == RecordType::BAKPAT32;
+    let segidx = rec.index()?;
+    let size = backpatch_size(rec.byte()?, is32)?;
+
+    if segidx == 0 || !obj.segdefs.is_valid_index(segidx) {
+        return Err(LinkerError::new(&format!("invalid segdef index {} in BAKPAT", segidx)));
+    }
+
+    if debug_segdef(state, obj, segidx).is_some() {
+        return Err(LinkerError::new("BAKPAT of a debug segment is not supported."));
+    }
+
+    if obj.segdefs[segidx].discarded {
+        return Ok(());
+    }
+
+    read_backpatches(rec, state, obj, segidx, size, is32, patches)
+}
+
+/// Handle an NBKPAT record, which patches data in a COMDAT of the module. If another
+/// module's definition of the COMDAT was chosen, the patches are dropped.
+///
+fn pass2_nbkpat(rec: &mut Record, state: &LinkState, obj: &Object, patches: &mut Vec<BackPatch>) -> Result<(), LinkerError> {
+    let is32 = rec.rectype == RecordType::NBKPAT32;
+    let size = backpatch_size(rec.byte()?, is32)?;
+    let nameidx = rec.index()?;
+
+    if nameidx == 0 || !obj.lnames.is_valid_index(nameidx) {
+        return Err(LinkerError::new(&format!("invalid name index {} in NBKPAT", nameidx)));
+    }
+
+    let name = state.lnames.get(obj.lnames.get(nameidx));
+
+    match obj.comdat_segdef(name) {
+        Some(segidx) if !obj.segdefs[segidx].discarded => read_backpatches(rec, state, obj, segidx, size, is32, patches),
+        _ => Ok(()),
+    }
+}
+
+/// Add the values of the module's back patches into the image.
+///
+fn apply_backpatches(image: &mut [u8], patches: &[BackPatch]) {
+    for patch in patches {
+        let location = &mut image[patch.base..patch.base + patch.size];
+        let mut bytes = [0u8; 4];
+        bytes[..patch.size].copy_from_slice(location);
+
+        let value = u32::from_le_bytes(bytes).wrapping_add(patch.value);
+        location.copy_from_slice(&value.to_le_bytes()[..patch.size]);
+    }
+}
+
+/// Given the index of a segdef, return the canonic frame of the containing segment, or
+/// of the pack it is in.
 /// 
 fn fixup_segdef_frame(state: &LinkState, obj: &Object, segidx: usize) -> Result<u16, LinkerError> {
-    let linear = fixup_segdef_base(state, obj, segidx)?;
-    Ok((linear >> 4) as u16)
+    if segidx == 0 || !obj.segdefs.is_valid_index(segidx) {
+        Err(LinkerError::new(&format!("invalid segdef index {} in fixup", segidx)))
+    } else {
+        Ok((state.segment_frame_base(obj.segdefs[segidx].segidx) >> 4) as u16)
+    }
 }
 
 /// Given the index of a grpdef, return the canonic frame of the containing group.
@@ -378,7 +993,7 @@ fn fixup_extdef_frame(state: &LinkState, obj: &Object, extidx: usize) -> Result<
     } else {
         let symname = &obj.extdefs[extidx];
         
-        let (grpidx, segidx, frame) = match state.symbols.symbols.get(symname) {
+        let (grpidx, segidx, frame) = match obj.symbol(&state.symbols, symname) {
             Some(Symbol::Public(public)) => {
                 (public.group, public.segment, public.frame)
             },
@@ -387,12 +1002,15 @@ fn fixup_extdef_frame(state: &LinkState, obj: &Object, extidx: usize) -> Result<
             None => return Err(LinkerError::new(&format!("{}: symbol does not exist in pass 2.", symname))),
         };
 
+        //
+        // The symbol's group and segment are the linker's, not indices into this module.
+        //
         if segidx == 0 {
-            Ok(frame as u16)
+            Ok(frame)
         } else if grpidx != 0 {
-            fixup_grpdef_frame(state, obj, grpidx)
+            Ok((state.groups[grpidx].base >> 4) as u16)
         } else {
-            fixup_segdef_frame(state, obj, segidx)
+            Ok((state.segment_frame_base(segidx) >> 4) as u16)
         }
     }
 }
@@ -431,7 +1049,7 @@ fn fixup_extdef_base(state: &LinkState, obj: &Object, extidx: usize) -> Result<u
     } else {
         let symname = &obj.extdefs[extidx];
         
-        let (segidx, offset) = match state.symbols.symbols.get(symname) {
+        let (segidx, offset) = match obj.symbol(&state.symbols, symname) {
             Some(Symbol::Public(public)) => {
                 (public.segment, public.offset)
             },
@@ -449,97 +1067,15 @@ fn fixup_extdef_base(state: &LinkState, obj: &Object, extidx: usize) -> Result<u
     }
 }
 
-/// Process a thread subrecord of a FIXUPP.
-/// 
-fn pass2_fixupp_thread(rec: &mut Record, obj: &mut Object, b0: u8) -> Result<(), LinkerError> {
-    let is_frame_thread = (b0 & 0x40) != 0;
-    let thread = (b0 & 0x03) as usize;
-
-    if is_frame_thread {
-        //
-        // Frame thread
-        //
-        let frame_type = FrameType::from_byte((b0 >> 2) & 0x07)?;
-
-        let frame_index = match frame_type {
-            FrameType::SEGDEF |
-            FrameType::GRPDEF |
-            FrameType::EXTDEF => rec.index()?,
-            _ => 0,
-        };
-
-        obj.fixup_threads.frame_threads[thread] = FrameThread{ frame_type: Some(frame_type), frame_index };
-    } else {
-        //
-        // Target thread
-        //
-        let target_type = TargetType::from_byte((b0 >> 2) & 0x03)?;
-
-        let target_index = match target_type {
-            TargetType::Frame => return Err(LinkerError::new("TargetType::Frame is not supported.")),
-            _ => rec.index()?,
-        };
-
-        obj.fixup_threads.target_threads[thread] = TargetThread{ target_type: Some(target_type), target_index };
-    }   
-
-    Ok(())
-}
-
 fn pass2_fixup_data(rec: &mut Record, state: &LinkState, obj: &Object, lastdata: &LastDataRegion)  -> Result<(u16, usize), LinkerError> {
-    let fixdat = rec.byte()?;
-    let is_frame_thread = (fixdat & 0x80) != 0;
-    let is_target_thread = (fixdat & 0x08) != 0;
-    let has_target_disp = (fixdat & 0x04) == 0;
-
-    //
-    // Frame data
-    //
-    let (frame_type, frame_index) = if is_frame_thread {
-        let threadidx = ((fixdat >> 4) & 0x03) as usize;
-        let thread = &obj.fixup_threads.frame_threads[threadidx];
-
-        match (thread.frame_type, thread.frame_index) {
-            (Some(frame_type), frame_index) => (frame_type, frame_index),
-            _ => return Err(LinkerError::new(&format!("use of undefined frame thread {}", threadidx))),
-        }
-    } else {
-        let frame_type = FrameType::from_byte((fixdat >> 4) & 0x07)?;
-
-        let frame_index = match frame_type {
-            FrameType::SEGDEF |
-            FrameType::GRPDEF |
-            FrameType::EXTDEF => rec.index()?,
-            FrameType::ExplicitFrame => rec.word()? as usize,
-            _ => 0,
-        };
-
-        (frame_type, frame_index)
-    };
-
-    //
-    // Target data 
-    //
-    let (target_type, target_index) = if is_target_thread {
-        let threadidx = (fixdat & 0x03) as usize;
-        let thread = &obj.fixup_threads.target_threads[threadidx];
-
-        match (thread.target_type, thread.target_index) {
-            (Some(target_type), target_index) => (target_type, target_index),
-            _ => return Err(LinkerError::new(&format!("use of undefined target thread {}", threadidx))),
-        }
-    } else {
-        let target_type = TargetType::from_byte(fixdat & 0x03)?;
-
-        let target_index = match target_type {
-            TargetType::Frame => return Err(LinkerError::new("TargetType::Frame is not supported.")),
-            _ => rec.index()?,
-        };
-
-        (target_type, target_index)
-    };
+    let fixup = obj.fixup_threads.read_fixup_data(rec)?;
+    resolve_fixup_data(state, obj, lastdata, &fixup)
+}
 
-    let target_disp = if has_target_disp { rec.word()? } else { 0 };
+/// Compute the frame and linear target address of a fixup.
+///
+fn resolve_fixup_data(state: &LinkState, obj: &Object, lastdata: &LastDataRegion, fixup: &FixupData) -> Result<(u16, usize), LinkerError> {
+    let FixupData { frame_type, frame_index, target_type, target_index, target_disp } = *fixup;
     
     //
     // Compute frame.
@@ -549,7 +1085,7 @@ fn pass2_fixup_data(rec: &mut Record, state: &LinkState, obj: &Object, lastdata:
         FrameType::GRPDEF => fixup_grpdef_frame(state, obj, frame_index)?,
         FrameType::EXTDEF => fixup_extdef_frame(state, obj, frame_index)?,
         FrameType::ExplicitFrame => frame_index as u16,
-        FrameType::SegOfPrevData => (lastdata.base >> 4) as u16,
+        FrameType::SegOfPrevData => lastdata.frame,
         FrameType::FromTarget => match target_type {
             TargetType::SEGDEF => fixup_segdef_frame(state, obj, target_index)?,
             TargetType::GRPDEF => fixup_grpdef_frame(state, obj, target_index)?,
@@ -577,6 +1113,100 @@ fn pass2_fixup_data(rec: &mut Record, state: &LinkState, obj: &Object, lastdata:
     Ok((fbval, target))
 }
 
+/// If the far pointer at `imageptr`, in code, is the operand of a far call to a procedure
+/// in the same frame as the call, rewrite the call as `nop; push cs; call near`, which
+/// needs no relocation. Returns whether the call was translated.
+///
+fn translate_far_call(image: &mut [u8], imageptr: usize, lastdata: &LastDataRegion, fbval: u16, foval: i32) -> bool {
+    const CALL_FAR: u8 = 0x9a;
+    const NOP: u8 = 0x90;
+    const PUSH_CS: u8 = 0x0e;
+    const CALL_NEAR: u8 = 0xe8;
+
+    //
+    // The opcode must be in the same data record as the pointer, which must be code, and
+    // the pointer must not already hold a segment.
+    //
+    if !lastdata.code || imageptr <= lastdata.base || image[imageptr - 1] != CALL_FAR || fbval != lastdata.frame || !(0..=0xffff).contains(&foval) {
+        return false;
+    }
+
+    if image[imageptr+2..imageptr+4] != [0, 0] {
+        return false;
+    }
+
+    let offset = u16::from_le_bytes([image[imageptr], image[imageptr+1]]).wrapping_add(foval as u16);
+    let next = (imageptr + 4 - ((fbval as usize) << 4)) as u16;
+
+    image[imageptr-1..imageptr+2].copy_from_slice(&[NOP, PUSH_CS, CALL_NEAR]);
+    image[imageptr+2..imageptr+4].copy_from_slice(&offset.wrapping_sub(next).to_le_bytes());
+
+    true
+}
+
+/// A fixup being applied, described in diagnostics.
+///
+struct FixupContext<'a> {
+    obj: &'a Object,
+    lastdata: &'a LastDataRegion,
+    fixup: &'a FixupData,
+    fbval: u16,
+}
+
+impl FixupContext<'_> {
+    /// Describe the fixup's target: the symbol, segment, group or frame, and displacement.
+    ///
+    fn target(&self, state: &LinkState) -> String {
+        let index = self.fixup.target_index;
+
+        let target = match self.fixup.target_type {
+            TargetType::SEGDEF => format!("segment {}", state.lnames.get(state.segments[self.obj.segdefs[index].segidx].name.nameidx)),
+            TargetType::GRPDEF => format!("group {}", state.lnames.get(state.groups[self.obj.grpdefs.get(index)].name)),
+            TargetType::EXTDEF => format!("symbol {}", self.obj.extdefs[index]),
+            TargetType::Frame => format!("frame {:04X}H", index),
+        };
+
+        match self.fixup.target_disp {
+            0 => target,
+            disp => format!("{}+{:04X}H", target, disp),
+        }
+    }
+
+    /// Report a fixup which cannot be applied at `imageptr`, as MS LINK's L2002 does: with
+    /// the location, target, frame and computed value, and the bytes of the data up to the
+    /// location. This is a warning, or an error with --fixup-errors.
+    ///
+    fn report(&self, state: &LinkState, image: &[u8], imageptr: usize, problem: &str, value: i32) -> Result<(), LinkerError> {
+        let frame = self.lastdata.frame as usize;
+        let segment = state.segment_order.iter()
+            .map(|segidx| &state.segments[*segidx])
+            .find(|seg| imageptr >= seg.base && imageptr < seg.base + seg.length)
+            .map_or("?", |seg| state.lnames.get(seg.name.nameidx));
+
+        //
+        // The location may be past the end of the data, or there may be no data at all.
+        //
+        let end = min(imageptr + 4, self.lastdata.base + self.lastdata.length).min(image.len());
+        let start = max(self.lastdata.base, imageptr.saturating_sub(4)).min(end);
+        let bytes: Vec<String> = image[start..end].iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        let value = if value < 0 { format!("-{:X}H", -(value as i64)) } else { format!("{:X}H", value) };
+
+        let message = format!("module {}: {} at {:04X}:{:04X} in segment {}: target {}, frame {:04X}H, value {}, data {}",
+            self.obj.name, problem, frame, imageptr.wrapping_sub(frame << 4) & 0xffff, segment, self.target(state), self.fbval, value,
+            if bytes.is_empty() { "none".to_owned() } else { bytes.join(" ") });
+
+        if state.fixup_errors {
+            Err(LinkerError::new(&message))
+        } else {
+            eprintln!("warning: {}.", message);
+            Ok(())
+        }
+    }
+}
+
 /// Process a fixup subrecord of a FIXUPP.
 /// 
 fn pass2_fixupp_fixup(rec: &mut Record, state: &mut LinkState, obj: &Object, image: &mut[u8], b0: u8, lastdata: &LastDataRegion, relocs: &mut Vec<Relocation>) -> Result<(), LinkerError> {
@@ -587,116 +1217,173 @@ fn pass2_fixupp_fixup(rec: &mut Record, state: &mut LinkState, obj: &Object, ima
     let is_segment_rel = (locat & 0x4000) != 0;
     let loctype = Locat::new((locat >> 10) & 0x000f)?;
 
-    let imageptr = lastdata.base + ((locat as usize) & 0x3ff);
-
-    let (fbval, target) = pass2_fixup_data(rec, state, obj, lastdata)?;
+    let fixup = obj.fixup_threads.read_fixup_data(rec)?;
+    let (fbval, target) = resolve_fixup_data(state, obj, lastdata, &fixup)?;
+    let context = FixupContext { obj, lastdata, fixup: &fixup, fbval };
 
     //
-    // Compute the fixup
+    // Compute the fixup, at each copy of the location in iterated data.
     //
-    let frame_base = (fbval as i32) << 4;
+    for imageptr in lastdata.fixup_locations((locat as usize) & 0x3ff)? {
+        let frame_base = (fbval as i32) << 4;
     
-    if is_segment_rel {
-        let foval = (target as i32) - frame_base;
+        if is_segment_rel {
+            let foval = (target as i32) - frame_base;
 
-        if foval < 0 || foval > 0xffff {
-            eprintln!("warning: fixup overflow.");
-        }
+            if foval < 0 || foval > 0xffff {
+                context.report(state, image, imageptr, "fixup overflow", foval)?;
+            }
 
-        match loctype {
-            Locat::Offset16 => {
-                if imageptr + 2 > image.len() {
-                    eprintln!("warning: fixup location {:08X}H outside of image {:08X}H.", imageptr, image.len());
-                } else {
-                    let slice = &mut image[imageptr..imageptr+2];
-                    let curr = u16::from_le_bytes(slice.try_into().unwrap());
-                    let next = u16::wrapping_add(curr, foval as u16);
+            match loctype {
+                Locat::Offset16 => {
+                    if imageptr + 2 > image.len() {
+                        context.report(state, image, imageptr, "fixup location outside of image", foval)?;
+                    } else {
+                        let slice = &mut image[imageptr..imageptr+2];
+                        let curr = u16::from_le_bytes(slice.try_into().unwrap());
+                        let next = u16::wrapping_add(curr, foval as u16);
                     
-                    slice.copy_from_slice(&next.to_le_bytes());
-                }
-            },
-            Locat::Segment16 => {
-                if imageptr + 2 > image.len() {
-                    eprintln!("warning: fixup location {:08X}H outside of image {:08X}H.", imageptr, image.len());
-                } else {
-                    let slice = &mut image[imageptr..imageptr+2];
-                    let curr = u16::from_le_bytes(slice.try_into().unwrap());
-                    let next = u16::wrapping_add(curr, fbval);
+                        slice.copy_from_slice(&next.to_le_bytes());
+                    }
+                },
+                Locat::Segment16 => {
+                    if imageptr + 2 > image.len() {
+                        context.report(state, image, imageptr, "fixup location outside of image", fbval as i32)?;
+                    } else {
+                        let slice = &mut image[imageptr..imageptr+2];
+                        let curr = u16::from_le_bytes(slice.try_into().unwrap());
+                        let next = u16::wrapping_add(curr, fbval);
                     
-                    slice.copy_from_slice(&next.to_le_bytes());
-
-                    let reloc = Relocation {
-                        seg: lastdata.frame,
-                        offset: (imageptr - ((lastdata.frame as usize) << 4)) as u16,
-                    };
-
-                    relocs.push(reloc);
-                }
-            },
-            Locat::FarPointer => {
-                if imageptr + 4 > image.len() {
-                    eprintln!("warning: fixup location {:08X}H outside of image {:08X}H.", imageptr, image.len());
-                } else {
-                    let slice = &mut image[imageptr..imageptr+2];
-                    let curr = u16::from_le_bytes(slice.try_into().unwrap());
-                    let next = u16::wrapping_add(curr, foval as u16);
+                        slice.copy_from_slice(&next.to_le_bytes());
+
+                        let reloc = Relocation {
+                            seg: lastdata.frame,
+                            offset: (imageptr - ((lastdata.frame as usize) << 4)) as u16,
+                        };
+
+                        relocs.push(reloc);
+                    }
+                },
+                Locat::FarPointer => {
+                    if imageptr + 4 > image.len() {
+                        context.report(state, image, imageptr, "fixup location outside of image", foval)?;
+                    } else if state.far_calls.is_some() && translate_far_call(image, imageptr, lastdata, fbval, foval) {
+                        state.far_calls = state.far_calls.map(|count| count + 1);
+                    } else {
+                        let slice = &mut image[imageptr..imageptr+2];
+                        let curr = u16::from_le_bytes(slice.try_into().unwrap());
+                        let next = u16::wrapping_add(curr, foval as u16);
                     
-                    slice.copy_from_slice(&next.to_le_bytes());
+                        slice.copy_from_slice(&next.to_le_bytes());
 
-                    let slice = &mut image[imageptr+2..imageptr+4];
-                    let curr = u16::from_le_bytes(slice.try_into().unwrap());
-                    let next = u16::wrapping_add(curr, fbval);
+                        let slice = &mut image[imageptr+2..imageptr+4];
+                        let curr = u16::from_le_bytes(slice.try_into().unwrap());
+                        let next = u16::wrapping_add(curr, fbval);
                     
-                    slice.copy_from_slice(&next.to_le_bytes());
-
-                    let reloc = Relocation {
-                        seg: lastdata.frame,
-                        offset: (imageptr + 2 - ((lastdata.frame as usize) << 4)) as u16,
-                    };
+                        slice.copy_from_slice(&next.to_le_bytes());
+
+                        let reloc = Relocation {
+                            seg: lastdata.frame,
+                            offset: (imageptr + 2 - ((lastdata.frame as usize) << 4)) as u16,
+                        };
+
+                        relocs.push(reloc);
+                    }
+                },
+                Locat::LowOrderByte => {
+                    if imageptr >= image.len() {
+                        context.report(state, image, imageptr, "fixup location outside of image", fbval as i32)?;
+                    } else {
+                        let slice = &mut image[imageptr..imageptr+1];
+                        let curr = u8::from_le_bytes(slice.try_into().unwrap());
+                        let next = u8::wrapping_add(curr, fbval as u8);
+                    
+                        slice.copy_from_slice(&next.to_le_bytes());
+                    }
+                },
+            }
+        } else {
+            let loc_delta = (imageptr as i32) - frame_base;
+            if loc_delta < 0 || loc_delta > 0xffff {
+                context.report(state, image, imageptr, "fixup location outside frame", loc_delta)?;
+            } else {
+                let target_delta = (target as i32) - frame_base;
 
-                    relocs.push(reloc);
+                if target_delta < 0 || target_delta > 0xffff {
+                    context.report(state, image, imageptr, "fixup target outside frame", target_delta)?;
                 }
-            },
-            Locat::LowOrderByte => {
-                if imageptr >= image.len() {
-                    eprintln!("warning: fixup location {:08X}H outside of image {:08X}H.", imageptr, image.len());
-                } else {
-                    let slice = &mut image[imageptr..imageptr+1];
-                    let curr = u8::from_le_bytes(slice.try_into().unwrap());
-                    let next = u8::wrapping_add(curr, fbval as u8);
-                    
-                    slice.copy_from_slice(&next.to_le_bytes());
+            }
+
+            match loctype {
+                Locat::Offset16 => {
+                    let disp = (target as i32) - ((imageptr as i32) + 2);
+
+                    if imageptr + 2 > image.len() {
+                        context.report(state, image, imageptr, "fixup location outside of image", disp)?;
+                    } else {
+                        let slice = &mut image[imageptr..imageptr+2];
+                        let curr = u16::from_le_bytes(slice.try_into().unwrap());
+                        let next = u16::wrapping_add(curr, disp as u16);
+
+                        slice.copy_from_slice(&next.to_le_bytes());
+                    }
+                },
+                _ => {
+                    return Err(LinkerError::new(
+                        &format!("invalid self-relative fixup location type {:?}", loctype)
+                    ))
                 }
-            },
+            }    
         }
-    } else {
-        let loc_delta = (imageptr as i32) - frame_base;
-        if loc_delta < 0 || loc_delta > 0xffff {
-            eprintln!("warning: fixup location {:08X}H is outside frame {:04X}H", imageptr, frame_base >> 4);
-        } else {
-            let target_delta = (target as i32) - frame_base;
+    }
 
-            if target_delta < 0 || target_delta > 0xffff {
-                eprintln!("warning: fixup location {:08X}H is outside frame {:04X}H", imageptr, frame_base >> 4);
-            }
+    Ok(())
+}
+
+/// Process a fixup subrecord of a FIXUPP which follows data for a debug segment. Addresses
+/// in debug information are logical segment numbers and offsets, as CodeView expects, and
+/// need no relocation by the loader.
+///
+fn pass2_fixupp_debug(rec: &mut Record, state: &LinkState, obj: &mut Object, b0: u8, lastdata: &LastDataRegion) -> Result<(), LinkerError> {
+    let locat = ((b0 as u16) << 8) | (rec.byte()? as u16);
+    let is_segment_rel = (locat & 0x4000) != 0;
+    let loctype = Locat::new((locat >> 10) & 0x000f)?;
+
+    let locations = lastdata.fixup_locations((locat as usize) & 0x3ff)?;
+
+    let (_, target) = pass2_fixup_data(rec, state, obj, lastdata)?;
+
+    if !is_segment_rel {
+        return Err(LinkerError::new("self-relative fixup in debug segment."));
+    }
+
+    let (seg, offset) = match codeview::logical_segment(state, target) {
+        Some(logical) => logical,
+        None => return Err(LinkerError::new(&format!("debug fixup target {:05X}H is not in any segment.", target))),
+    };
+
+    let data = obj.debug_data_mut(lastdata.debug.unwrap());
+
+    let add16 = |data: &mut [u8], at: usize, value: u16| -> Result<(), LinkerError> {
+        if at + 2 > data.len() {
+            return Err(LinkerError::new(&format!("debug fixup location {:04X}H is outside of data.", at)));
         }
 
-        match loctype {
-            Locat::Offset16 => {
-                let disp = (target as i32) - ((imageptr as i32) + 2);
+        let curr = u16::from_le_bytes([data[at], data[at+1]]);
+        data[at..at+2].copy_from_slice(&curr.wrapping_add(value).to_le_bytes());
+        Ok(())
+    };
 
-                let slice = &mut image[imageptr..imageptr+2];
-                let curr = u16::from_le_bytes(slice.try_into().unwrap());
-                let next = u16::wrapping_add(curr, disp as u16);
-                
-                slice.copy_from_slice(&next.to_le_bytes());
+    for ptr in locations {
+        match loctype {
+            Locat::Offset16 => add16(data, ptr, offset as u16)?,
+            Locat::Segment16 => add16(data, ptr, seg)?,
+            Locat::FarPointer => {
+                add16(data, ptr, offset as u16)?;
+                add16(data, ptr + 2, seg)?;
             },
-            _ => {
-                return Err(LinkerError::new(
-                    &format!("invalid self-relative fixup location type {:?}", loctype)
-                ))
-            }
-        }    
+            Locat::LowOrderByte => return Err(LinkerError::new("byte fixup in debug segment.")),
+        }
     }
 
     Ok(())
@@ -709,7 +1396,12 @@ fn pass2_fixupp(rec: &mut Record, state: &mut LinkState, obj: &mut Object, image
         let b0 = rec.byte()?;
 
         if (b0 & 0x80) == 0x00 {
-            pass2_fixupp_thread(rec, obj, b0)?;
+            obj.fixup_threads.read_thread(rec, b0)?;
+        } else if lastdata.discarded {
+            rec.byte()?;
+            obj.fixup_threads.read_fixup_data(rec)?;
+        } else if lastdata.debug.is_some() {
+            pass2_fixupp_debug(rec, state, obj, b0, lastdata)?;
         } else {
             pass2_fixupp_fixup(rec, state, obj, image, b0, lastdata, relocs)?;
         }
@@ -747,4 +1439,296 @@ fn pass2_modend(rec: &mut Record, state: &mut LinkState, obj: &Object, lastdata:
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::group::Group;
+    use crate::segment::{Align, SegDef, SegName, Segment};
+
+    #[test]
+    fn exe_allocations() -> Result<(), LinkerError> {
+        //
+        // 21H bytes of uninitialized data need 3 paragraphs, plus any extra asked for.
+        //
+        let mut image = vec![0u8; 0x40];
+        assert_eq!(exe_allocation(&mut image, 0x61, 0x40, 0, None, false)?, (0x40, 3, 0xffff));
+        assert_eq!(exe_allocation(&mut image, 0x61, 0x40, 0x100, Some(0x200), false)?, (0x40, 0x103, 0x200));
+        assert_eq!(image.len(), 0x40);
+
+        //
+        // Loaded high, the image is padded out to all the memory the program uses.
+        //
+        assert_eq!(exe_allocation(&mut image, 0x61, 0x40, 0, None, true)?, (0x61, 0, 0));
+        assert_eq!(image.len(), 0x61);
+
+        let mut image = vec![0u8; 0x40];
+        assert!(exe_allocation(&mut image, 0x40, 0x40, 0x10000, None, false)
+            .is_err_and(|err| err.to_string() == "program needs 10000H paragraphs beyond its image, more than an EXE header can ask for."));
+        assert!(exe_allocation(&mut image, 0x40, 0x40, 0, Some(0x10000), false)
+            .is_err_and(|err| err.to_string() == "maximum allocation of 10000H paragraphs is more than an EXE header can ask for."));
+        assert!(exe_allocation(&mut image, 0x61, 0x40, 0, Some(2), false)
+            .is_err_and(|err| err.to_string() == "maximum allocation of 2H paragraphs is less than the 3H paragraphs the program needs beyond its image."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn far_call_translation() {
+        //
+        // At 0100:0010, call far 0100:0040 then call far 0200:0000.
+        //
+        let mut image = vec![0u8; 0x1020];
+        image[0x1010..0x101a].copy_from_slice(&[0x9a, 0x00, 0x00, 0x00, 0x00, 0x9a, 0x00, 0x00, 0x00, 0x00]);
+        let lastdata = LastDataRegion{ frame: 0x100, base: 0x1010, length: 10, debug: None, discarded: false, iterated: None, code: true };
+
+        assert!(translate_far_call(&mut image, 0x1011, &lastdata, 0x100, 0x40));
+        assert_eq!(&image[0x1010..0x1015], &[0x90, 0x0e, 0xe8, 0x2b, 0x00]);
+
+        assert!(!translate_far_call(&mut image, 0x1016, &lastdata, 0x200, 0));
+        assert!(!translate_far_call(&mut image, 0x1010, &lastdata, 0x100, 0x40));
+
+        //
+        // The same bytes in data, such as a 9AH followed by a far pointer, are left alone.
+        //
+        image[0x1010..0x1015].copy_from_slice(&[0x9a, 0x00, 0x00, 0x00, 0x00]);
+        let lastdata = LastDataRegion{ code: false, ..lastdata };
+
+        assert!(!translate_far_call(&mut image, 0x1011, &lastdata, 0x100, 0x40));
+        assert_eq!(&image[0x1010..0x1015], &[0x9a, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn lidata_fixup_locations() -> Result<(), LinkerError> {
+        //
+        // Twice, a block of AA BB then three copies of a block of CC.
+        //
+        let rec = [
+            0xa2, 0x15, 0x00, 0x01, 0x00, 0x00,
+            0x02, 0x00, 0x02, 0x00,
+            0x01, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb,
+            0x03, 0x00, 0x00, 0x00, 0x01, 0xcc,
+            0x00,
+        ];
+        let mut rec = Record::new(&rec)?;
+        rec.index()?;
+        rec.word()?;
+        let start = rec.offset();
+
+        let mut data = Vec::new();
+        let mut sources = Vec::new();
+        accum_lidata_sources(&mut rec, start, &mut data, &mut sources)?;
+
+        assert_eq!(data, [0xaa, 0xbb, 0xcc, 0xcc, 0xcc, 0xaa, 0xbb, 0xcc, 0xcc, 0xcc]);
+        assert_eq!(sources, [9, 10, 16, 16, 16, 9, 10, 16, 16, 16]);
+
+        let lastdata = LastDataRegion{ frame: 0x10, base: 0x100, length: data.len(), debug: None, discarded: false, iterated: Some(sources), code: false };
+        assert_eq!(lastdata.fixup_locations(9)?, [0x100, 0x105]);
+        assert_eq!(lastdata.fixup_locations(16)?, [0x102, 0x103, 0x104, 0x107, 0x108, 0x109]);
+        assert!(lastdata.fixup_locations(4).is_err());
+
+        let lastdata = LastDataRegion{ iterated: None, ..lastdata };
+        assert_eq!(lastdata.fixup_locations(4)?, [0x104]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_data() {
+        let mut initmap = InitMap::new(0x20);
+
+        initmap.start_module("a.obj");
+        assert_eq!(initmap.initialize(0x00, 0x10), None);
+        assert_eq!(initmap.initialize(0x08, 0x04), None);
+
+        initmap.start_module("b.obj");
+        assert_eq!(initmap.initialize(0x10, 0x08), None);
+        assert_eq!(initmap.initialize(0x0c, 0x08), Some((0x0c, "a.obj")));
+
+        initmap.start_module("c.obj");
+        assert_eq!(initmap.initialize(0x18, 0x08), None);
+        assert_eq!(initmap.initialize(0x00, 0x20), Some((0x00, "a.obj")));
+    }
+
+    #[test]
+    fn overlap_errors() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let name = state.lnames.add("_DATA");
+        state.segments.add(Segment::new(SegName::new(name, name, 0), 0x10, Align::Para, Combine::Public));
+        state.segments[1].base = 0x20;
+
+        let mut obj = Object::new();
+        obj.segdefs.add(SegDef::new(1, 0x10, 0x68, Align::Para, Combine::Public));
+
+        let mut image = vec![0; 0x30];
+        let mut initmap = InitMap::new(image.len());
+
+        initmap.start_module("a.obj");
+        obj.name = "a.obj".to_owned();
+        install_data(&state, &obj, 1, &mut image, &mut initmap, 0x20, &[1, 2, 3, 4])?;
+
+        //
+        // Overlapping data is only a warning unless overlaps are errors.
+        //
+        initmap.start_module("b.obj");
+        obj.name = "b.obj".to_owned();
+        install_data(&state, &obj, 1, &mut image, &mut initmap, 0x22, &[5, 6])?;
+        assert_eq!(image[0x20..0x24], [1, 2, 5, 6]);
+
+        state.overlap_errors = true;
+        assert!(install_data(&state, &obj, 1, &mut image, &mut initmap, 0x21, &[7, 8])
+            .is_err_and(|err| err.to_string() == "data of module b.obj overlaps data of module a.obj at 0002:0001 in segment _DATA."));
+        install_data(&state, &obj, 1, &mut image, &mut initmap, 0x28, &[9])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn fixup_overflow_report() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let names = ["_TEXT", "CODE", "FAR2", "FAR_DATA"].map(|name| state.lnames.add(name));
+
+        state.segments.add(Segment::new(SegName::new(names[0], names[1], 0), 0x06, Align::Para, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(names[2], names[3], 0), 0x10, Align::Para, Combine::Public));
+        state.segments[2].base = 0x10000;
+        state.segment_order = vec![1, 2];
+        state.symbols.update("FARVAR", Symbol::public(0, 2, 0, 4))?;
+
+        let mut obj = Object::new();
+        obj.name = "fa".to_owned();
+        obj.extdefs.add("FARVAR".to_owned());
+
+        //
+        // mov ax,[FARVAR] relative to the frame of the code, which FARVAR is not in.
+        //
+        let mut image = vec![0xa1, 0x00, 0x00, 0xcd, 0x20, 0x90];
+        let lastdata = LastDataRegion{ frame: 0, base: 0, length: 6, debug: None, discarded: false, iterated: None, code: false };
+        let fixup = FixupData{ frame_type: FrameType::FromTarget, frame_index: 0, target_type: TargetType::EXTDEF, target_index: 1, target_disp: 0 };
+
+        let context = FixupContext{ obj: &obj, lastdata: &lastdata, fixup: &fixup, fbval: 0 };
+        assert!(context.report(&state, &image, 1, "fixup overflow", 0x10004).is_ok());
+
+        state.fixup_errors = true;
+        image[1] = 0x04;
+        assert!(context.report(&state, &image, 1, "fixup overflow", 0x10004).is_err_and(|err| err.to_string() ==
+            "module fa: fixup overflow at 0000:0001 in segment _TEXT: target symbol FARVAR, frame 0000H, value 10004H, data A1 04 00 CD 20"));
+
+        //
+        // A location past the end of the data has none to show.
+        //
+        assert!(context.report(&state, &image, 0x10, "fixup overflow", 0x10004).is_err_and(|err| err.to_string() ==
+            "module fa: fixup overflow at 0000:0010 in segment ?: target symbol FARVAR, frame 0000H, value 10004H, data none"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fixup_outside_image() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let names = ["_TEXT", "CODE", "FAR2", "FAR_DATA"].map(|name| state.lnames.add(name));
+
+        state.segments.add(Segment::new(SegName::new(names[0], names[1], 0), 0x06, Align::Para, Combine::Public));
+        state.segments.add(Segment::new(SegName::new(names[2], names[3], 0), 0x10, Align::Para, Combine::Public));
+        state.segments[2].base = 0x10000;
+        state.segment_order = vec![1, 2];
+        state.symbols.update("FARVAR", Symbol::public(0, 2, 0, 4))?;
+        state.fixup_errors = true;
+
+        let mut obj = Object::new();
+        obj.name = "fa".to_owned();
+        obj.extdefs.add("FARVAR".to_owned());
+
+        //
+        // An offset fixup of FARVAR at offset 5, which runs off the end of the image.
+        //
+        let mut image = vec![0xa1, 0x00, 0x00, 0xcd, 0x20, 0x90];
+        let lastdata = LastDataRegion{ frame: 0, base: 0, length: 6, debug: None, discarded: false, iterated: None, code: true };
+        let data = [0x9c, 0x06, 0x00, 0x05, 0x52, 0x01, 0x00, 0x00, 0x00];
+        let mut rec = Record::new(&data)?;
+
+        assert!(pass2_fixupp_fixup(&mut rec, &mut state, &obj, &mut image, 0xc4, &lastdata, &mut Vec::new()).is_err_and(|err| err.to_string() ==
+            "module fa: fixup location outside of image at 0000:0005 in segment _TEXT: target symbol FARVAR, frame 1000H, value 4H, data 00 00 CD 20 90"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn extdef_frames() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let names = ["CODE", "FAR_DATA", "DGROUP"].map(|name| state.lnames.add(name));
+
+        for (class, base) in [(names[0], 0x0000), (names[0], 0x0020), (names[1], 0x10000)] {
+            let mut segment = Segment::new(SegName::new(0, class, 0), 0x10, Align::Para, Combine::Public);
+            segment.base = base;
+            state.segments.add(segment);
+        }
+
+        state.segments[2].pack = 1;
+        let mut group = Group::new(names[2]);
+        group.base = 0x8000;
+        state.groups.add(group);
+
+        state.symbols.update("PACKED", Symbol::public(0, 2, 0, 4))?;
+        state.symbols.update("FAR", Symbol::public(0, 3, 0, 0))?;
+        state.symbols.update("GROUPED", Symbol::public(1, 3, 0, 0))?;
+        state.symbols.update("ABSOLUTE", Symbol::public(0, 0, 0xb800, 0))?;
+
+        //
+        // The module has no SEGDEFs or GRPDEFs of its own; the symbols' segments and
+        // groups are the linker's.
+        //
+        let mut obj = Object::new();
+
+        for name in ["PACKED", "FAR", "GROUPED", "ABSOLUTE"] {
+            obj.extdefs.add(name.to_owned());
+        }
+
+        assert_eq!(fixup_extdef_frame(&state, &obj, 1)?, 0x0000);
+        assert_eq!(fixup_extdef_frame(&state, &obj, 2)?, 0x1000);
+        assert_eq!(fixup_extdef_frame(&state, &obj, 3)?, 0x0800);
+        assert_eq!(fixup_extdef_frame(&state, &obj, 4)?, 0xb800);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prev_data_frame() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let mut segment = Segment::new(SegName::new(0, 0, 0), 0x40, Align::Para, Combine::Public);
+        segment.base = 0x10020;
+        state.segments.add(segment);
+        state.symbols.update("VAR", Symbol::public(0, 1, 0, 0x12))?;
+
+        let mut obj = Object::new();
+        obj.extdefs.add("VAR".to_owned());
+
+        //
+        // The frame is that of the segment holding the data, not the data's own paragraph.
+        //
+        let lastdata = LastDataRegion{ frame: 0x1000, base: 0x10032, length: 4, debug: None, discarded: false, iterated: None, code: false };
+        let fixup = FixupData{ frame_type: FrameType::SegOfPrevData, frame_index: 0, target_type: TargetType::EXTDEF, target_index: 1, target_disp: 0 };
+
+        assert_eq!(resolve_fixup_data(&state, &obj, &lastdata, &fixup)?, (0x1000, 0x10032));
+
+        Ok(())
+    }
+
+    #[test]
+    fn backpatches() -> Result<(), LinkerError> {
+        assert_eq!(backpatch_size(1, false)?, 2);
+        assert!(backpatch_size(2, false).is_err());
+        assert_eq!(backpatch_size(2, true)?, 4);
+
+        let mut image = vec![0x90, 0xeb, 0x00, 0xe9, 0xfe, 0xff, 0x00, 0x00, 0x00, 0x00];
+        let patches = [
+            BackPatch{ base: 2, size: 1, value: 0x0105 },
+            BackPatch{ base: 4, size: 2, value: 0x0010 },
+            BackPatch{ base: 6, size: 4, value: 0x12345678 },
+        ];
+        apply_backpatches(&mut image, &patches);
+
+        assert_eq!(image, [0x90, 0xeb, 0x05, 0xe9, 0x0e, 0x00, 0x78, 0x56, 0x34, 0x12]);
+
+        Ok(())
+    }
+}
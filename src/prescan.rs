@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+use std::thread;
+
+use crate::linker_error::LinkerError;
+use crate::object::Object;
+use crate::record::{Record, RecordType};
+use crate::segment::{Align, Combine};
+
+//
+// Reading and pre-scanning object modules, which is done for many modules at once. A
+// module's records are framed, and its LNAMES, SEGDEF, EXTDEF and PUBDEF records decoded,
+// into tables which do not depend on the rest of the link. Pass 1 then merges the tables
+// into the link state one module at a time, in the order a sequential link would, so the
+// output does not depend on how the work was scheduled.
+//
+// Errors in decoding a record are kept in the scan at the record they occur in, so that
+// they are reported when the merge reaches that record, as they would be sequentially.
+//
+
+/// The fields of a SEGDEF record, with name indices local to the module.
+///
+pub struct SegDefRecord {
+    pub acbp: u8,
+    pub align: Align,
+    pub combine: Combine,
+    pub length: u16,
+    pub nameidx: usize,
+    pub classidx: usize,
+    pub ovlyidx: usize,
+}
+
+impl SegDefRecord {
+    pub fn read(rec: &mut Record) -> Result<SegDefRecord, LinkerError> {
+        let acbp = rec.byte()?;
+
+        let align = Align::from_acbp(acbp)?;
+        let combine = Combine::from_acbp(acbp)?;
+
+        if align == Align::Absolute {
+            //
+            // We do not support it, but if align is Absolute, there are an absolute frame and offset.
+            //
+            let _frame = rec.word()?;
+            let _offset = rec.byte()?;
+        }
+
+        let length = rec.word()?;
+        let nameidx = rec.index()?;
+        let classidx = rec.index()?;
+        let ovlyidx = rec.index()?;
+
+        Ok(SegDefRecord { acbp, align, combine, length, nameidx, classidx, ovlyidx })
+    }
+}
+
+/// The fields of a PUBDEF record: the group and segment indices local to the module, the
+/// frame if the segment is absolute, and each public's name and offset.
+///
+pub struct PubDefRecord {
+    pub group: usize,
+    pub segment: usize,
+    pub frame: u16,
+    pub publics: Vec<(String, u16)>,
+}
+
+impl PubDefRecord {
+    pub fn read(rec: &mut Record) -> Result<PubDefRecord, LinkerError> {
+        let group = rec.index()?;
+        let segment = rec.index()?;
+        let frame = if segment == 0 { rec.word()? } else { 0 };
+
+        let mut publics = Vec::new();
+
+        while !rec.end() {
+            let name = rec.counted_string()?;
+            let offset = rec.word()?;
+
+            //
+            // There is an unused type index after each symbol.
+            //
+            rec.index()?;
+
+            publics.push((name, offset));
+        }
+
+        Ok(PubDefRecord { group, segment, frame, publics })
+    }
+}
+
+/// Parse an LNAMES or LLNAMES record, returning the names.
+///
+pub fn read_lnames(rec: &mut Record) -> Result<Vec<String>, LinkerError> {
+    let mut names = Vec::new();
+
+    while !rec.end() {
+        names.push(rec.counted_string()?);
+    }
+
+    Ok(names)
+}
+
+/// Parse an EXTDEF record, returning just the names without updating any data structures.
+///
+pub fn read_extdef_names(rec: &mut Record) -> Result<Vec<String>, LinkerError> {
+    let mut names = Vec::new();
+
+    while !rec.end() {
+        let name = rec.counted_string()?;
+
+        //
+        // there is an unused type index after every name.
+        //
+        rec.index()?;
+
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+/// What the scan found in a record.
+///
+pub enum Scanned {
+    Names(Vec<String>),
+    SegDef(SegDefRecord),
+    PubDef(PubDefRecord),
+    Other,
+    Error(LinkerError),
+}
+
+/// A record of a scanned module: where it starts in the module, its type (None if it
+/// could not be framed), and its decoded contents.
+///
+pub struct ScannedRecord {
+    pub start: usize,
+    pub rectype: Option<RecordType>,
+    pub scanned: Scanned,
+}
+
+/// The records of an object module, up to its MODEND.
+///
+pub struct ModuleScan {
+    pub records: Vec<ScannedRecord>,
+}
+
+impl ModuleScan {
+    /// Frame the records of a module and decode those pass 1 merges into the link state.
+    /// Scanning stops at MODEND, or at a record which cannot be framed.
+    ///
+    pub fn new(data: &[u8]) -> ModuleScan {
+        let mut records = Vec::new();
+        let mut start = 0;
+
+        while start < data.len() {
+            let mut rec = match Record::new(&data[start..]) {
+                Ok(rec) => rec,
+                Err(err) => {
+                    records.push(ScannedRecord { start, rectype: None, scanned: Scanned::Error(err) });
+                    break;
+                },
+            };
+
+            let rectype = rec.rectype;
+
+            let scanned = match rectype {
+                RecordType::LNAMES |
+                RecordType::LLNAMES => read_lnames(&mut rec).map(Scanned::Names),
+                RecordType::EXTDEF => read_extdef_names(&mut rec).map(Scanned::Names),
+                RecordType::SEGDEF => SegDefRecord::read(&mut rec).map(Scanned::SegDef),
+                RecordType::PUBDEF => PubDefRecord::read(&mut rec).map(Scanned::PubDef),
+                _ => Ok(Scanned::Other),
+            };
+
+            records.push(ScannedRecord { start, rectype: Some(rectype), scanned: scanned.unwrap_or_else(Scanned::Error) });
+
+            if rectype == RecordType::MODEND {
+                break;
+            }
+
+            start += rec.total_length();
+        }
+
+        ModuleScan { records }
+    }
+}
+
+/// Apply `f` to each item on up to `jobs` threads, returning the results in the order of
+/// the items.
+///
+pub fn map_in_parallel<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk = items.len().div_ceil(jobs);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = items.chunks(chunk)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<R>>()))
+            .collect();
+
+        workers.into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// The number of threads to use by default: one per CPU.
+///
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |jobs| jobs.get())
+}
+
+/// Read and scan object files. Each file's result is kept, so that an error reading a
+/// file is reported only once the files before it have been linked.
+///
+pub fn read_objects(paths: &[PathBuf], jobs: usize) -> Vec<Result<(Object, ModuleScan), LinkerError>> {
+    map_in_parallel(paths, jobs, |path| {
+        let obj = Object::from_filename(path)?;
+        let scan = ModuleScan::new(obj.data.as_ref().unwrap());
+        Ok((obj, scan))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scan() {
+        let data = [
+            0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
+            0x96, 0x09, 0x00, 0x03, 0x41, 0x42, 0x43, 0x03, 0x44, 0x45, 0x46, 0x00,
+            0x98, 0x07, 0x00, 0x28, 0x10, 0x00, 0x02, 0x03, 0x01, 0x00,
+            0x90, 0x05, 0x00, 0x00, 0x01, 0x03, 0x41, 0x00,
+            0x8a, 0x02, 0x00, 0x00, 0x00,
+            0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
+        ];
+
+        let scan = ModuleScan::new(&data);
+        let starts: Vec<usize> = scan.records.iter().map(|rec| rec.start).collect();
+        assert_eq!(starts, [0, 8, 20, 30, 38]);
+
+        assert!(matches!(scan.records[0].scanned, Scanned::Other));
+        assert!(matches!(&scan.records[1].scanned, Scanned::Names(names) if names == &["ABC", "DEF"]));
+        assert!(matches!(&scan.records[2].scanned,
+            Scanned::SegDef(segdef) if segdef.length == 0x10 && segdef.nameidx == 2 && segdef.combine == Combine::Public));
+
+        //
+        // The PUBDEF is truncated, which is reported when the merge reaches it.
+        //
+        assert!(matches!(&scan.records[3].scanned, Scanned::Error(err) if err.to_string() == "record is truncated."));
+        assert_eq!(scan.records[4].rectype, Some(RecordType::MODEND));
+
+        let scan = ModuleScan::new(&data[..24]);
+        assert_eq!(scan.records.len(), 3);
+        assert!(scan.records[2].rectype.is_none());
+    }
+
+    #[test]
+    fn parallel_order() {
+        let items: Vec<usize> = (0..100).collect();
+
+        for jobs in [1, 3, 8, 200] {
+            assert_eq!(map_in_parallel(&items, jobs, |item| item * 2), (0..200).step_by(2).collect::<Vec<usize>>());
+        }
+    }
+}
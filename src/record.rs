@@ -14,6 +14,8 @@ pub enum RecordType {
     MODEND = 0x8a,
     EXTDEF = 0x8c,
     PUBDEF = 0x90,
+    LINNUM = 0x94,
+    LINNUM32 = 0x95,
     LNAMES = 0x96,
     SEGDEF = 0x98,
     GRPDEF = 0x9a,
@@ -21,9 +23,17 @@ pub enum RecordType {
     LEDATA = 0xa0,
     LIDATA = 0xa2,
     COMDEF = 0xb0,
+    BAKPAT = 0xb2,
+    BAKPAT32 = 0xb3,
     LEXTDEF = 0xb4,
     LPUBDEF = 0xb6,
     LCOMDEF = 0xb8,
+    CEXTDEF = 0xbc,
+    COMDAT = 0xc2,
+    COMDAT32 = 0xc3,
+    NBKPAT = 0xc8,
+    NBKPAT32 = 0xc9,
+    LLNAMES = 0xca,
     LIBHDR = 0xf0,
     LIBEND = 0xf1,
     EXTDCT = 0xf2,
@@ -37,6 +47,8 @@ impl RecordType {
             0x8a => RecordType::MODEND,
             0x8c => RecordType::EXTDEF,
             0x90 => RecordType::PUBDEF,
+            0x94 => RecordType::LINNUM,
+            0x95 => RecordType::LINNUM32,
             0x96 => RecordType::LNAMES,
             0x98 => RecordType::SEGDEF,
             0x9a => RecordType::GRPDEF,
@@ -44,9 +56,17 @@ impl RecordType {
             0xa0 => RecordType::LEDATA,
             0xa2 => RecordType::LIDATA,
             0xb0 => RecordType::COMDEF,
+            0xb2 => RecordType::BAKPAT,
+            0xb3 => RecordType::BAKPAT32,
             0xb4 => RecordType::LEXTDEF,
             0xb6 => RecordType::LPUBDEF,
             0xb8 => RecordType::LCOMDEF,
+            0xbc => RecordType::CEXTDEF,
+            0xc2 => RecordType::COMDAT,
+            0xc3 => RecordType::COMDAT32,
+            0xc8 => RecordType::NBKPAT,
+            0xc9 => RecordType::NBKPAT32,
+            0xca => RecordType::LLNAMES,
             0xf0 => RecordType::LIBHDR,
             0xf1 => RecordType::LIBEND,
             0xf2 => RecordType::EXTDCT,
@@ -207,6 +227,12 @@ impl<'a> Record<'a> {
         slice
     }
 
+    /// Return the offset of the next byte to be parsed in the record proper.
+    ///
+    pub fn offset(&self) -> usize {
+        self.next
+    }
+
     /// Check if all data in the record has been parsed.
     ///
     pub fn end(&self) -> bool {
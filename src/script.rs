@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::linker_error::LinkerError;
+
+//
+// Linker scripts, which control where segments are placed in memory. A script is a list
+// of statements, one per line, with # starting a comment:
+//
+//     order CLASS...                  place segments of these classes first, in this order
+//     region NAME START SIZE          a region of SIZE bytes from paragraph START
+//     place NAME in REGION            place a segment, or all of a class, in a region
+//     at NAME PARAGRAPH               place a segment or group at a fixed paragraph
+//     align NAME BYTES                align a segment to a multiple of BYTES
+//     pad NAME BYTES                  leave BYTES of padding before a segment
+//     load SEGMENT in REGION as SYMBOL
+//                                     store a segment's initial data in a region rather
+//                                     than where it runs, for startup code to copy; the
+//                                     absolute SYMBOL is the paragraph it is stored at
+//
+// Paragraphs are relative to the start of the image. Numbers are decimal, or hex with
+// a 0x prefix.
+//
+
+/// A named range of memory segments can be placed in.
+///
+#[derive(Debug, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub start: usize,
+    pub size: usize,
+}
+
+/// How to place a segment, group or class.
+///
+#[derive(Debug, Default, PartialEq)]
+pub struct Placement {
+    pub at: Option<usize>,
+    pub align: Option<usize>,
+    pub pad: usize,
+    pub region: Option<String>,
+}
+
+/// A segment whose initial data is stored somewhere other than where it runs.
+///
+#[derive(Debug, PartialEq)]
+pub struct Load {
+    pub segment: String,
+    pub region: String,
+    pub symbol: String,
+}
+
+#[derive(Debug, Default)]
+pub struct LinkScript {
+    pub class_order: Vec<String>,
+    pub regions: Vec<Region>,
+    pub placements: HashMap<String, Placement>,
+    pub loads: Vec<Load>,
+}
+
+fn number(word: &str) -> Result<usize, LinkerError> {
+    let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+
+    value.map_err(|_| LinkerError::new(&format!("{} is not a number.", word)))
+}
+
+impl LinkScript {
+    pub fn parse(text: &str) -> Result<LinkScript, LinkerError> {
+        let mut script = LinkScript::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            script.statement(&words).map_err(|err| LinkerError::new(&format!("script line {}: {}", i + 1, err)))?;
+        }
+
+        for (name, placement) in script.placements.iter() {
+            if let Some(region) = &placement.region {
+                if script.region(region).is_none() {
+                    return Err(LinkerError::new(&format!("{} is placed in undefined region {}.", name, region)));
+                }
+            }
+        }
+
+        if let Some(load) = script.loads.iter().find(|load| script.region(&load.region).is_none()) {
+            return Err(LinkerError::new(&format!("{} is loaded in undefined region {}.", load.segment, load.region)));
+        }
+
+        Ok(script)
+    }
+
+    pub fn read(path: &PathBuf) -> Result<LinkScript, LinkerError> {
+        LinkScript::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Add the placements given on the command line, by `--order` and `--at`, as if they
+    /// were statements at the end of the script.
+    ///
+    pub fn add_options(&mut self, order: &[String], at: &[String]) -> Result<(), LinkerError> {
+        self.class_order.extend(order.iter().cloned());
+
+        for option in at {
+            let Some((name, paragraph)) = option.split_once('=') else {
+                return Err(LinkerError::new(&format!("--at {} is not NAME=PARAGRAPH.", option)));
+            };
+
+            self.statement(&["at", name, paragraph])?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, words: &[&str]) -> Result<(), LinkerError> {
+        let keyword = words.first().map(|word| word.to_ascii_lowercase()).unwrap_or_default();
+
+        match (keyword.as_str(), words.len()) {
+            ("", _) => {},
+            ("order", _) => self.class_order.extend(words[1..].iter().map(|word| word.to_string())),
+            ("region", 4) => self.regions.push(Region {
+                name: words[1].to_owned(),
+                start: number(words[2])? << 4,
+                size: number(words[3])?,
+            }),
+            ("place", 4) if words[2].eq_ignore_ascii_case("in") => self.placement(words[1]).region = Some(words[3].to_owned()),
+            ("at", 3) => self.placement(words[1]).at = Some(number(words[2])?),
+            ("align", 3) => {
+                let align = number(words[2])?;
+                if align == 0 {
+                    return Err(LinkerError::new("alignment must not be zero."));
+                }
+                self.placement(words[1]).align = Some(align);
+            },
+            ("pad", 3) => self.placement(words[1]).pad = number(words[2])?,
+            ("load", 6) if words[2].eq_ignore_ascii_case("in") && words[4].eq_ignore_ascii_case("as") => self.loads.push(Load {
+                segment: words[1].to_owned(),
+                region: words[3].to_owned(),
+                symbol: words[5].to_owned(),
+            }),
+            _ => return Err(LinkerError::new(&format!("cannot understand \"{}\".", words.join(" ")))),
+        }
+
+        Ok(())
+    }
+
+    fn placement(&mut self, name: &str) -> &mut Placement {
+        self.placements.entry(name.to_owned()).or_default()
+    }
+
+    pub fn region(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// Where a class comes in the memory map: classes named by `order` first, in that
+    /// order, then all others.
+    ///
+    pub fn class_rank(&self, class: &str) -> usize {
+        self.class_order.iter().position(|name| name == class).unwrap_or(self.class_order.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statements() -> Result<(), LinkerError> {
+        let script = LinkScript::parse("
+            # A ROM with its data copied to RAM.
+            order CODE DATA BSS
+            region ROM 0xf000 0x10000
+            region RAM 0x40 0x8000
+            place CODE in ROM
+            place _DATA in RAM
+            at DGROUP 0x40
+            align _BSS 0x100
+            pad _TEXT 16         # room for a jump
+            load _DATA in ROM as DATA_LOAD
+        ")?;
+
+        assert_eq!(script.class_rank("DATA"), 1);
+        assert_eq!(script.class_rank("STACK"), 3);
+        assert_eq!(script.region("ROM"), Some(&Region { name: "ROM".to_owned(), start: 0xf0000, size: 0x10000 }));
+        assert_eq!(script.placements["CODE"].region.as_deref(), Some("ROM"));
+        assert_eq!(script.placements["DGROUP"].at, Some(0x40));
+        assert_eq!(script.placements["_BSS"].align, Some(0x100));
+        assert_eq!(script.placements["_TEXT"].pad, 16);
+        assert_eq!(script.loads[0].symbol, "DATA_LOAD");
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        assert!(LinkScript::parse("order CODE\nat _TEXT").is_err_and(|err| err.to_string() == "script line 2: cannot understand \"at _TEXT\"."));
+        assert!(LinkScript::parse("align _TEXT 0").is_err());
+        assert!(LinkScript::parse("place CODE in ROM").is_err());
+        assert!(LinkScript::parse("region ROM 0xf000 zero").is_err());
+    }
+
+    #[test]
+    fn options() -> Result<(), LinkerError> {
+        let mut script = LinkScript::parse("order CODE\nat _TEXT 0x10")?;
+        script.add_options(&["DATA".to_owned(), "BSS".to_owned()], &["DGROUP=0x40".to_owned(), "_TEXT=0".to_owned()])?;
+
+        assert_eq!(script.class_order, ["CODE", "DATA", "BSS"]);
+        assert_eq!(script.placements["DGROUP"].at, Some(0x40));
+        assert_eq!(script.placements["_TEXT"].at, Some(0));
+
+        let mut script = LinkScript::default();
+        assert!(script.add_options(&[], &["DGROUP".to_owned()])
+            .is_err_and(|err| err.to_string() == "--at DGROUP is not NAME=PARAGRAPH."));
+        assert!(script.add_options(&[], &["DGROUP=low".to_owned()])
+            .is_err_and(|err| err.to_string() == "low is not a number."));
+
+        Ok(())
+    }
+}
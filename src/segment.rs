@@ -37,6 +37,21 @@ impl Align {
         })
     }
 
+    /// The alignment field of an ACBP byte.
+    ///
+    pub fn to_acbp(self) -> u8 {
+        let align = match self {
+            Align::Absolute => 0,
+            Align::Byte => 1,
+            Align::Word => 2,
+            Align::Para => 3,
+            Align::Page => 4,
+            Align::Dword => 5,
+        };
+
+        align << 5
+    }
+
     /// Given an offset, adjust it upwards to the next boundary implied
     /// by the alignment.
     /// 
@@ -77,10 +92,31 @@ impl Combine {
     }
 }
 
+/// The kinds of debug information segment. These hold CodeView data for the debugger
+/// rather than anything to be loaded, and are kept per module instead of being combined.
+///
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DebugInfo {
+    Types,
+    Symbols,
+}
+
+impl DebugInfo {
+    /// Recognize a debug segment by its name and class.
+    ///
+    pub fn from_names(name: &str, class: &str) -> Option<Self> {
+        match (name, class) {
+            (_, "DEBTYP") | ("$$TYPES", _) => Some(DebugInfo::Types),
+            (_, "DEBSYM") | ("$$SYMBOLS", _) => Some(DebugInfo::Symbols),
+            _ => None,
+        }
+    }
+}
+
 /// A `SegDef` is the representation of a segment in the object module.
 /// It contains a reference back to the combined segment, as well as 
 /// the base and length of the segment's data owned by the object 
-/// module.
+/// module. A SEGDEF nothing refers to may be discarded by garbage collection.
 /// 
 pub struct SegDef {
     pub segidx: usize,
@@ -89,6 +125,7 @@ pub struct SegDef {
     pub acbp: u8,
     pub align: Align,
     pub combine: Combine,
+    pub discarded: bool,
 }
 
 impl SegDef {
@@ -99,7 +136,8 @@ impl SegDef {
             length,
             acbp,
             align,
-            combine
+            combine,
+            discarded: false,
         }
     }
 }
@@ -122,7 +160,8 @@ impl SegName {
 
 /// A `Segment` is the collection of all combined `SegDef`'s of the same
 /// name. It represents a contiguous region of memory in the final executable's
-/// address space.
+/// address space. A segment packed with the ones before it has `pack` set to the
+/// first segment of the pack, whose frame it shares.
 ///
 #[derive(Clone)] 
 pub struct Segment {
@@ -132,6 +171,9 @@ pub struct Segment {
     pub combine: Combine,
     pub base: usize,
     pub group: usize,
+    pub debug: Option<DebugInfo>,
+    pub has_data: bool,
+    pub pack: usize,
 }
 
 /// The maximum size of a 32-bit segment.
@@ -140,7 +182,7 @@ const MAX_SEGMENT_SIZE: usize = 0x10000;
 
 impl Segment {
     pub fn new(name: SegName, length: usize, align: Align, combine: Combine) -> Segment {
-        Segment{ name, length, align, combine, base: 0, group: 0 }
+        Segment{ name, length, align, combine, base: 0, group: 0, debug: None, has_data: false, pack: 0 }
     }
 
     /// Add a SEGDEF to the segment, validating the combine type and total size, and returning
@@ -49,6 +49,7 @@ impl SymbolTable {
         }
     }
 
+    #[cfg(test)]
     pub fn undefined_symbols(&self) -> Vec<&String> {
         self.symbols.keys().filter(|s| self.symbols[*s] == Symbol::Undefined).collect()
     }
@@ -0,0 +1,238 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::linker_error::LinkerError;
+use crate::linkstate::LinkState;
+use crate::symbols::Symbol;
+
+//
+// Symbol files in the format written by MAPSYM, as loaded by SYMDEB, WDEB386 and
+// SoftICE.
+//
+// The file is a map header, followed by a paragraph-aligned definition of each
+// segment, followed by an end-of-maps marker. Each definition holds the symbols
+// (value and name) and then a table of pointers to the symbols sorted by value.
+// Segment definitions are linked in a ring by paragraph number.
+//
+
+/// The MAPSYM version the file claims to be from.
+///
+const SYM_VERSION: (u8, u8) = (4, 0);
+
+/// The size of the fixed part of the map header.
+///
+const MAPDEF_SIZE: usize = 16;
+
+/// The size of the fixed part of a segment definition.
+///
+const SEGDEF_SIZE: usize = 21;
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..std::cmp::min(name.len(), 255)];
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(name);
+}
+
+fn pad_paragraph(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(16) {
+        buf.push(0);
+    }
+}
+
+/// Append symbols, then the table of pointers to them in value order, to `buf`. The
+/// pointers are relative to `base`, the start of the structure that owns the symbols.
+///
+fn push_symbols(buf: &mut Vec<u8>, base: usize, symbols: &mut [(u16, &String)]) -> Result<usize, LinkerError> {
+    symbols.sort();
+
+    let mut pointers = Vec::new();
+
+    for (value, name) in symbols.iter() {
+        pointers.push(buf.len() - base);
+        push_u16(buf, *value);
+        push_name(buf, name);
+    }
+
+    let table = buf.len() - base;
+
+    for pointer in pointers {
+        let pointer = u16::try_from(pointer).map_err(|_| LinkerError::new("too many symbols in one segment for .SYM file."))?;
+        push_u16(buf, pointer);
+    }
+
+    Ok(table)
+}
+
+/// Build a .SYM file for the linked program, naming the map `module`.
+///
+pub fn symfile(state: &LinkState, module: &str) -> Result<Vec<u8>, LinkerError> {
+    //
+    // Sort the publics into absolute symbols and those in each segment.
+    //
+    let mut absolutes = Vec::new();
+    let mut segment_symbols = vec![Vec::new(); state.segments.len() + 1];
+
+    for (name, symbol) in state.symbols.symbols.iter() {
+        if let Symbol::Public(public) = symbol {
+            if public.segment == 0 {
+                absolutes.push((public.offset, name));
+            } else {
+                let base = state.segment_address_base(public.segment);
+                let value = (base & 0x000f) + state.segments[public.segment].base - base + public.offset as usize;
+                segment_symbols[public.segment].push((value as u16, name));
+            }
+        }
+    }
+
+    let max_name = state.symbols.symbols.keys().map(|name| name.len()).max().unwrap_or(0);
+
+    //
+    // Map header, with the absolute symbols.
+    //
+    let mut sym = vec![0u8; MAPDEF_SIZE - 1];
+    push_name(&mut sym, module);
+    let abs_table = push_symbols(&mut sym, 0, &mut absolutes)?;
+    pad_paragraph(&mut sym);
+
+    let first_segdef = sym.len() >> 4;
+
+    sym[4..6].copy_from_slice(&state.entry.map(|entry| entry.seg).unwrap_or(0).to_le_bytes());
+    sym[6..8].copy_from_slice(&(absolutes.len() as u16).to_le_bytes());
+    sym[8..10].copy_from_slice(&(abs_table as u16).to_le_bytes());
+    sym[10..12].copy_from_slice(&(state.segment_order.len() as u16).to_le_bytes());
+    sym[12..14].copy_from_slice(&(first_segdef as u16).to_le_bytes());
+    sym[14] = std::cmp::min(max_name, 255) as u8;
+
+    //
+    // Segment definitions, in memory map order.
+    //
+    let mut segdefs = Vec::new();
+
+    for segidx in state.segment_order.iter() {
+        let seg = &state.segments[*segidx];
+        let symbols = &mut segment_symbols[*segidx];
+
+        let start = sym.len();
+        segdefs.push(start);
+
+        sym.resize(start + SEGDEF_SIZE - 1, 0);
+        push_name(&mut sym, state.lnames.get(seg.name.nameidx));
+        let table = push_symbols(&mut sym, start, symbols)?;
+
+        sym[start+2..start+4].copy_from_slice(&(symbols.len() as u16).to_le_bytes());
+        sym[start+4..start+6].copy_from_slice(&(table as u16).to_le_bytes());
+        sym[start+6..start+8].copy_from_slice(&((state.segment_address_base(*segidx) >> 4) as u16).to_le_bytes());
+
+        pad_paragraph(&mut sym);
+
+        if sym.len() > 0xffff0 {
+            return Err(LinkerError::new(".SYM file is too large."));
+        }
+    }
+
+    //
+    // Link the segment definitions into a ring.
+    //
+    for (i, start) in segdefs.iter().enumerate() {
+        let next = segdefs[(i + 1) % segdefs.len()] >> 4;
+        sym[*start..*start+2].copy_from_slice(&(next as u16).to_le_bytes());
+    }
+
+    //
+    // End of maps: no next map, then the version.
+    //
+    push_u16(&mut sym, 0);
+    sym.push(SYM_VERSION.1);
+    sym.push(SYM_VERSION.0);
+
+    Ok(sym)
+}
+
+/// Write a .SYM file, named for the map it contains.
+///
+pub fn write_symfile(path: &PathBuf, state: &LinkState) -> Result<(), LinkerError> {
+    let module = path.file_stem().map(|stem| stem.to_string_lossy().to_uppercase()).unwrap_or_default();
+
+    let mut fp = File::create(path)?;
+    fp.write_all(&symfile(state, &module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::segment::{Align, Combine, SegName, Segment};
+
+    #[test]
+    fn segments_and_symbols() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let name = state.lnames.add("_TEXT");
+        let class = state.lnames.add("CODE");
+
+        let mut seg = Segment::new(SegName::new(name, class, 0), 0x20, Align::Byte, Combine::Public);
+        seg.base = 0x123;
+        let segidx = state.segments.add(seg);
+        state.segment_order.push(segidx);
+
+        state.symbols.update("_main", Symbol::public(0, segidx, 0, 0x10))?;
+        state.symbols.update("_exit", Symbol::public(0, segidx, 0, 0x04))?;
+        state.symbols.update("ABS", Symbol::public(0, 0, 0, 0x1234))?;
+
+        let sym = symfile(&state, "HELLO")?;
+
+        //
+        // Header: one absolute symbol and one segment, at paragraph 2.
+        //
+        assert_eq!(&sym[6..14], &[1, 0, 27, 0, 1, 0, 2, 0]);
+        assert_eq!(&sym[15..21], b"\x05HELLO");
+        assert_eq!(&sym[21..29], b"\x34\x12\x03ABS\x15\x00");
+
+        //
+        // The segment, which links to itself, with its symbols sorted by value and
+        // relative to the segment's frame.
+        //
+        let seg = &sym[0x20..];
+        assert_eq!(&seg[0..8], &[2, 0, 2, 0, 0x2a, 0, 0x12, 0]);
+        assert_eq!(&seg[20..26], b"\x05_TEXT");
+        assert_eq!(&seg[26..29], &[0x07, 0x00, 5]);
+        assert_eq!(&seg[0x2a..0x2e], &[26, 0, 34, 0]);
+
+        assert_eq!(&sym[sym.len()-4..], &[0, 0, 0, 4]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn packed_segments() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let class = state.lnames.add("CODE");
+
+        for (name, base) in [("_TEXT", 0x100), ("FAR_TEXT", 0x132)] {
+            let mut seg = Segment::new(SegName::new(state.lnames.add(name), class, 0), 0x20, Align::Byte, Combine::Public);
+            seg.base = base;
+            let segidx = state.segments.add(seg);
+            state.segment_order.push(segidx);
+        }
+
+        state.segments[2].pack = 1;
+        state.symbols.update("_far", Symbol::public(0, 2, 0, 0x04))?;
+
+        let sym = symfile(&state, "HELLO")?;
+
+        //
+        // The packed segment is in the frame of the first segment of its pack, as is the
+        // value of its symbol.
+        //
+        let seg = &sym[0x40..];
+        assert_eq!(&seg[20..29], b"\x08FAR_TEXT");
+        assert_eq!(&seg[6..8], &[0x10, 0]);
+        assert_eq!(&seg[29..31], &[0x36, 0]);
+
+        Ok(())
+    }
+}
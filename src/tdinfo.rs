@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+
+use crate::linker_error::LinkerError;
+use crate::linkmap::LinkMap;
+use crate::linkstate::LinkState;
+use crate::object::{BorlandDebug, BorlandLocal, Object};
+
+//
+// Turbo Debugger symbol tables, as appended to the executable by TLINK /v.
+//
+// The table starts with a header signed FB52H, followed by fixed-size records in this
+// order: symbols, modules, source files, line numbers, scopes, segments, correlations,
+// types, members, then a pool of zero-terminated names. Records refer to each other by
+// 1-based index, with 0 meaning none.
+//
+// The symbols are the publics (as globals), then each module's local symbols from its
+// Borland debug comments: its statics outside any function, then those of each scope, so
+// that every scope's symbols are contiguous. Scopes are listed by the code segment they
+// are in, and the types and members each module defines follow one another in the order
+// the module defines them. A module's type indices are renumbered into the table; types
+// it uses but does not define, such as the compiler's predefined types, are left unknown.
+//
+
+const TD_SIGNATURE: u16 = 0x52fb;
+const TD_VERSION: u16 = 0x0400;
+
+/// Symbol classes.
+///
+pub const SYMBOL_STATIC: u8 = 0;
+const SYMBOL_ABSOLUTE: u8 = 1;
+
+/// Module languages.
+///
+const LANGUAGE_UNKNOWN: u8 = 0;
+const LANGUAGE_C: u8 = 1;
+const LANGUAGE_PASCAL: u8 = 2;
+const LANGUAGE_ASSEMBLER: u8 = 4;
+const LANGUAGE_CPP: u8 = 5;
+
+/// Guess a module's language from the extension of its source file.
+///
+fn language(source: &str) -> u8 {
+    let ext = source.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+
+    match ext.as_str() {
+        "c" => LANGUAGE_C,
+        "cpp" | "cxx" => LANGUAGE_CPP,
+        "pas" => LANGUAGE_PASCAL,
+        "asm" => LANGUAGE_ASSEMBLER,
+        _ => LANGUAGE_UNKNOWN,
+    }
+}
+
+/// The pool of names, which records refer to by 1-based index.
+///
+struct Names {
+    pool: Vec<u8>,
+    count: usize,
+}
+
+impl Names {
+    fn add(&mut self, name: &str) -> u16 {
+        self.pool.extend_from_slice(name.as_bytes());
+        self.pool.push(0);
+        self.count += 1;
+        self.count as u16
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Convert a count or index to the 16-bit field the table stores it in.
+///
+fn word(value: usize, what: &str) -> Result<u16, LinkerError> {
+    u16::try_from(value).map_err(|_| LinkerError::new(&format!("too many {} for Turbo Debugger symbol table.", what)))
+}
+
+/// The table index of each type a module defines, numbered from `first`.
+///
+fn number_types(obj: &Object, first: usize) -> Result<HashMap<usize, usize>, LinkerError> {
+    let mut types = HashMap::new();
+
+    for debug in obj.borland_debug.iter() {
+        if let BorlandDebug::Type { typeidx, .. } = debug {
+            if types.insert(*typeidx, first + types.len() + 1).is_some() {
+                return Err(LinkerError::new(&format!("module {}: type index {} is defined twice.", obj.name, typeidx)));
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+/// The table index of one of a module's types, or 0 if the module does not define it.
+///
+fn type_index(types: &HashMap<usize, usize>, typeidx: usize) -> Result<u16, LinkerError> {
+    word(types.get(&typeidx).copied().unwrap_or(0), "types")
+}
+
+/// The frame and offset an offset in one of a module's SEGDEFs runs at, or None if
+/// the SEGDEF was discarded.
+///
+fn segdef_address(state: &LinkState, obj: &Object, segment: usize, offset: u16) -> Option<(usize, usize)> {
+    let segdef = &obj.segdefs[segment];
+
+    if segdef.discarded {
+        return None;
+    }
+
+    let frame = state.segment_address_base(segdef.segidx) >> 4;
+    Some((frame, state.segments[segdef.segidx].base + segdef.base + offset as usize - (frame << 4)))
+}
+
+/// A scope from a module's debug comments, with the symbol records of its locals.
+///
+struct Scope {
+    segment: usize,
+    start: u16,
+    end: u16,
+    parent: Option<usize>,
+    symbols: Vec<u8>,
+    count: usize,
+    first: usize,
+}
+
+/// Build the symbol record of a local, or None if it is a static in a discarded SEGDEF.
+///
+fn local_symbol(state: &LinkState, obj: &Object, types: &HashMap<usize, usize>, names: &mut Names, local: &BorlandLocal) -> Result<Option<Vec<u8>>, LinkerError> {
+    let (frame, offset) = if local.class == SYMBOL_STATIC {
+        match segdef_address(state, obj, local.segment, local.value) {
+            Some(address) => address,
+            None => return Ok(None),
+        }
+    } else {
+        (0, local.value as usize)
+    };
+
+    let mut symbol = Vec::new();
+    push_u16(&mut symbol, names.add(&local.name));
+    push_u16(&mut symbol, type_index(types, local.typeidx)?);
+    push_u16(&mut symbol, offset as u16);
+    push_u16(&mut symbol, frame as u16);
+    symbol.push(local.class);
+
+    Ok(Some(symbol))
+}
+
+/// Gather a module's scopes and the symbol records of its locals: those outside any
+/// scope, then each scope's.
+///
+fn gather_scopes(state: &LinkState, obj: &Object, types: &HashMap<usize, usize>, names: &mut Names) -> Result<(Vec<u8>, usize, Vec<Scope>), LinkerError> {
+    let mut statics = Vec::new();
+    let mut nstatics = 0;
+    let mut scopes: Vec<Scope> = Vec::new();
+    let mut open = Vec::new();
+
+    for debug in obj.borland_debug.iter() {
+        match debug {
+            BorlandDebug::BeginScope { segment, offset } => {
+                open.push(scopes.len());
+                scopes.push(Scope { segment: *segment, start: *offset, end: *offset, parent: open.iter().rev().nth(1).copied(), symbols: Vec::new(), count: 0, first: 0 });
+            },
+            BorlandDebug::Locals(locals) => {
+                for local in locals {
+                    if let Some(symbol) = local_symbol(state, obj, types, names, local)? {
+                        match open.last() {
+                            Some(scope) => {
+                                scopes[*scope].symbols.extend_from_slice(&symbol);
+                                scopes[*scope].count += 1;
+                            },
+                            None => {
+                                statics.extend_from_slice(&symbol);
+                                nstatics += 1;
+                            },
+                        }
+                    }
+                }
+            },
+            BorlandDebug::EndScope { offset } => {
+                let scope = open.pop().ok_or_else(|| LinkerError::new(&format!("module {}: end of scope at {:04X}H which was not begun.", obj.name, offset)))?;
+                scopes[scope].end = *offset;
+            },
+            _ => {},
+        }
+    }
+
+    if !open.is_empty() {
+        return Err(LinkerError::new(&format!("module {}: scope at {:04X}H is not ended.", obj.name, scopes[open[0]].start)));
+    }
+
+    Ok((statics, nstatics, scopes))
+}
+
+/// Build the type and member records for the types a module defines, returning how many
+/// members there are.
+///
+fn module_types(obj: &Object, types_map: &HashMap<usize, usize>, names: &mut Names, types: &mut Vec<u8>, members: &mut Vec<u8>, mut nmembers: usize) -> Result<usize, LinkerError> {
+    let mut current = None;
+
+    for debug in obj.borland_debug.iter() {
+        match debug {
+            BorlandDebug::Type { name, size, id, reference, .. } => {
+                current = Some(types.len());
+                types.push(*id);
+                push_u16(types, if name.is_empty() { 0 } else { names.add(name) });
+                push_u16(types, *size);
+                push_u16(types, type_index(types_map, *reference)?);
+                push_u16(types, 0);
+                push_u16(types, 0);
+            },
+            BorlandDebug::Member { name, typeidx, offset } => {
+                let Some(at) = current else {
+                    return Err(LinkerError::new(&format!("module {}: member {} follows no type.", obj.name, name)));
+                };
+
+                push_u16(members, names.add(name));
+                push_u16(members, type_index(types_map, *typeidx)?);
+                push_u16(members, *offset);
+                nmembers += 1;
+
+                let count = u16::from_le_bytes([types[at+9], types[at+10]]) + 1;
+
+                if count == 1 {
+                    types[at+7..at+9].copy_from_slice(&word(nmembers, "members")?.to_le_bytes());
+                }
+
+                types[at+9..at+11].copy_from_slice(&count.to_le_bytes());
+            },
+            _ => {},
+        }
+    }
+
+    Ok(nmembers)
+}
+
+/// Build the Turbo Debugger symbol table for the linked program. `file_size` is the size of
+/// the executable without the table, which the debugger uses to check the table belongs to
+/// the file.
+///
+pub fn build(state: &LinkState, objects: &[Object], file_size: usize) -> Result<Vec<u8>, LinkerError> {
+    let mut names = Names { pool: Vec::new(), count: 0 };
+
+    let mut symbols = Vec::new();
+    let mut modules = Vec::new();
+    let mut source_files = Vec::new();
+    let mut line_numbers = Vec::new();
+    let mut scopes = Vec::new();
+    let mut segments = Vec::new();
+    let mut correlations = Vec::new();
+    let mut types = Vec::new();
+    let mut members = Vec::new();
+
+    let (mut nsymbols, mut nsource_files, mut nline_numbers, mut nsegments, mut ncorrelations) = (0, 0, 0, 0, 0);
+    let (mut nscopes, mut nmembers) = (0, 0);
+
+    //
+    // Number the types of every module first, so that publics can be given theirs.
+    //
+    let mut type_maps = Vec::new();
+    let mut ntypes = 0;
+
+    for obj in objects.iter() {
+        let map = number_types(obj, ntypes)?;
+        ntypes += map.len();
+        type_maps.push(map);
+    }
+
+    let mut public_types = HashMap::new();
+
+    for (obj, map) in objects.iter().zip(type_maps.iter()) {
+        for debug in obj.borland_debug.iter() {
+            if let BorlandDebug::PublicType { name, typeidx } = debug {
+                public_types.insert(name.as_str(), type_index(map, *typeidx)?);
+            }
+        }
+    }
+
+    //
+    // Publics are the global symbols, in name order.
+    //
+    let map = LinkMap::new(state, objects);
+
+    for public in map.publics.iter() {
+        push_u16(&mut symbols, names.add(&public.name));
+        push_u16(&mut symbols, public_types.get(public.name.as_str()).copied().unwrap_or(0));
+        push_u16(&mut symbols, public.offset as u16);
+        push_u16(&mut symbols, public.frame as u16);
+        symbols.push(if public.absolute { SYMBOL_ABSOLUTE } else { SYMBOL_STATIC });
+        nsymbols += 1;
+    }
+
+    let nglobals = nsymbols;
+
+    for (imod, obj) in objects.iter().enumerate() {
+        let type_map = &type_maps[imod];
+
+        nmembers = module_types(obj, type_map, &mut names, &mut types, &mut members, nmembers)?;
+
+        //
+        // The module's code, in which its scopes are.
+        //
+        let code = (1..=obj.segdefs.len())
+            .filter(|segidx| {
+                let segdef = &obj.segdefs[*segidx];
+                let seg = &state.segments[segdef.segidx];
+                segdef.length != 0 && !segdef.discarded && seg.debug.is_none() && state.lnames.get(seg.name.classidx).ends_with("CODE")
+            })
+            .collect::<Vec<_>>();
+
+        //
+        // The module's local symbols: its statics, then those of each scope in its code.
+        //
+        let (statics, nstatics, mut module_scopes) = gather_scopes(state, obj, type_map, &mut names)?;
+        let first_static = nsymbols + 1;
+
+        symbols.extend_from_slice(&statics);
+        nsymbols += nstatics;
+
+        for scope in module_scopes.iter_mut().filter(|scope| code.contains(&scope.segment)) {
+            scope.first = nsymbols + 1;
+            symbols.extend_from_slice(&scope.symbols);
+            nsymbols += scope.count;
+        }
+
+        //
+        // The module's source files: the THEADR name if any line numbers are attributed
+        // to it, then the files named by comments.
+        //
+        let first_source_file = nsource_files + 1;
+        let mut file_index = vec![0; obj.source_files.len() + 1];
+
+        if obj.linnums.iter().any(|linnums| linnums.source == 0) {
+            push_u16(&mut source_files, names.add(&obj.name));
+            push_u32(&mut source_files, 0);
+            nsource_files += 1;
+            file_index[0] = nsource_files;
+        }
+
+        for (i, file) in obj.source_files.iter().enumerate() {
+            push_u16(&mut source_files, names.add(&file.name));
+            push_u32(&mut source_files, file.timestamp);
+            nsource_files += 1;
+            file_index[i + 1] = nsource_files;
+        }
+
+        //
+        // The scopes in each piece of the module's code. They are numbered before any are
+        // written, as they refer to their parents.
+        //
+
+        let mut scope_index = vec![0; module_scopes.len()];
+
+        for segidx in code.iter() {
+            for (i, scope) in module_scopes.iter().enumerate().filter(|(_, scope)| scope.segment == *segidx) {
+                nscopes += 1;
+                scope_index[i] = nscopes;
+
+                let (frame, offset) = segdef_address(state, obj, scope.segment, scope.start).unwrap();
+                let function = match scope.parent {
+                    Some(_) => 0,
+                    None => map.publics.iter().position(|public| !public.absolute && public.linear == (frame << 4) + offset).map_or(0, |index| index + 1),
+                };
+
+                push_u16(&mut scopes, if scope.count != 0 { word(scope.first, "symbols")? } else { 0 });
+                push_u16(&mut scopes, word(scope.count, "symbols")?);
+                push_u16(&mut scopes, word(scope.parent.map_or(0, |parent| scope_index[parent]), "scopes")?);
+                push_u16(&mut scopes, word(function, "symbols")?);
+                push_u16(&mut scopes, offset as u16);
+                push_u16(&mut scopes, scope.end.wrapping_sub(scope.start));
+            }
+        }
+
+        let first_segment = nsegments + 1;
+
+        for segidx in code {
+            let segdef = &obj.segdefs[segidx];
+            let seg = &state.segments[segdef.segidx];
+
+            let (frame, start) = segdef_address(state, obj, segidx, 0).unwrap();
+            let first_correlation = ncorrelations + 1;
+
+            for linnums in obj.linnums.iter().filter(|linnums| linnums.segidx == segdef.segidx) {
+                let lines = linnums.lines.iter()
+                    .filter(|line| line.offset >= segdef.base && line.offset < segdef.base + segdef.length)
+                    .collect::<Vec<_>>();
+
+                if lines.is_empty() {
+                    continue;
+                }
+
+                push_u16(&mut correlations, word(nsegments + 1, "segments")?);
+                push_u16(&mut correlations, word(file_index[linnums.source], "source files")?);
+                push_u16(&mut correlations, word(nline_numbers + 1, "line numbers")?);
+                push_u16(&mut correlations, word(lines.len(), "line numbers")?);
+                ncorrelations += 1;
+
+                for line in lines {
+                    push_u16(&mut line_numbers, line.line);
+                    push_u16(&mut line_numbers, (seg.base + line.offset - (frame << 4)) as u16);
+                    nline_numbers += 1;
+                }
+            }
+
+            let count = ncorrelations + 1 - first_correlation;
+            let segment_scopes = module_scopes.iter().enumerate()
+                .filter(|(_, scope)| scope.segment == segidx)
+                .map(|(i, _)| scope_index[i])
+                .collect::<Vec<_>>();
+
+            push_u16(&mut segments, word(imod + 1, "modules")?);
+            push_u16(&mut segments, frame as u16);
+            push_u16(&mut segments, start as u16);
+            push_u16(&mut segments, segdef.length as u16);
+            push_u16(&mut segments, word(segment_scopes.first().copied().unwrap_or(0), "scopes")?);
+            push_u16(&mut segments, word(segment_scopes.len(), "scopes")?);
+            push_u16(&mut segments, if count != 0 { word(first_correlation, "correlations")? } else { 0 });
+            push_u16(&mut segments, word(count, "correlations")?);
+            nsegments += 1;
+        }
+
+        let source_count = nsource_files + 1 - first_source_file;
+        let segment_count = nsegments + 1 - first_segment;
+
+        push_u16(&mut modules, names.add(&obj.name));
+        modules.push(language(&obj.name));
+        modules.push(0);
+        push_u16(&mut modules, if nstatics != 0 { word(first_static, "symbols")? } else { 0 });
+        push_u16(&mut modules, word(nstatics, "symbols")?);
+        push_u16(&mut modules, if source_count != 0 { word(first_source_file, "source files")? } else { 0 });
+        push_u16(&mut modules, word(source_count, "source files")?);
+        push_u16(&mut modules, if segment_count != 0 { word(first_segment, "segments")? } else { 0 });
+        push_u16(&mut modules, word(segment_count, "segments")?);
+    }
+
+    let mut td = Vec::new();
+    push_u16(&mut td, TD_SIGNATURE);
+    push_u16(&mut td, TD_VERSION);
+    push_u32(&mut td, names.pool.len() as u32);
+    push_u16(&mut td, word(names.count, "names")?);
+    push_u16(&mut td, word(ntypes, "types")?);
+    push_u16(&mut td, word(nmembers, "members")?);
+    push_u16(&mut td, word(nsymbols, "symbols")?);
+    push_u16(&mut td, word(nglobals, "symbols")?);
+    push_u16(&mut td, word(objects.len(), "modules")?);
+    push_u16(&mut td, word(nsymbols - nglobals, "symbols")?);
+    push_u16(&mut td, word(nscopes, "scopes")?);
+    push_u16(&mut td, word(nline_numbers, "line numbers")?);
+    push_u16(&mut td, word(nsource_files, "source files")?);
+    push_u16(&mut td, word(nsegments, "segments")?);
+    push_u16(&mut td, word(ncorrelations, "correlations")?);
+    push_u32(&mut td, file_size as u32);
+    push_u32(&mut td, 0);                               // basic information
+    td.push(0);                                         // program flags
+    td.push(0);
+    push_u16(&mut td, 0);                               // extension size
+
+    td.extend_from_slice(&symbols);
+    td.extend_from_slice(&modules);
+    td.extend_from_slice(&source_files);
+    td.extend_from_slice(&line_numbers);
+    td.extend_from_slice(&scopes);
+    td.extend_from_slice(&segments);
+    td.extend_from_slice(&correlations);
+    td.extend_from_slice(&types);
+    td.extend_from_slice(&members);
+    td.extend_from_slice(&names.pool);
+
+    Ok(td)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::{LineNumber, LineNumbers};
+    use crate::symbols::Symbol;
+    use crate::segment::{Align, Combine, SegDef, SegName, Segment};
+
+    #[test]
+    fn languages() {
+        assert_eq!(language("hello.c"), LANGUAGE_C);
+        assert_eq!(language("HELLO.ASM"), LANGUAGE_ASSEMBLER);
+        assert_eq!(language("hello"), LANGUAGE_UNKNOWN);
+    }
+
+    #[test]
+    fn empty_program() -> Result<(), LinkerError> {
+        let state = LinkState::new();
+        let td = build(&state, &[], 0x220)?;
+
+        assert_eq!(td.len(), 44);
+        assert_eq!(&td[0..2], &[0xfb, 0x52]);
+        assert_eq!(&td[32..36], &[0x20, 0x02, 0x00, 0x00]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn packed_code() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let class = state.lnames.add("CODE");
+
+        for (name, base, length) in [("_TEXT", 0x100, 0x32), ("FAR_TEXT", 0x132, 0x10)] {
+            let mut seg = Segment::new(SegName::new(state.lnames.add(name), class, 0), length, Align::Byte, Combine::Public);
+            seg.base = base;
+            state.segments.add(seg);
+        }
+
+        state.segments[2].pack = 1;
+
+        let mut obj = Object::new();
+        obj.name = "far.c".to_owned();
+        obj.segdefs.add(SegDef::new(2, 0x10, 0x28, Align::Byte, Combine::Public));
+        obj.linnums.push(LineNumbers { segidx: 2, source: 0, group: 0, lines: vec![LineNumber { line: 5, offset: 4 }] });
+
+        let td = build(&state, &[obj], 0x200)?;
+
+        //
+        // The code and its line are in the frame of the pack, as the program runs them.
+        //
+        assert_eq!(&td[66..70], &[5, 0, 0x36, 0]);
+        assert_eq!(&td[70..76], &[1, 0, 0x10, 0, 0x32, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn scopes_locals_and_types() -> Result<(), LinkerError> {
+        let mut state = LinkState::new();
+        let class = state.lnames.add("CODE");
+        let mut seg = Segment::new(SegName::new(state.lnames.add("_TEXT"), class, 0), 0x40, Align::Byte, Combine::Public);
+        seg.base = 0x100;
+        state.segments.add(seg);
+        state.symbols.update("_main", Symbol::public(0, 1, 0, 0x20))?;
+
+        let local = |name: &str, typeidx, class, segment, value| BorlandLocal { name: name.to_owned(), typeidx, class, segment, value };
+
+        //
+        // struct node { int next; }, and _main, a function of type 2, with a local in a
+        // nested block. Type 5 is not defined by the module.
+        //
+        let mut obj = Object::new();
+        obj.name = "main.c".to_owned();
+        let mut segdef = SegDef::new(1, 0x20, 0x28, Align::Byte, Combine::Public);
+        segdef.base = 0x10;
+        obj.segdefs.add(segdef);
+        obj.borland_debug = vec![
+            BorlandDebug::PublicType { name: "_main".to_owned(), typeidx: 2 },
+            BorlandDebug::Type { typeidx: 1, name: "node".to_owned(), size: 2, id: 0x1e, reference: 0 },
+            BorlandDebug::Member { name: "next".to_owned(), typeidx: 2, offset: 0 },
+            BorlandDebug::Type { typeidx: 2, name: String::new(), size: 0, id: 0x20, reference: 1 },
+            BorlandDebug::Locals(vec![local("count", 0, SYMBOL_STATIC, 1, 4)]),
+            BorlandDebug::BeginScope { segment: 1, offset: 0x10 },
+            BorlandDebug::Locals(vec![local("i", 1, 2, 0, 0xfffe)]),
+            BorlandDebug::BeginScope { segment: 1, offset: 0x14 },
+            BorlandDebug::Locals(vec![local("j", 5, 2, 0, 0xfffc)]),
+            BorlandDebug::EndScope { offset: 0x18 },
+            BorlandDebug::EndScope { offset: 0x20 },
+        ];
+
+        let td = build(&state, &[obj], 0x200)?;
+
+        //
+        // Two types, one member, four symbols of which one is global, one module, three
+        // locals, two scopes and a segment.
+        //
+        assert_eq!(&td[10..32], &[2, 0, 1, 0, 4, 0, 1, 0, 1, 0, 3, 0, 2, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+
+        //
+        // _main, with its type, then the static, and the locals of each scope.
+        //
+        assert_eq!(&td[44..53], &[1, 0, 2, 0, 0x20, 0, 0x10, 0, SYMBOL_STATIC]);
+        assert_eq!(&td[53..62], &[4, 0, 0, 0, 0x14, 0, 0x10, 0, SYMBOL_STATIC]);
+        assert_eq!(&td[62..71], &[5, 0, 1, 0, 0xfe, 0xff, 0, 0, 2]);
+        assert_eq!(&td[71..80], &[6, 0, 0, 0, 0xfc, 0xff, 0, 0, 2]);
+
+        //
+        // The module's statics, then the scopes: _main's, and the block within it.
+        //
+        assert_eq!(&td[84..88], &[2, 0, 1, 0]);
+        assert_eq!(&td[96..108], &[3, 0, 1, 0, 0, 0, 1, 0, 0x20, 0, 0x10, 0]);
+        assert_eq!(&td[108..120], &[4, 0, 1, 0, 1, 0, 0, 0, 0x24, 0, 0x04, 0]);
+        assert_eq!(&td[120..136], &[1, 0, 0x10, 0, 0x10, 0, 0x20, 0, 1, 0, 2, 0, 0, 0, 0, 0]);
+
+        //
+        // The structure and its member, and the function type referring to it.
+        //
+        assert_eq!(&td[136..147], &[0x1e, 2, 0, 2, 0, 0, 0, 1, 0, 1, 0]);
+        assert_eq!(&td[147..158], &[0x20, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(&td[158..164], &[3, 0, 2, 0, 0, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced_scopes() {
+        let state = LinkState::new();
+        let mut obj = Object::new();
+        obj.name = "bad.c".to_owned();
+        obj.borland_debug = vec![BorlandDebug::EndScope { offset: 0x10 }];
+
+        assert!(build(&state, &[obj], 0x200)
+            .is_err_and(|err| err.to_string() == "module bad.c: end of scope at 0010H which was not begun."));
+    }
+}